@@ -0,0 +1,62 @@
+-- Optional second level OCR cache (enabled with OCR_CACHE_SQL=true, see src/ocr/cache.rs)
+
+IF OBJECT_ID('dbo.OcrResultCache', 'U') IS NULL
+BEGIN
+    CREATE TABLE dbo.OcrResultCache (
+        CacheKey    CHAR(64)      NOT NULL PRIMARY KEY,
+        Provider    VARCHAR(50)   NOT NULL,
+        ResultJson  NVARCHAR(MAX) NOT NULL,
+        CreatedAt   DATETIME2(3)  NOT NULL CONSTRAINT DF_OcrResultCache_CreatedAt DEFAULT SYSUTCDATETIME(),
+        LastHitAt   DATETIME2(3)  NULL
+    );
+END
+GO
+
+CREATE OR ALTER PROCEDURE dbo.usp_Get_Ocr_Cache
+    @cacheKey   CHAR(64),
+    @retStatus  BIT OUTPUT,
+    @retMessage VARCHAR(1000) OUTPUT
+AS
+BEGIN
+    SET NOCOUNT ON;
+    BEGIN TRY
+        UPDATE dbo.OcrResultCache SET LastHitAt = SYSUTCDATETIME() WHERE CacheKey = @cacheKey;
+
+        SELECT ResultJson AS resultJson FROM dbo.OcrResultCache WHERE CacheKey = @cacheKey;
+        SET @retStatus = 1;
+        SET @retMessage = 'OCR cache lookup done';
+    END TRY
+    BEGIN CATCH
+        SET @retStatus = 0;
+        SET @retMessage = ERROR_MESSAGE();
+    END CATCH
+END
+GO
+
+CREATE OR ALTER PROCEDURE dbo.usp_Upsert_Ocr_Cache
+    @cacheKey   CHAR(64),
+    @provider   VARCHAR(50),
+    @resultJson NVARCHAR(MAX),
+    @retStatus  BIT OUTPUT,
+    @retMessage VARCHAR(1000) OUTPUT
+AS
+BEGIN
+    SET NOCOUNT ON;
+    BEGIN TRY
+        MERGE dbo.OcrResultCache AS target
+        USING (SELECT @cacheKey AS CacheKey) AS source
+        ON target.CacheKey = source.CacheKey
+        WHEN MATCHED THEN
+            UPDATE SET Provider = @provider, ResultJson = @resultJson, CreatedAt = SYSUTCDATETIME()
+        WHEN NOT MATCHED THEN
+            INSERT (CacheKey, Provider, ResultJson) VALUES (@cacheKey, @provider, @resultJson);
+
+        SET @retStatus = 1;
+        SET @retMessage = 'OCR cache saved';
+    END TRY
+    BEGIN CATCH
+        SET @retStatus = 0;
+        SET @retMessage = ERROR_MESSAGE();
+    END CATCH
+END
+GO
@@ -0,0 +1,152 @@
+-- OCR result history (see src/ocr/history.rs)
+
+IF OBJECT_ID('dbo.OcrResult', 'U') IS NULL
+BEGIN
+    CREATE TABLE dbo.OcrResult (
+        OcrResultId   BIGINT IDENTITY(1,1) NOT NULL PRIMARY KEY,
+        InputHash     CHAR(64)       NOT NULL,
+        Provider      VARCHAR(50)    NOT NULL,
+        Model         VARCHAR(200)   NULL,
+        Prompt        NVARCHAR(MAX)  NULL,
+        RawResponse   NVARCHAR(MAX)  NULL,
+        ParsedJson    NVARCHAR(MAX)  NULL,
+        IsSuccess     BIT            NOT NULL,
+        ErrorMessage  NVARCHAR(4000) NULL,
+        LatencyMs     INT            NOT NULL,
+        AtmId         BIGINT         NULL,
+        Caller        NVARCHAR(200)  NULL,
+        CreatedAt     DATETIME2(3)   NOT NULL CONSTRAINT DF_OcrResult_CreatedAt DEFAULT SYSUTCDATETIME()
+    );
+
+    CREATE INDEX IX_OcrResult_AtmId_CreatedAt ON dbo.OcrResult (AtmId, CreatedAt);
+    CREATE INDEX IX_OcrResult_Provider_CreatedAt ON dbo.OcrResult (Provider, CreatedAt);
+    CREATE INDEX IX_OcrResult_InputHash ON dbo.OcrResult (InputHash);
+END
+GO
+
+-- Counter type, prompt template version and schema check (src/ocr/prompts.rs)
+IF COL_LENGTH('dbo.OcrResult', 'PromptVersion') IS NULL
+BEGIN
+    ALTER TABLE dbo.OcrResult ADD
+        CounterName   VARCHAR(100)   NULL,
+        PromptName    VARCHAR(100)   NULL,
+        PromptVersion INT            NULL,
+        IsValid       BIT            NULL;
+END
+GO
+
+IF NOT EXISTS (SELECT 1 FROM sys.indexes WHERE name = 'IX_OcrResult_Prompt_CreatedAt' AND object_id = OBJECT_ID('dbo.OcrResult'))
+    CREATE INDEX IX_OcrResult_Prompt_CreatedAt ON dbo.OcrResult (PromptName, PromptVersion, CreatedAt) INCLUDE (CounterName, IsSuccess, IsValid, LatencyMs);
+GO
+
+CREATE OR ALTER PROCEDURE dbo.usp_Insert_Ocr_Result
+    @inputHash    CHAR(64),
+    @provider     VARCHAR(50),
+    @model        VARCHAR(200),
+    @prompt       NVARCHAR(MAX),
+    @rawResponse  NVARCHAR(MAX),
+    @parsedJson   NVARCHAR(MAX),
+    @isSuccess    BIT,
+    @errorMessage NVARCHAR(4000),
+    @latencyMs    INT,
+    @atmId        BIGINT,
+    @caller       NVARCHAR(200),
+    @counterName  VARCHAR(100),
+    @promptName   VARCHAR(100),
+    @promptVersion INT,
+    @isValid      BIT,
+    @retStatus    BIT OUTPUT,
+    @retMessage   VARCHAR(1000) OUTPUT
+AS
+BEGIN
+    SET NOCOUNT ON;
+    BEGIN TRY
+        INSERT INTO dbo.OcrResult (InputHash, Provider, Model, Prompt, RawResponse, ParsedJson, IsSuccess, ErrorMessage, LatencyMs, AtmId, Caller,
+                                   CounterName, PromptName, PromptVersion, IsValid)
+        VALUES (@inputHash, @provider, @model, @prompt, @rawResponse, @parsedJson, @isSuccess, @errorMessage, @latencyMs, @atmId, @caller,
+                @counterName, @promptName, @promptVersion, @isValid);
+
+        SELECT CAST(SCOPE_IDENTITY() AS BIGINT) AS ocrResultId;
+        SET @retStatus = 1;
+        SET @retMessage = 'OCR result saved';
+    END TRY
+    BEGIN CATCH
+        SET @retStatus = 0;
+        SET @retMessage = ERROR_MESSAGE();
+    END CATCH
+END
+GO
+
+-- All filters are optional; @dateTo is exclusive
+CREATE OR ALTER PROCEDURE dbo.usp_Get_Ocr_Results
+    @atmId      BIGINT,
+    @dateFrom   DATETIME2(3),
+    @dateTo     DATETIME2(3),
+    @provider   VARCHAR(50),
+    @pageNumber INT,
+    @pageSize   INT,
+    @retStatus  BIT OUTPUT,
+    @retMessage VARCHAR(1000) OUTPUT
+AS
+BEGIN
+    SET NOCOUNT ON;
+    BEGIN TRY
+        SELECT OcrResultId AS ocrResultId, InputHash AS inputHash, Provider AS provider, Model AS model,
+               Prompt AS prompt, RawResponse AS rawResponse, ParsedJson AS parsedJson, IsSuccess AS isSuccess,
+               ErrorMessage AS errorMessage, LatencyMs AS latencyMs, AtmId AS atmId, Caller AS caller,
+               CounterName AS counterName, PromptName AS promptName, PromptVersion AS promptVersion, IsValid AS isValid, CreatedAt AS createdAt
+        FROM dbo.OcrResult
+        WHERE (@atmId IS NULL OR AtmId = @atmId)
+          AND (@dateFrom IS NULL OR CreatedAt >= @dateFrom)
+          AND (@dateTo IS NULL OR CreatedAt < @dateTo)
+          AND (@provider IS NULL OR Provider = @provider)
+        ORDER BY CreatedAt DESC
+        OFFSET (@pageNumber - 1) * @pageSize ROWS FETCH NEXT @pageSize ROWS ONLY;
+
+        SET @retStatus = 1;
+        SET @retMessage = 'OCR results fetched';
+    END TRY
+    BEGIN CATCH
+        SET @retStatus = 0;
+        SET @retMessage = ERROR_MESSAGE();
+    END CATCH
+END
+GO
+
+-- One row per counter type / prompt version / provider / model; @dateTo is exclusive.
+-- validRate only counts results that had a schema to check against.
+CREATE OR ALTER PROCEDURE dbo.usp_Get_Ocr_Prompt_Stats
+    @dateFrom    DATETIME2(3),
+    @dateTo      DATETIME2(3),
+    @counterName VARCHAR(100),
+    @retStatus   BIT OUTPUT,
+    @retMessage  VARCHAR(1000) OUTPUT
+AS
+BEGIN
+    SET NOCOUNT ON;
+    BEGIN TRY
+        SELECT CounterName AS counterName, PromptName AS promptName, PromptVersion AS promptVersion,
+               Provider AS provider, Model AS model,
+               COUNT(*) AS total,
+               SUM(CASE WHEN IsSuccess = 1 THEN 1 ELSE 0 END) AS succeeded,
+               SUM(CASE WHEN IsValid = 1 THEN 1 ELSE 0 END) AS valid,
+               CAST(AVG(CASE WHEN IsSuccess = 1 THEN 1.0 ELSE 0.0 END) AS FLOAT) AS successRate,
+               CAST(AVG(CASE IsValid WHEN 1 THEN 1.0 WHEN 0 THEN 0.0 END) AS FLOAT) AS validRate,
+               AVG(LatencyMs) AS avgLatencyMs
+        FROM dbo.OcrResult
+        WHERE PromptName IS NOT NULL
+          AND (@dateFrom IS NULL OR CreatedAt >= @dateFrom)
+          AND (@dateTo IS NULL OR CreatedAt < @dateTo)
+          AND (@counterName IS NULL OR CounterName = @counterName)
+        GROUP BY CounterName, PromptName, PromptVersion, Provider, Model
+        ORDER BY CounterName, PromptName, PromptVersion DESC, Provider, Model;
+
+        SET @retStatus = 1;
+        SET @retMessage = 'Prompt statistics fetched';
+    END TRY
+    BEGIN CATCH
+        SET @retStatus = 0;
+        SET @retMessage = ERROR_MESSAGE();
+    END CATCH
+END
+GO
@@ -0,0 +1,61 @@
+use axum::{Json, extract::State, http::StatusCode, response::{IntoResponse, Response}, routing::{MethodRouter, any}};
+use serde::Serialize;
+
+use crate::{constant::ApiResponse, error::AppError, state::AppState};
+
+// An optional part of the service and the configuration it needs
+#[derive(Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Feature {
+    pub name: &'static str,
+    pub enabled: bool,
+    // Settings that still have to be provided to enable it
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub missing: Vec<&'static str>,
+    // Routes that answer 503 while the feature is disabled
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub routes: Vec<&'static str>,
+}
+
+impl Feature {
+    // Enabled when every setting is present; `settings` pairs each config key with whether it is set
+    pub fn new(name: &'static str, settings: &[(&'static str, bool)], routes: &[&'static str]) -> Self {
+        let missing: Vec<&'static str> = settings.iter()
+            .filter(|(_, set)| !set)
+            .map(|(key, _)| *key)
+            .collect();
+
+        if !missing.is_empty() {
+            tracing::warn!("{} disabled, missing {}", name, missing.join(", "));
+        }
+        Self { name, enabled: missing.is_empty(), missing, routes: routes.to_vec() }
+    }
+
+    // The real handler when enabled, otherwise one that explains what is missing
+    pub fn route(&self, handler: MethodRouter<AppState>) -> MethodRouter<AppState> {
+        if self.enabled {
+            return handler;
+        }
+        let feature = self.clone();
+        any(move || async move { feature.unavailable() })
+    }
+
+    pub fn unavailable(&self) -> AppError {
+        AppError::ProviderUnavailable(format!("{} is not configured on this server, missing {}", self.name, self.missing.join(", ")))
+    }
+}
+
+// What this instance can do, decided once at startup from the configuration
+#[derive(Serialize, Clone, Debug)]
+pub struct Capabilities {
+    pub providers: Vec<Feature>,
+    pub database: Feature,
+    pub webhooks: Feature,
+    pub video: Feature,
+    pub admin: Feature,
+}
+
+pub async fn get_capabilities(State(state): State<AppState>) -> Response {
+    let capabilities = state.capabilities.as_ref().clone();
+    (StatusCode::OK, Json(ApiResponse::success(capabilities, "Capabilities"))).into_response()
+}
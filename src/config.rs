@@ -0,0 +1,646 @@
+use std::{collections::BTreeMap, ffi::OsString, fmt, fs, path::PathBuf};
+use axum::{Json, extract::State, http::StatusCode, response::{IntoResponse, Response}};
+use clap::{CommandFactory, FromArgMatches, Parser};
+use serde::{Deserialize, Serialize};
+use tiberius::Config;
+
+use crate::{constant::ApiResponse, ocr::{counters::{CounterRegistry, CounterType}, ollama_options::GenerationOptions, ollama_pool::BalanceStrategy, prompts::{PromptRegistry, PromptTemplate}}, state::AppState};
+
+// Read from the working directory when no --config is given; it may be absent
+pub const DEFAULT_CONFIG_FILE: &str = "config.toml";
+const MASK: &str = "********";
+// Shortest admin.token accepted at startup
+const MIN_ADMIN_TOKEN_LEN: usize = 16;
+
+// Settings are layered: defaults < TOML file < environment < command line.
+// Every flag below can also be set through the environment variable shown in --help.
+#[derive(Parser, Debug, Default)]
+#[command(version, about = "OCR service")]
+pub struct Cli {
+    #[arg(long, env = "OCR_CONFIG")]
+    pub config: Option<PathBuf>,
+    #[arg(long, env = "OCR_HOST")]
+    pub host: Option<String>,
+    #[arg(long, env = "OCR_PORT")]
+    pub port: Option<u16>,
+    #[arg(long, env = "OCR_ADMIN_TOKEN", hide_env_values = true)]
+    pub admin_token: Option<String>,
+
+    #[arg(long, env = "ATM_SYNC_DATABASE_CONNECTION_STRING", hide_env_values = true)]
+    pub database_connection_string: Option<String>,
+    #[arg(long, env = "OCR_DB_POOL_SIZE")]
+    pub database_pool_size: Option<u32>,
+    #[arg(long, env = "OCR_CACHE_SQL")]
+    pub cache_sql: Option<bool>,
+
+    #[arg(long, env = "OCR_PROVIDER_TIMEOUT_SECS")]
+    pub provider_timeout_secs: Option<u64>,
+    #[arg(long, env = "OCR_JOB_WORKERS")]
+    pub job_workers: Option<usize>,
+    #[arg(long, env = "OCR_JOB_QUEUE_CAPACITY")]
+    pub job_queue_capacity: Option<usize>,
+    #[arg(long, env = "OCR_CACHE_CAPACITY")]
+    pub cache_capacity: Option<usize>,
+
+    // Comma separated list of instances
+    #[arg(long, env = "OLLAMA_URL", value_delimiter = ',')]
+    pub ollama_url: Option<Vec<String>>,
+    #[arg(long, env = "OLLAMA_STRATEGY", value_enum)]
+    pub ollama_strategy: Option<BalanceStrategy>,
+    #[arg(long, env = "OLLAMA_MAX_CONCURRENCY")]
+    pub ollama_max_concurrency: Option<usize>,
+
+    #[arg(long, env = "VISION_ENDPOINT")]
+    pub azure_read_endpoint: Option<String>,
+    #[arg(long, env = "VISION_KEY", hide_env_values = true)]
+    pub azure_read_key: Option<String>,
+    #[arg(long, env = "AZURE_DOCUMENT_INTELLIGENCE_ENDPOINT")]
+    pub azure_document_endpoint: Option<String>,
+    #[arg(long, env = "AZURE_DOCUMENT_INTELLIGENCE_KEY", hide_env_values = true)]
+    pub azure_document_key: Option<String>,
+
+    #[arg(long, env = "TENANT_ID")]
+    pub copilot_tenant_id: Option<String>,
+    #[arg(long, env = "CLIENT_ID")]
+    pub copilot_client_id: Option<String>,
+    #[arg(long, env = "CLIENT_SECRET", hide_env_values = true)]
+    pub copilot_client_secret: Option<String>,
+    #[arg(long, env = "GRAPH_AUTHORITY_URL")]
+    pub copilot_authority_url: Option<String>,
+
+    #[arg(long, env = "WEBHOOK_SECRET", hide_env_values = true)]
+    pub webhook_secret: Option<String>,
+
+    #[arg(long, env = "OCR_CHUNK_DIR")]
+    pub chunk_dir: Option<String>,
+    #[arg(long, env = "OCR_OUTPUT_DIR")]
+    pub output_dir: Option<String>,
+}
+
+impl Cli {
+    // Parses `args` with the variables looked up through `env` instead of clap reading the process
+    // environment itself, so tests can layer settings without touching it
+    pub fn parse_with_env<I, T>(args: I, env: impl Fn(&str) -> Option<OsString>) -> Result<Self, clap::Error>
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<OsString>,
+    {
+        let mut args: Vec<OsString> = args.into_iter().map(Into::into).collect();
+        let mut variables = Vec::new();
+        let command = Self::command().mut_args(|arg| {
+            let Some(name) = arg.get_env().and_then(|n| n.to_str()).map(str::to_string) else { return arg };
+            let help = format!("[env: {}]", name);
+            variables.push((arg.get_id().clone(), arg.get_long().unwrap_or_default().to_string(), name));
+            arg.env(None::<&str>).help(help)
+        });
+
+        // 1. Command line
+        let matches = command.clone().try_get_matches_from(&args)?;
+
+        // 2. A variable only fills a flag the command line left out
+        for (id, long, name) in variables {
+            if !matches.contains_id(id.as_str()) && let Some(value) = env(&name) {
+                let mut flag = OsString::from(format!("--{}=", long));
+                flag.push(value);
+                args.push(flag);
+            }
+        }
+        Self::from_arg_matches(&command.try_get_matches_from(args)?)
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct AppConfig {
+    pub server: ServerConfig,
+    pub admin: AdminConfig,
+    pub database: DatabaseConfig,
+    pub ocr: OcrConfig,
+    pub ollama: OllamaConfig,
+    pub azure_read: AzureServiceConfig,
+    pub azure_document: AzureServiceConfig,
+    pub copilot: CopilotConfig,
+    pub webhooks: WebhookConfig,
+    pub video: VideoConfig,
+    // Extra document types, or replacements for built-in ones, keyed by `?counter_name=`
+    pub counters: BTreeMap<String, CounterType>,
+    // Extra prompt template versions, or replacements for built-in ones
+    pub prompts: Vec<PromptTemplate>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub host: String,
+    pub port: u16,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self { host: "0.0.0.0".to_string(), port: 8791 }
+    }
+}
+
+// /admin/* answers only requests carrying `Authorization: Bearer <token>`; without a token it is disabled
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct AdminConfig {
+    pub token: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct DatabaseConfig {
+    // ADO connection string; without it history, /ocr/results and /ocr/test are disabled
+    pub connection_string: Option<String>,
+    pub pool_size: u32,
+    // Opt-in second level cache table shared between instances
+    pub cache_sql: bool,
+}
+
+impl Default for DatabaseConfig {
+    fn default() -> Self {
+        Self { connection_string: None, pool_size: 20, cache_sql: false }
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct OcrConfig {
+    pub provider_timeout_secs: u64,
+    pub job_workers: usize,
+    pub job_queue_capacity: usize,
+    pub cache_capacity: usize,
+}
+
+impl Default for OcrConfig {
+    fn default() -> Self {
+        Self { provider_timeout_secs: 120, job_workers: 4, job_queue_capacity: 100, cache_capacity: 500 }
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct OllamaConfig {
+    pub instances: Vec<OllamaInstanceConfig>,
+    pub strategy: BalanceStrategy,
+    // Concurrent generations per instance, unless the instance sets its own
+    pub max_concurrency: usize,
+    pub health_check_interval_secs: u64,
+    // How long a request waits for a free slot when every instance is at its limit
+    pub queue_timeout_secs: u64,
+    // Generation options for every model, then per model name (`deepseek-ocr` or `deepseek-ocr:3b`)
+    pub defaults: GenerationOptions,
+    pub models: BTreeMap<String, GenerationOptions>,
+}
+
+impl Default for OllamaConfig {
+    fn default() -> Self {
+        Self {
+            instances: vec![OllamaInstanceConfig { url: "http://127.0.0.1:11434".to_string(), max_concurrency: None }],
+            strategy: BalanceStrategy::default(),
+            max_concurrency: 2,
+            health_check_interval_secs: 30,
+            queue_timeout_secs: 60,
+            defaults: GenerationOptions::default(),
+            models: BTreeMap::new(),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct OllamaInstanceConfig {
+    pub url: String,
+    #[serde(default)]
+    pub max_concurrency: Option<usize>,
+}
+
+// Azure AI Vision (Read) and Document Intelligence share the same shape
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct AzureServiceConfig {
+    pub endpoint: Option<String>,
+    pub key: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct CopilotConfig {
+    pub tenant_id: Option<String>,
+    pub client_id: Option<String>,
+    pub client_secret: Option<String>,
+    // Identity platform base URL, pointed at a mock in tests
+    pub authority_url: String,
+}
+
+impl Default for CopilotConfig {
+    fn default() -> Self {
+        Self {
+            tenant_id: None,
+            client_id: None,
+            client_secret: None,
+            authority_url: crate::ocr::graph_token::DEFAULT_AUTHORITY.to_string(),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct WebhookConfig {
+    // Callbacks are only accepted when a signing secret is configured
+    pub secret: Option<String>,
+    // Hosts callbacks may reach; empty allows any public host but no internal address
+    pub allowed_hosts: Vec<String>,
+}
+
+// Where /ocr/test finds uploaded video chunks and writes the merged file
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct VideoConfig {
+    pub chunk_dir: Option<String>,
+    pub output_dir: Option<String>,
+}
+
+// Every problem found at startup, reported together
+#[derive(Debug)]
+pub struct ConfigError(pub Vec<String>);
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Invalid configuration:")?;
+        for problem in &self.0 {
+            writeln!(f, "  - {}", problem)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl AppConfig {
+    pub fn load(cli: Cli) -> Result<Self, ConfigError> {
+        // 1. File (an explicit --config must exist, the default one is optional)
+        let path = cli.config.clone().unwrap_or_else(|| PathBuf::from(DEFAULT_CONFIG_FILE));
+        let mut config = match fs::read_to_string(&path) {
+            Ok(text) => Self::from_toml(&text).map_err(|e| ConfigError(vec![format!("{}: {}", path.display(), e)]))?,
+            Err(_) if cli.config.is_none() => Self::default(),
+            Err(e) => return Err(ConfigError(vec![format!("{}: {}", path.display(), e)])),
+        };
+
+        // 2. Environment and command line, already merged by Cli::parse_with_env
+        config.apply(cli);
+        config.normalize();
+
+        // 3. Validation
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn from_toml(text: &str) -> Result<Self, toml::de::Error> {
+        toml::from_str(text)
+    }
+
+    fn apply(&mut self, cli: Cli) {
+        fn set<T>(target: &mut T, value: Option<T>) {
+            if let Some(value) = value {
+                *target = value;
+            }
+        }
+        fn set_opt<T>(target: &mut Option<T>, value: Option<T>) {
+            if value.is_some() {
+                *target = value;
+            }
+        }
+
+        set(&mut self.server.host, cli.host);
+        set(&mut self.server.port, cli.port);
+        set_opt(&mut self.admin.token, cli.admin_token);
+        set_opt(&mut self.database.connection_string, cli.database_connection_string);
+        set(&mut self.database.pool_size, cli.database_pool_size);
+        set(&mut self.database.cache_sql, cli.cache_sql);
+        set(&mut self.ocr.provider_timeout_secs, cli.provider_timeout_secs);
+        set(&mut self.ocr.job_workers, cli.job_workers);
+        set(&mut self.ocr.job_queue_capacity, cli.job_queue_capacity);
+        set(&mut self.ocr.cache_capacity, cli.cache_capacity);
+        if let Some(urls) = cli.ollama_url {
+            self.ollama.instances = urls.into_iter()
+                .map(|url| OllamaInstanceConfig { url: url.trim().to_string(), max_concurrency: None })
+                .collect();
+        }
+        set(&mut self.ollama.strategy, cli.ollama_strategy);
+        set(&mut self.ollama.max_concurrency, cli.ollama_max_concurrency);
+        set_opt(&mut self.azure_read.endpoint, cli.azure_read_endpoint);
+        set_opt(&mut self.azure_read.key, cli.azure_read_key);
+        set_opt(&mut self.azure_document.endpoint, cli.azure_document_endpoint);
+        set_opt(&mut self.azure_document.key, cli.azure_document_key);
+        set_opt(&mut self.copilot.tenant_id, cli.copilot_tenant_id);
+        set_opt(&mut self.copilot.client_id, cli.copilot_client_id);
+        set_opt(&mut self.copilot.client_secret, cli.copilot_client_secret);
+        set(&mut self.copilot.authority_url, cli.copilot_authority_url);
+        set_opt(&mut self.webhooks.secret, cli.webhook_secret);
+        set_opt(&mut self.video.chunk_dir, cli.chunk_dir);
+        set_opt(&mut self.video.output_dir, cli.output_dir);
+    }
+
+    // Blank optional settings (`key = ""` in the file) count as unset
+    fn normalize(&mut self) {
+        for value in [
+            &mut self.admin.token,
+            &mut self.database.connection_string,
+            &mut self.azure_read.endpoint,
+            &mut self.azure_read.key,
+            &mut self.azure_document.endpoint,
+            &mut self.azure_document.key,
+            &mut self.copilot.tenant_id,
+            &mut self.copilot.client_id,
+            &mut self.copilot.client_secret,
+            &mut self.webhooks.secret,
+            &mut self.video.chunk_dir,
+            &mut self.video.output_dir,
+        ] {
+            if value.as_deref().is_some_and(|v| v.trim().is_empty()) {
+                *value = None;
+            }
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let mut problems = Vec::new();
+
+        if self.server.port == 0 {
+            problems.push("server.port must be between 1 and 65535".to_string());
+        }
+        if self.admin.token.as_ref().is_some_and(|t| t.trim().len() < MIN_ADMIN_TOKEN_LEN) {
+            problems.push(format!("admin.token must be at least {} characters", MIN_ADMIN_TOKEN_LEN));
+        }
+        if !(1..=1000).contains(&self.database.pool_size) {
+            problems.push(format!("database.pool_size must be between 1 and 1000, got {}", self.database.pool_size));
+        }
+        match &self.database.connection_string {
+            Some(conn_str) => if let Err(e) = Config::from_ado_string(conn_str) {
+                problems.push(format!("database.connection_string is not a valid ADO connection string: {}", e));
+            },
+            None if self.database.cache_sql => problems.push("database.cache_sql requires database.connection_string".to_string()),
+            None => {}
+        }
+
+        for (name, value) in [
+            ("ocr.provider_timeout_secs", self.ocr.provider_timeout_secs as usize),
+            ("ocr.job_workers", self.ocr.job_workers),
+            ("ocr.job_queue_capacity", self.ocr.job_queue_capacity),
+            ("ocr.cache_capacity", self.ocr.cache_capacity),
+            ("ollama.max_concurrency", self.ollama.max_concurrency),
+            ("ollama.health_check_interval_secs", self.ollama.health_check_interval_secs as usize),
+            ("ollama.queue_timeout_secs", self.ollama.queue_timeout_secs as usize),
+        ] {
+            if value == 0 {
+                problems.push(format!("{} must be at least 1", name));
+            }
+        }
+
+        if self.ollama.instances.is_empty() {
+            problems.push("ollama.instances must list at least one instance".to_string());
+        }
+        for (i, instance) in self.ollama.instances.iter().enumerate() {
+            if !instance.url.starts_with("http://") && !instance.url.starts_with("https://") {
+                problems.push(format!("ollama.instances[{}].url must be an http(s) URL, got '{}'", i, instance.url));
+            }
+            if instance.max_concurrency == Some(0) {
+                problems.push(format!("ollama.instances[{}].max_concurrency must be at least 1", i));
+            }
+        }
+
+        problems.extend(self.ollama.defaults.problems().into_iter().map(|p| format!("ollama.defaults.{}", p)));
+        for (model, options) in &self.ollama.models {
+            problems.extend(options.problems().into_iter().map(|p| format!("ollama.models.\"{}\".{}", model, p)));
+        }
+
+        for (name, url) in [
+            ("azure_read.endpoint", self.azure_read.endpoint.as_ref()),
+            ("azure_document.endpoint", self.azure_document.endpoint.as_ref()),
+            ("copilot.authority_url", Some(&self.copilot.authority_url)),
+        ] {
+            let Some(url) = url else { continue };
+            match reqwest::Url::parse(url) {
+                Ok(parsed) if matches!(parsed.scheme(), "http" | "https") => {}
+                _ => problems.push(format!("{} must be an http(s) URL, got '{}'", name, url)),
+            }
+        }
+
+        if let Err(counter_problems) = CounterRegistry::new(&self.counters) {
+            problems.extend(counter_problems);
+        }
+        if let Err(prompt_problems) = PromptRegistry::new(&self.prompts) {
+            problems.extend(prompt_problems);
+        }
+
+        // Half-configured services are almost always a typo, refuse them instead of silently disabling
+        let groups: [(&str, &[(&str, bool)]); 4] = [
+            ("azure_read", &[("endpoint", self.azure_read.endpoint.is_some()), ("key", self.azure_read.key.is_some())]),
+            ("azure_document", &[("endpoint", self.azure_document.endpoint.is_some()), ("key", self.azure_document.key.is_some())]),
+            ("copilot", &[
+                ("tenant_id", self.copilot.tenant_id.is_some()),
+                ("client_id", self.copilot.client_id.is_some()),
+                ("client_secret", self.copilot.client_secret.is_some()),
+            ]),
+            ("video", &[("chunk_dir", self.video.chunk_dir.is_some()), ("output_dir", self.video.output_dir.is_some())]),
+        ];
+        for (section, settings) in groups {
+            if settings.iter().any(|(_, set)| *set) {
+                for (key, _) in settings.iter().filter(|(_, set)| !set) {
+                    problems.push(format!("{}.{} is required when the other {} settings are set", section, key, section));
+                }
+            }
+        }
+
+        if problems.is_empty() { Ok(()) } else { Err(ConfigError(problems)) }
+    }
+
+    // Copy safe to show to operators: keys, secrets and the connection string are replaced
+    pub fn masked(&self) -> Self {
+        let mut config = self.clone();
+        for secret in [
+            &mut config.admin.token,
+            &mut config.database.connection_string,
+            &mut config.azure_read.key,
+            &mut config.azure_document.key,
+            &mut config.copilot.client_secret,
+            &mut config.webhooks.secret,
+        ] {
+            if secret.is_some() {
+                *secret = Some(MASK.to_string());
+            }
+        }
+        config
+    }
+}
+
+pub async fn get_config(State(state): State<AppState>) -> Response {
+    (StatusCode::OK, Json(ApiResponse::success(state.config.masked(), "Effective configuration"))).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cli_with_env(args: &[&str], env: &[(&str, &str)]) -> Cli {
+        let env: BTreeMap<&str, &str> = env.iter().copied().collect();
+        Cli::parse_with_env([&["ocr"], args].concat(), |name| env.get(name).map(OsString::from)).unwrap()
+    }
+
+    fn cli(args: &[&str]) -> Cli {
+        cli_with_env(args, &[])
+    }
+
+    fn problems(config: &AppConfig) -> Vec<String> {
+        config.validate().err().map(|e| e.0).unwrap_or_default()
+    }
+
+    #[test]
+    fn the_example_file_is_valid() {
+        let config = AppConfig::from_toml(include_str!("../config.example.toml")).unwrap();
+        assert_eq!(problems(&config), Vec::<String>::new());
+    }
+
+    #[test]
+    fn environment_overrides_the_file_and_flags_override_both() {
+        let path = std::env::temp_dir().join(format!("ocr-config-{}.toml", std::process::id()));
+        fs::write(&path, "[server]\nport = 1111\n[ocr]\njob_workers = 2\njob_queue_capacity = 4\ncache_capacity = 3\n").unwrap();
+        let env = [("OCR_JOB_WORKERS", "5"), ("OCR_CACHE_CAPACITY", "6"), ("OLLAMA_URL", "http://env:1,http://env:2")];
+        let config = AppConfig::load(cli_with_env(&["--config", path.to_str().unwrap(), "--job-workers", "7", "--ollama-url", "http://flag:1"], &env));
+        fs::remove_file(&path).unwrap();
+        let config = config.unwrap();
+
+        assert_eq!(config.server.port, 1111);
+        assert_eq!(config.ocr.job_queue_capacity, 4);
+        assert_eq!(config.ocr.cache_capacity, 6);
+        assert_eq!(config.ocr.job_workers, 7);
+        assert_eq!(config.ocr.provider_timeout_secs, OcrConfig::default().provider_timeout_secs);
+        // A list from the command line replaces the one from the environment instead of adding to it
+        let urls: Vec<&str> = config.ollama.instances.iter().map(|i| i.url.as_str()).collect();
+        assert_eq!(urls, ["http://flag:1"]);
+        let urls = cli_with_env(&[], &env).ollama_url.unwrap();
+        assert_eq!(urls, ["http://env:1", "http://env:2"]);
+
+        // An explicit file must exist, unknown keys are refused
+        assert!(AppConfig::load(cli(&["--config", "/nonexistent/ocr.toml"])).is_err());
+        assert!(AppConfig::from_toml("[server]\nprot = 1\n").is_err());
+    }
+
+    #[test]
+    fn flags_replace_settings_and_instances() {
+        let mut config = AppConfig::from_toml("[azure_read]\nendpoint = \"https://file\"\nkey = \"k\"\n").unwrap();
+        config.apply(cli(&["--azure-read-endpoint", "https://flag", "--ollama-url", "http://a:1, http://b:2", "--admin-token", "t"]));
+
+        assert_eq!(config.azure_read.endpoint.as_deref(), Some("https://flag"));
+        assert_eq!(config.azure_read.key.as_deref(), Some("k"));
+        let urls: Vec<&str> = config.ollama.instances.iter().map(|i| i.url.as_str()).collect();
+        assert_eq!(urls, ["http://a:1", "http://b:2"]);
+        assert_eq!(config.admin.token.as_deref(), Some("t"));
+    }
+
+    #[test]
+    fn blank_settings_count_as_unset() {
+        let mut config = AppConfig::from_toml("[admin]\ntoken = \" \"\n[database]\nconnection_string = \"\"\n[webhooks]\nsecret = \"s\"\n").unwrap();
+        config.normalize();
+        assert_eq!(config.admin.token, None);
+        assert_eq!(config.database.connection_string, None);
+        assert_eq!(config.webhooks.secret.as_deref(), Some("s"));
+    }
+
+    #[test]
+    fn every_problem_is_reported() {
+        let config = AppConfig::from_toml(r#"
+            [server]
+            port = 0
+            [admin]
+            token = "short"
+            [database]
+            pool_size = 0
+            cache_sql = true
+            [ocr]
+            job_workers = 0
+            [ollama]
+            max_concurrency = 0
+            instances = [{ url = "ftp://ollama", max_concurrency = 0 }]
+            [ollama.defaults]
+            temperature = 3.0
+            [ollama.models."m"]
+            top_p = 0.0
+            [azure_read]
+            endpoint = "not a url"
+            [copilot]
+            tenant_id = "t"
+            [counters.BAD]
+            description = ""
+            prompt = "p"
+            azure_model = "m"
+            schema = { type = "object" }
+            [[prompts]]
+            name = "counter"
+            version = 99
+            template = "{{nope}}"
+        "#).unwrap();
+        let found = problems(&config);
+
+        for expected in [
+            "server.port must be between 1 and 65535",
+            "admin.token must be at least 16 characters",
+            "database.pool_size must be between 1 and 1000, got 0",
+            "database.cache_sql requires database.connection_string",
+            "ocr.job_workers must be at least 1",
+            "ollama.max_concurrency must be at least 1",
+            "ollama.instances[0].url must be an http(s) URL, got 'ftp://ollama'",
+            "ollama.instances[0].max_concurrency must be at least 1",
+            "ollama.defaults.temperature must be between 0 and 2, got 3",
+            "ollama.models.\"m\".top_p must be greater than 0 and at most 1, got 0",
+            "azure_read.endpoint must be an http(s) URL, got 'not a url'",
+            "azure_read.key is required when the other azure_read settings are set",
+            "copilot.client_id is required when the other copilot settings are set",
+            "copilot.client_secret is required when the other copilot settings are set",
+            "counters.BAD: description must not be empty",
+        ] {
+            assert!(found.iter().any(|p| p == expected), "missing {:?} in {:#?}", expected, found);
+        }
+        assert!(found.iter().any(|p| p.starts_with("prompts counter v99: unknown variable {{nope}}")), "{:#?}", found);
+
+        let bad_connection = AppConfig::from_toml("[database]\nconnection_string = \"server=tcp:db,notaport\"\n").unwrap();
+        assert!(problems(&bad_connection).iter().any(|p| p.starts_with("database.connection_string is not a valid ADO connection string")));
+
+        let no_instances = AppConfig::from_toml("[ollama]\ninstances = []\n").unwrap();
+        assert_eq!(problems(&no_instances), ["ollama.instances must list at least one instance"]);
+    }
+
+    #[test]
+    fn secrets_are_masked() {
+        let config = AppConfig::from_toml(r#"
+            [admin]
+            token = "0123456789abcdef"
+            [database]
+            connection_string = "server=tcp:db,1433;user=ocr;password=hunter2"
+            [azure_read]
+            endpoint = "https://read"
+            key = "read-key"
+            [azure_document]
+            endpoint = "https://document"
+            key = "document-key"
+            [copilot]
+            tenant_id = "tenant"
+            client_id = "client"
+            client_secret = "client-secret"
+            [webhooks]
+            secret = "webhook-secret"
+        "#).unwrap();
+        let masked = toml::to_string(&config.masked()).unwrap();
+
+        for secret in ["0123456789abcdef", "hunter2", "read-key", "document-key", "client-secret", "webhook-secret"] {
+            assert!(!masked.contains(secret), "{} leaked", secret);
+        }
+        for shown in ["https://read", "https://document", "tenant", "client"] {
+            assert!(masked.contains(shown), "{} hidden", shown);
+        }
+        // Unset secrets stay unset
+        assert_eq!(AppConfig::default().masked().webhooks.secret, None);
+    }
+}
@@ -1,6 +1,6 @@
 use serde::{Deserialize, Serialize};
 
-use crate::status_code::AppStatusCode;
+use crate::{ocr::ollama_options::GenerationInfo, status_code::AppStatusCode};
 
 // 2. Standardized Response Envelope
 #[derive(Serialize, Deserialize)]
@@ -10,6 +10,29 @@ pub struct ApiResponse<T> {
     pub result: Option<T>, // None becomes null in JSON
     #[serde(rename = "statusCode")]
     pub status_code: AppStatusCode,
+    // Set on OCR responses only
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub meta: Option<ResponseMeta>,
+}
+
+// Who answered an OCR request and how long it took
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ResponseMeta {
+    pub request_id: String,
+    pub provider: String,
+    pub model: Option<String>,
+    pub elapsed_ms: u64,
+    // Ollama answers only
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub generation: Option<GenerationInfo>,
+}
+
+impl ResponseMeta {
+    pub fn with_generation(mut self, generation: Option<GenerationInfo>) -> Self {
+        self.generation = generation;
+        self
+    }
 }
 
 impl<T> ApiResponse<T> {
@@ -20,6 +43,7 @@ impl<T> ApiResponse<T> {
             message: message.to_string(),
             result: Some(result),
             status_code: AppStatusCode::Success,
+            meta: None,
         }
     }
 
@@ -29,10 +53,16 @@ impl<T> ApiResponse<T> {
             status: false,
             message,
             // result: None,
-            result: result,
+            result,
             status_code: code,
+            meta: None,
         }
     }
+
+    pub fn with_meta(mut self, meta: ResponseMeta) -> Self {
+        self.meta = Some(meta);
+        self
+    }
 }
 
 // const COUNTER_NAMES: &[&str] = &["HDFC ", "green", "blue"];
@@ -0,0 +1,377 @@
+use base64::{Engine, engine::general_purpose};
+use chrono::{DateTime, FixedOffset, NaiveDate, NaiveDateTime, NaiveTime};
+use futures_util::TryStreamExt;
+use serde_json::{Map, Value, json};
+use tiberius::{ColumnData, ColumnType, FromSql, Query as SqlQuery, QueryItem, Uuid, numeric::Numeric, xml::XmlData};
+
+use crate::model::{BindTarget, SpResult, SqlParam};
+
+// Only plain identifiers may be spliced into the SQL text; every value travels as a bound @P parameter
+fn is_sql_identifier(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn build_sp_sql(sp_name: &str, params: &[(&str, SqlParam)]) -> Result<String, String> {
+    if !sp_name.split('.').all(is_sql_identifier) {
+        return Err(format!("Invalid stored procedure name: {}", sp_name));
+    }
+
+    // @name = @P1, @other = @P2, ...
+    let mut bindings = Vec::with_capacity(params.len() + 2);
+    for (i, (name, _)) in params.iter().enumerate() {
+        if !is_sql_identifier(name) {
+            return Err(format!("Invalid parameter name: {}", name));
+        }
+        bindings.push(format!("@{} = @P{}", name, i + 1));
+    }
+    bindings.push("@retStatus = @status OUTPUT".to_string());
+    bindings.push("@retMessage = @msg OUTPUT".to_string());
+
+    // Add semicolons and ensure 'EXEC' isn't jammed against the DECLARE
+    Ok(format!(
+        "DECLARE @status BIT, @msg VARCHAR(1000); \
+        EXEC {} {}; \
+        SELECT @status AS retStatus, @msg AS retMessage;",
+        sp_name, bindings.join(", ")
+    ))
+}
+
+// Values in the order of their @P1.. placeholders
+fn bind_params<'a>(target: &mut impl BindTarget<'a>, params: &'a [(&str, SqlParam)]) {
+    for (_, value) in params {
+        value.bind_to_query(target);
+    }
+}
+
+pub async fn execute_sp_dynamic(
+    client: &mut bb8::PooledConnection<'_, bb8_tiberius::ConnectionManager>,
+    sp_name: &str,
+    params: &[(&str, SqlParam)], 
+) -> Result<SpResult, String> {
+
+    let sql = build_sp_sql(sp_name, params)?;
+
+    let mut query = SqlQuery::new(sql);
+    
+    // 3. Bind the Rust values to the @P placeholder markers
+    bind_params(&mut query, params);
+    
+    let mut stream = match query.query(client).await {
+        Ok(s) => s,
+        Err(e) => {
+            return Ok(SpResult { message: e.to_string(), ..Default::default() });
+        }
+    };
+
+    let mut folder = ResultSetFolder::default();
+    loop {
+        match stream.try_next().await {
+            // Every result set starts with its column metadata
+            Ok(Some(QueryItem::Metadata(meta))) => folder.columns(meta.columns().first().map(|c| c.name()).unwrap_or("")),
+            Ok(Some(QueryItem::Row(row))) => folder.row(row_to_json(&row)),
+            Ok(None) => break,
+            Err(e) => return Ok(SpResult { message: e.to_string(), ..Default::default() }),
+        }
+    }
+
+    Ok(folder.finish())
+}
+
+// Folds a procedure's output into SpResult: each column metadata opens a data result set, except
+// the retStatus/retMessage footer, whose row gives the status and message and is not reported as data
+#[derive(Default)]
+struct ResultSetFolder {
+    result: SpResult,
+    in_footer: bool,
+}
+
+impl ResultSetFolder {
+    fn columns(&mut self, first_column: &str) {
+        self.in_footer = first_column == "retStatus";
+        if !self.in_footer {
+            self.result.result_sets.push(Vec::new());
+        }
+    }
+
+    fn row(&mut self, row: Map<String, Value>) {
+        if self.in_footer {
+            self.result.status = row.get("retStatus").and_then(Value::as_bool).unwrap_or(false);
+            self.result.message = row.get("retMessage").and_then(Value::as_str).unwrap_or("Unknown").to_string();
+        } else if let Some(set) = self.result.result_sets.last_mut() {
+            set.push(row);
+        }
+    }
+
+    fn finish(self) -> SpResult {
+        self.result
+    }
+}
+
+fn row_to_json(row: &tiberius::Row) -> Map<String, Value> {
+    row.cells()
+        .map(|(column, data)| (column.name().to_string(), column_to_json(column.column_type(), data)))
+        .collect()
+}
+
+// An unscaled integer and its scale as a decimal string, without going through floats.
+// Used instead of Numeric's Display, which prints a sign on both sides of the point for negatives.
+fn decimal_to_string(value: i128, scale: u8) -> String {
+    let sign = if value < 0 { "-" } else { "" };
+    let digits = value.unsigned_abs();
+    if scale == 0 {
+        return format!("{}{}", sign, digits);
+    }
+    let divisor = 10u128.pow(scale as u32);
+    format!("{}{}.{:0width$}", sign, digits / divisor, digits % divisor, width = scale as usize)
+}
+
+// Maps one cell to JSON based on its declared SQL Server type.
+// DECIMAL/NUMERIC/MONEY are emitted as strings so amounts never lose precision,
+// dates and times as ISO-8601 and binary columns as base64.
+fn column_to_json(column_type: ColumnType, data: &ColumnData<'static>) -> Value {
+    fn get<'a, T: FromSql<'a>>(data: &'a ColumnData<'static>) -> Option<T> {
+        T::from_sql(data).ok().flatten()
+    }
+
+    let value = match column_type {
+        ColumnType::Null => None,
+        ColumnType::Bit | ColumnType::Bitn => get::<bool>(data).map(Value::Bool),
+        // Intn carries its width in the type info, so try each size
+        ColumnType::Int1 | ColumnType::Int2 | ColumnType::Int4 | ColumnType::Int8 | ColumnType::Intn => {
+            get::<i64>(data)
+                .or_else(|| get::<i32>(data).map(i64::from))
+                .or_else(|| get::<i16>(data).map(i64::from))
+                .or_else(|| get::<u8>(data).map(i64::from))
+                .map(|n| json!(n))
+        }
+        ColumnType::Float4 | ColumnType::Float8 | ColumnType::Floatn => {
+            get::<f64>(data)
+                .or_else(|| get::<f32>(data).map(f64::from))
+                .map(|n| json!(n))
+        }
+        // tiberius hands money over as the ten-thousandths count divided by 10^4 in an f64. Rounding
+        // back to that count is exact for amounts up to about 900 billion (2^53 ten-thousandths);
+        // the string is then built from the integer.
+        ColumnType::Money | ColumnType::Money4 => get::<f64>(data).map(|n| json!(decimal_to_string((n * 10_000.0).round() as i128, 4))),
+        ColumnType::Decimaln | ColumnType::Numericn => get::<Numeric>(data).map(|n| json!(decimal_to_string(n.value(), n.scale()))),
+        ColumnType::Guid => get::<Uuid>(data).map(|g| json!(g.to_string())),
+        ColumnType::Datetime | ColumnType::Datetime4 | ColumnType::Datetimen | ColumnType::Datetime2 => {
+            get::<NaiveDateTime>(data).map(|dt| json!(dt.format("%Y-%m-%dT%H:%M:%S%.f").to_string()))
+        }
+        ColumnType::Daten => get::<NaiveDate>(data).map(|d| json!(d.format("%Y-%m-%d").to_string())),
+        ColumnType::Timen => get::<NaiveTime>(data).map(|t| json!(t.format("%H:%M:%S%.f").to_string())),
+        ColumnType::DatetimeOffsetn => get::<DateTime<FixedOffset>>(data).map(|dt| json!(dt.to_rfc3339())),
+        ColumnType::BigVarBin | ColumnType::BigBinary | ColumnType::Image => {
+            get::<&[u8]>(data).map(|b| json!(general_purpose::STANDARD.encode(b)))
+        }
+        ColumnType::BigVarChar | ColumnType::BigChar | ColumnType::NVarchar | ColumnType::NChar
+        | ColumnType::Text | ColumnType::NText => get::<&str>(data).map(|s| json!(s)),
+        ColumnType::Xml => get::<&XmlData>(data).map(|x| json!(x.to_string())),
+        // Not decodable by tiberius; fall back to text if the driver allows it
+        ColumnType::Udt | ColumnType::SSVariant => get::<&str>(data).map(|s| json!(s)),
+    };
+
+    value.unwrap_or(Value::Null)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::borrow::Cow;
+    use tiberius::IntoSql;
+
+    const HOSTILE: &[&str] = &[
+        "O'Brien",
+        "'; DROP TABLE Users; --",
+        "C:\\chunks\\it's here\\video.mp4",
+        "@status = 1",
+        "'' OR 1=1 --",
+        "\u{feff}ünïcødé ' \" ; ",
+    ];
+
+    #[test]
+    fn hostile_strings_never_reach_sql_text() {
+        for value in HOSTILE {
+            let params = vec![
+                ("dailyRunAtmId", SqlParam::I64(7)),
+                ("filePath", SqlParam::String(value.to_string())),
+            ];
+            let sql = build_sp_sql("usp_Complete_Chunk_Video", &params).unwrap();
+
+            assert!(sql.contains("EXEC usp_Complete_Chunk_Video @dailyRunAtmId = @P1, @filePath = @P2, @retStatus"));
+            assert!(!sql.contains(value), "value leaked into SQL: {}", sql);
+        }
+    }
+
+    // Records what would be sent to SQL Server for each @P placeholder
+    #[derive(Default)]
+    struct RecordedBinds<'a>(Vec<ColumnData<'a>>);
+
+    impl<'a> BindTarget<'a> for RecordedBinds<'a> {
+        fn bind_value(&mut self, value: impl IntoSql<'a> + 'a) {
+            self.0.push(value.into_sql());
+        }
+    }
+
+    fn placeholders(sql: &str) -> Vec<usize> {
+        sql.split("@P").skip(1)
+            .map(|rest| rest.chars().take_while(char::is_ascii_digit).collect::<String>().parse().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn hostile_strings_are_bound_unchanged() {
+        for value in HOSTILE {
+            let params = vec![
+                ("dailyRunAtmId", SqlParam::I64(7)),
+                ("filePath", SqlParam::String(value.to_string())),
+                ("url", SqlParam::Null),
+            ];
+            let sql = build_sp_sql("usp_Complete_Chunk_Video", &params).unwrap();
+            let mut binds = RecordedBinds::default();
+            bind_params(&mut binds, &params);
+
+            // One bound value per placeholder, in placeholder order, the string untouched
+            assert_eq!(placeholders(&sql), [1, 2, 3]);
+            assert_eq!(binds.0, [
+                ColumnData::I64(Some(7)),
+                ColumnData::String(Some(Cow::Borrowed(*value))),
+                ColumnData::String(None),
+            ]);
+        }
+    }
+
+    fn row(value: Value) -> Map<String, Value> {
+        value.as_object().cloned().unwrap()
+    }
+
+    #[test]
+    fn result_sets_are_folded_and_the_footer_is_not_data() {
+        let mut folder = ResultSetFolder::default();
+        // A row before any metadata has no result set to go to
+        folder.row(row(json!({ "stray": 1 })));
+        folder.columns("atmId");
+        folder.row(row(json!({ "atmId": 1 })));
+        folder.row(row(json!({ "atmId": 2 })));
+        // An empty result set is kept so set positions stay stable
+        folder.columns("total");
+        folder.columns("retStatus");
+        folder.row(row(json!({ "retStatus": true, "retMessage": "Saved" })));
+        folder.columns("late");
+        folder.row(row(json!({ "late": "x" })));
+
+        let result = folder.finish();
+        assert!(result.status);
+        assert_eq!(result.message, "Saved");
+        assert_eq!(result.result_sets, [
+            vec![row(json!({ "atmId": 1 })), row(json!({ "atmId": 2 }))],
+            vec![],
+            vec![row(json!({ "late": "x" }))],
+        ]);
+    }
+
+    #[test]
+    fn a_null_footer_reads_as_failure() {
+        let mut folder = ResultSetFolder::default();
+        folder.columns("retStatus");
+        folder.row(row(json!({ "retStatus": null, "retMessage": null })));
+
+        let result = folder.finish();
+        assert!(!result.status);
+        assert_eq!(result.message, "Unknown");
+        assert!(result.result_sets.is_empty() && result.first_row().is_none());
+    }
+
+    #[test]
+    fn decimals_are_formatted_from_the_unscaled_integer() {
+        assert_eq!(decimal_to_string(0, 4), "0.0000");
+        assert_eq!(decimal_to_string(-1, 4), "-0.0001");
+        assert_eq!(decimal_to_string(12_345_000, 4), "1234.5000");
+        assert_eq!(decimal_to_string(-42, 0), "-42");
+        // MONEY limits
+        assert_eq!(decimal_to_string(i64::MAX as i128, 4), "922337203685477.5807");
+        assert_eq!(decimal_to_string(i64::MIN as i128, 4), "-922337203685477.5808");
+        // DECIMAL(38, 38)
+        assert_eq!(decimal_to_string(-(10i128.pow(38) - 1), 38), format!("-0.{}", "9".repeat(38)));
+    }
+
+    #[test]
+    fn every_declared_type_maps_to_json() {
+        use chrono::TimeZone;
+        let date = NaiveDate::from_ymd_opt(2026, 1, 5).unwrap();
+        let time = NaiveTime::from_hms_micro_opt(10, 30, 15, 250_000).unwrap();
+        let datetime = date.and_time(time);
+        let offset = FixedOffset::east_opt(5 * 3600 + 1800).unwrap().from_local_datetime(&datetime).unwrap();
+        let guid = Uuid::parse_str("6f9619ff-8b86-d011-b42d-00c04fc964ff").unwrap();
+
+        let cases: Vec<(ColumnType, ColumnData<'static>, Value)> = vec![
+            (ColumnType::Null, ColumnData::I32(Some(1)), Value::Null),
+            (ColumnType::Bit, ColumnData::Bit(Some(true)), json!(true)),
+            (ColumnType::Bitn, ColumnData::Bit(None), Value::Null),
+            (ColumnType::Int1, ColumnData::U8(Some(255)), json!(255)),
+            (ColumnType::Int2, ColumnData::I16(Some(-32768)), json!(-32768)),
+            (ColumnType::Int4, ColumnData::I32(Some(42)), json!(42)),
+            (ColumnType::Int8, ColumnData::I64(Some(i64::MAX)), json!(i64::MAX)),
+            (ColumnType::Intn, ColumnData::I64(None), Value::Null),
+            (ColumnType::Float4, ColumnData::F32(Some(1.5)), json!(1.5)),
+            (ColumnType::Floatn, ColumnData::F64(Some(-0.25)), json!(-0.25)),
+            (ColumnType::Money, ColumnData::F64(Some(1234.5)), json!("1234.5000")),
+            (ColumnType::Money, ColumnData::F64(Some(-0.0001)), json!("-0.0001")),
+            // Largest amounts the driver's f64 still carries exactly
+            (ColumnType::Money, ColumnData::F64(Some(900_000_000_000.999_9)), json!("900000000000.9999")),
+            (ColumnType::Money4, ColumnData::F64(Some(-214_748.364_8)), json!("-214748.3648")),
+            (ColumnType::Money4, ColumnData::F64(None), Value::Null),
+            (ColumnType::Decimaln, ColumnData::Numeric(Some(Numeric::new_with_scale(-12_345_678_901_234_567_890_123_456_789, 4))), json!("-1234567890123456789012345.6789")),
+            (ColumnType::Numericn, ColumnData::Numeric(Some(Numeric::new_with_scale(5, 2))), json!("0.05")),
+            (ColumnType::Decimaln, ColumnData::Numeric(None), Value::Null),
+            (ColumnType::Guid, ColumnData::Guid(Some(guid)), json!(guid.to_string())),
+            (ColumnType::Datetime, ColumnData::DateTime(Some(tiberius::time::DateTime::new(0, 0))), json!("1900-01-01T00:00:00")),
+            (ColumnType::Datetime2, datetime.into_sql(), json!("2026-01-05T10:30:15.250")),
+            (ColumnType::Daten, date.into_sql(), json!("2026-01-05")),
+            (ColumnType::Timen, time.into_sql(), json!("10:30:15.250")),
+            (ColumnType::DatetimeOffsetn, offset.into_sql(), json!("2026-01-05T10:30:15.250+05:30")),
+            (ColumnType::BigVarBin, ColumnData::Binary(Some(Cow::Borrowed(&[0x00, 0xff]))), json!("AP8=")),
+            (ColumnType::NVarchar, ColumnData::String(Some(Cow::Borrowed("O'Brien"))), json!("O'Brien")),
+            (ColumnType::BigVarChar, ColumnData::String(None), Value::Null),
+            (ColumnType::Xml, ColumnData::Xml(Some(Cow::Owned(XmlData::new("<slip/>")))), json!("<slip/>")),
+        ];
+        for (column_type, data, expected) in cases {
+            assert_eq!(column_to_json(column_type, &data), expected, "{:?} {:?}", column_type, data);
+        }
+    }
+
+    #[test]
+    fn no_params_still_produces_valid_exec() {
+        let sql = build_sp_sql("dbo.usp_Ping", &[]).unwrap();
+        assert!(sql.contains("EXEC dbo.usp_Ping @retStatus = @status OUTPUT, @retMessage = @msg OUTPUT;"));
+    }
+
+    #[test]
+    fn rejects_injected_identifiers() {
+        assert!(build_sp_sql("usp_X; DROP TABLE Users", &[]).is_err());
+        assert!(build_sp_sql("usp_X", &[("a = 1; --", SqlParam::Null)]).is_err());
+    }
+
+    // Full round trip through SQL Server; run with `cargo test -- --ignored` and a reachable database
+    #[tokio::test]
+    #[ignore]
+    async fn hostile_strings_round_trip_through_sql_server() {
+        use bb8::Pool;
+        use bb8_tiberius::ConnectionManager;
+
+        dotenv::dotenv().ok();
+        let conn_str = std::env::var("ATM_SYNC_DATABASE_CONNECTION_STRING").expect("ATM_SYNC_DATABASE_CONNECTION_STRING missing");
+        let config = tiberius::Config::from_ado_string(&conn_str).unwrap();
+        let pool = Pool::builder().max_size(1).build(ConnectionManager::new(config)).await.unwrap();
+        let mut client = pool.get().await.unwrap();
+
+        for value in HOSTILE {
+            let param = SqlParam::String(value.to_string());
+            let mut query = SqlQuery::new("SELECT @P1 AS echoed");
+            param.bind_to_query(&mut query);
+
+            let row = query.query(&mut client).await.unwrap().into_row().await.unwrap().unwrap();
+            assert_eq!(row.get::<&str, _>(0), Some(*value));
+        }
+    }
+}
@@ -0,0 +1,160 @@
+use std::fmt;
+use axum::{Json, body, http::header, response::{IntoResponse, Response}};
+use serde_json::Value;
+
+use crate::{constant::ApiResponse, status_code::AppStatusCode};
+
+// Plain-text rejections are short; anything longer is not worth echoing back
+const MAX_REJECTION_BODY: usize = 4096;
+
+// Everything a handler can fail with. Rendered as the usual ApiResponse envelope,
+// so callers always get `status: false` plus a statusCode instead of a dropped connection.
+#[derive(Debug)]
+pub enum AppError {
+    // Bad query parameters or body
+    InvalidPayload(String),
+    // Missing or wrong credentials for a protected route
+    Unauthorized(String),
+    // The request is valid but the resource is not in a state that allows it
+    Conflict(String),
+    NotFound(String),
+    // The upload is not an image/PDF/TIFF we can read
+    UnsupportedMedia(String),
+    ImageTooLarge(String),
+    RateLimited(String),
+    // The OCR backend failed (5xx, connection error, bad credentials on our side)
+    Provider(String),
+    ProviderTimeout(String),
+    // The OCR backend refused this particular input
+    ProviderRejected(String),
+    // The OCR backend answered with something we could not parse
+    ParseFailure(String),
+    // The requested backend is not configured on this server
+    ProviderUnavailable(String),
+    QueueFull(String),
+    JobNotFound,
+    DbPool,
+    // The procedure ran and reported failure through @retStatus, with its first row if any
+    SpExecution { message: String, data: Option<Value> },
+    // The procedure could not be executed at all
+    Database(String),
+    FileSystem(String),
+    Internal(String),
+}
+
+impl AppError {
+    pub fn code(&self) -> AppStatusCode {
+        match self {
+            AppError::InvalidPayload(_) => AppStatusCode::InvalidPayload,
+            AppError::Unauthorized(_) => AppStatusCode::Unauthorized,
+            AppError::Conflict(_) => AppStatusCode::Conflict,
+            AppError::NotFound(_) => AppStatusCode::NotFound,
+            AppError::UnsupportedMedia(_) => AppStatusCode::UnsupportedMedia,
+            AppError::ImageTooLarge(_) => AppStatusCode::ImageTooLarge,
+            AppError::RateLimited(_) => AppStatusCode::RateLimited,
+            AppError::Provider(_) => AppStatusCode::ProviderFailed,
+            AppError::ProviderTimeout(_) => AppStatusCode::ProviderTimeout,
+            AppError::ProviderRejected(_) => AppStatusCode::ProviderRejected,
+            AppError::ParseFailure(_) => AppStatusCode::ParseFailure,
+            AppError::ProviderUnavailable(_) => AppStatusCode::ProviderUnavailable,
+            AppError::QueueFull(_) => AppStatusCode::QueueFull,
+            AppError::JobNotFound => AppStatusCode::JobNotFound,
+            AppError::DbPool => AppStatusCode::DbPoolError,
+            AppError::SpExecution { .. } => AppStatusCode::SpExecutionFailed,
+            AppError::Database(_) => AppStatusCode::SpKnownFailed,
+            AppError::FileSystem(_) => AppStatusCode::PathCreation,
+            AppError::Internal(_) => AppStatusCode::InternalError,
+        }
+    }
+
+    // Classifies a non-2xx answer from an OCR backend
+    pub fn upstream(status: u16, message: String) -> Self {
+        match status {
+            429 => AppError::RateLimited(message),
+            408 | 504 => AppError::ProviderTimeout(message),
+            413 => AppError::ImageTooLarge(message),
+            415 => AppError::UnsupportedMedia(message),
+            // Our own credentials were refused, nothing the caller can fix
+            401 | 403 => AppError::Provider(message),
+            400..=499 => AppError::ProviderRejected(message),
+            _ => AppError::Provider(message),
+        }
+    }
+
+    // Classifies a failed HTTP call to an OCR backend
+    pub fn request(context: &str, e: reqwest::Error) -> Self {
+        if e.is_timeout() {
+            AppError::ProviderTimeout(format!("{}: timed out", context))
+        } else if e.is_decode() {
+            AppError::ParseFailure(format!("{}: {}", context, e))
+        } else {
+            AppError::Provider(format!("{}: {}", context, e))
+        }
+    }
+}
+
+impl fmt::Display for AppError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AppError::InvalidPayload(msg)
+            | AppError::Unauthorized(msg)
+            | AppError::Conflict(msg)
+            | AppError::NotFound(msg)
+            | AppError::UnsupportedMedia(msg)
+            | AppError::ImageTooLarge(msg)
+            | AppError::RateLimited(msg)
+            | AppError::Provider(msg)
+            | AppError::ProviderTimeout(msg)
+            | AppError::ProviderRejected(msg)
+            | AppError::ParseFailure(msg)
+            | AppError::ProviderUnavailable(msg)
+            | AppError::QueueFull(msg)
+            | AppError::Database(msg)
+            | AppError::FileSystem(msg)
+            | AppError::Internal(msg)
+            | AppError::SpExecution { message: msg, .. } => write!(f, "{}", msg),
+            AppError::JobNotFound => write!(f, "Job not found"),
+            AppError::DbPool => write!(f, "DB Pool Error"),
+        }
+    }
+}
+
+impl std::error::Error for AppError {}
+
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        let code = self.code();
+        let message = self.to_string();
+        let data = match self {
+            AppError::SpExecution { data, .. } => data,
+            _ => None,
+        };
+        (code.http_status(), Json(ApiResponse::<Value>::error(message, code, data))).into_response()
+    }
+}
+
+// Router-wide layer: axum's own rejections (bad query string, body too large, unknown route,
+// wrong method) are plain text; re-wrap them so every error uses the ApiResponse envelope.
+pub async fn wrap_rejections(response: Response) -> Response {
+    let status = response.status();
+    let is_json = response.headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.starts_with("application/json"));
+    if !(status.is_client_error() || status.is_server_error()) || is_json {
+        return response;
+    }
+
+    let text = body::to_bytes(response.into_body(), MAX_REJECTION_BODY).await
+        .ok()
+        .map(|b| String::from_utf8_lossy(&b).trim().to_string())
+        .filter(|t| !t.is_empty())
+        .unwrap_or_else(|| status.canonical_reason().unwrap_or("Request failed").to_string());
+
+    let res = ApiResponse::<Value>::error(text, AppStatusCode::from_http_status(status), None);
+    (status, Json(res)).into_response()
+}
+
+pub async fn not_found() -> AppError {
+    AppError::NotFound("Route not found".to_string())
+}
@@ -1,4 +1,3 @@
-use std::net::SocketAddr;
 use tracing_subscriber::EnvFilter;
 use dotenv::dotenv;
 mod router;
@@ -7,6 +6,11 @@ mod constant;
 mod status_code;
 mod state;
 mod model;
+mod db;
+mod error;
+mod request;
+mod capabilities;
+mod config;
 
 #[tokio::main]
 async  fn main() {
@@ -15,9 +19,14 @@ async  fn main() {
     .with_env_filter(EnvFilter::from_default_env())
     .init();
 
-    let rt = router::get_router().await;
+    let config = config::AppConfig::load(config::Cli::parse_with_env(std::env::args_os(), |name| std::env::var_os(name)).unwrap_or_else(|e| e.exit())).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+    let address = format!("{}:{}", config.server.host, config.server.port);
+
+    let rt = router::get_router(config).await;
 
-    let address = SocketAddr::from(([0, 0, 0, 0], 8791));
     println!("listening on {}", address);
 
     let listener = tokio::net::TcpListener::bind(address).await.unwrap();
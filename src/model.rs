@@ -1,36 +1,71 @@
-use serde::{Deserialize};
-use tiberius::Query;
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use tiberius::{IntoSql, Query, numeric::{Decimal, Numeric}};
 
-#[derive(Clone)]
+#[derive(Clone, Debug)]
+#[allow(dead_code)] // not every type is used by the current procedures yet
 pub enum SqlParam {
     String(String),
-    // I32(i32),
+    I32(i32),
     I64(i64),
-    // F32(f32),
-    // F64(f64),
-    // DateTime(NaiveDateTime), 
-    // Bool(bool),
-    // Null,
+    F32(f32),
+    F64(f64),
+    Decimal(Decimal),
+    DateTime(NaiveDateTime), 
+    Bool(bool),
+    Null,
     // Add other types as needed
 }
 
+// Where parameter values are bound: a tiberius Query, or a recorder in tests
+pub trait BindTarget<'a> {
+    fn bind_value(&mut self, value: impl IntoSql<'a> + 'a);
+}
+
+impl<'a> BindTarget<'a> for Query<'a> {
+    fn bind_value(&mut self, value: impl IntoSql<'a> + 'a) {
+        self.bind(value);
+    }
+}
+
 impl SqlParam {
-    pub fn bind_to_query<'a>(&'a self, query: &mut Query<'a>) {
+    pub fn bind_to_query<'a>(&'a self, query: &mut impl BindTarget<'a>) {
         match self {
-            SqlParam::String(s) => query.bind(s.as_str()),
-            //SqlParam::I32(i) => query.bind(*i),
-            SqlParam::I64(i) => query.bind(*i),
-            // SqlParam::F64(i) => query.bind(*i),
-            // SqlParam::F32(i) => query.bind(*i),
-            // SqlParam::Bool(b) => query.bind(*b),
-            // SqlParam::DateTime(dt) => query.bind(*dt),
-            // SqlParam::Null => query.bind(Option::<&str>::None),
+            SqlParam::String(s) => query.bind_value(s.as_str()),
+            SqlParam::I32(i) => query.bind_value(*i),
+            SqlParam::I64(i) => query.bind_value(*i),
+            SqlParam::F32(i) => query.bind_value(*i),
+            SqlParam::F64(i) => query.bind_value(*i),
+            // tiberius only implements IntoSql for its own Numeric
+            SqlParam::Decimal(d) => query.bind_value(Numeric::new_with_scale(d.mantissa(), d.scale() as u8)),
+            SqlParam::Bool(b) => query.bind_value(*b),
+            SqlParam::DateTime(dt) => query.bind_value(*dt),
+            SqlParam::Null => query.bind_value(Option::<&str>::None),
         };
     }
 }
 
+// Output of a stored procedure call: the retStatus/retMessage footer plus
+// every data result set, in order, as an array of row objects
+#[derive(Serialize, Default, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct SpResult {
+    pub status: bool,
+    pub message: String,
+    pub result_sets: Vec<Vec<Map<String, Value>>>,
+}
+
+impl SpResult {
+    pub fn first_row(&self) -> Option<Map<String, Value>> {
+        self.result_sets.first().and_then(|set| set.first()).cloned()
+    }
+}
+
 #[derive(Deserialize, Debug)]
 pub struct TokenResponse {
-    pub access_token: String
+    pub access_token: String,
+    // Lifetime in seconds, about an hour for client-credentials tokens
+    pub expires_in: Option<u64>,
 }
 
@@ -0,0 +1,33 @@
+use std::collections::HashMap;
+use axum::{Json, body::Bytes, extract::{Extension, Query, State}, http::{HeaderMap, StatusCode}, response::{IntoResponse, Response}};
+
+use crate::{constant::ApiResponse, error::AppError, request::RequestInfo, ocr::{history::RequestContext, pipeline::AnalyzeOutput, provider::OcrOptions}, state::AppState};
+
+pub async fn analyze(
+    State(state): State<AppState>,
+    Extension(request): Extension<RequestInfo>,
+    Query(params): Query<HashMap<String, String>>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Response, AppError> {
+    // 1. Validation
+    let provider_name = params.get("provider").map(|p| p.trim()).unwrap_or("");
+    let provider = state.provider(provider_name)?;
+
+    if body.is_empty() {
+        return Err(AppError::InvalidPayload("Empty request body".to_string()));
+    }
+
+    let options = OcrOptions::from_query(&params, provider.as_ref(), &state.counters, &state.prompts)?;
+
+    // 2. Run the selected backend
+    let ctx = RequestContext::from_request(&headers, &params);
+    let result = state.pipeline.analyze(provider.as_ref(), body, &options, &ctx).await?;
+    Ok(analyze_response(&request, provider.name(), result))
+}
+
+// Also used by the legacy endpoints for PDF/TIFF uploads, which have no shape of their own
+pub fn analyze_response(request: &RequestInfo, provider: &str, result: AnalyzeOutput) -> Response {
+    let meta = request.meta(provider, result.model()).with_generation(result.generation());
+    (StatusCode::OK, Json(ApiResponse::success(result, "OCR completed").with_meta(meta))).into_response()
+}
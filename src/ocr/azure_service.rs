@@ -1,137 +1,313 @@
-
-use axum::{Json, body::Bytes, extract::{ State}, http::StatusCode, response::IntoResponse};
+use std::{collections::HashMap, time::Duration};
+use async_trait::async_trait;
+use axum::{Json, body::Bytes, extract::{Extension, Query, State}, http::{self, StatusCode}, response::{IntoResponse, Response}};
 use reqwest::header::{ HeaderMap, HeaderValue};
+use serde::Serialize;
 use serde_json::{Value, json};
+use tokio::time::Instant;
+
+use crate::{ constant::ApiResponse, error::AppError, request::RequestInfo, ocr::{analyze::analyze_response, document::OcrDocument, history::RequestContext, pipeline::AnalyzeOutput, preprocess::PreprocessOptions, provider::{OcrOptions, OcrProvider, OcrResult}, schema::SchemaValidation}, state::AppState};
+
+// Used when no counter type picks another model
+const DOCUMENT_MODEL: &str = "prebuilt-receipt";
+
+pub struct AzureReadProvider {
+    client: reqwest::Client,
+    endpoint: String,
+    key: String,
+}
+
+impl AzureReadProvider {
+    pub fn new(client: reqwest::Client, endpoint: String, key: String) -> Self {
+        Self { client, endpoint, key }
+    }
+}
+
+#[async_trait]
+impl OcrProvider for AzureReadProvider {
+    fn name(&self) -> &'static str {
+        "azure-read"
+    }
+
+    async fn analyze(&self, image: Bytes, _options: &OcrOptions) -> Result<OcrResult, AppError> {
+        // Construct the URL for Image Analysis 4.0 - Read (OCR) feature
+        let url = format!(
+            // "{}/computervision/imageanalysis:analyze?api-version=2023-02-01-preview&features=read",
+            "{}/computervision/imageanalysis:analyze?api-version=2024-02-01&features=read",
+            self.endpoint.trim_end_matches('/')
+        );
+
+        let mut headers = HeaderMap::new();
+        headers.insert("Ocp-Apim-Subscription-Key", HeaderValue::from_str(&self.key).map_err(|e| AppError::Provider(format!("Invalid Azure Vision key: {}", e)))?);
+        headers.insert("Content-Type", HeaderValue::from_static("application/octet-stream"));
+
+        let response = self.client
+            .post(url)
+            .headers(headers)
+            .body(image)
+            .send()
+            .await
+            .map_err(|e| AppError::request("Failed to call Azure Vision API", e))?;
+
+        let status = response.status();
+        let text = response.text().await.map_err(|e| AppError::request("Failed to read Azure Vision response", e))?;
+        if !status.is_success() {
+            return Err(AppError::upstream(status.as_u16(), format!("Azure Vision error ({}): {}", status, text)));
+        }
+
+        // The response includes text blocks, lines, and words with coordinates
+        let result: Value = serde_json::from_str(&text)
+            .map_err(|e| AppError::ParseFailure(format!("Failed to parse Azure Vision response: {}", e)))?;
+
+        let document = OcrDocument::from_azure_read(&result);
 
-use crate::{ state::AppState};
+        Ok(OcrResult {
+            provider: self.name().to_string(),
+            model: result["modelVersion"].as_str().unwrap_or("read").to_string(),
+            text: document.text(),
+            data: None,
+            document,
+            raw: result,
+            cache: None,
+            preprocess: None,
+            validation: None,
+            repairs: Vec::new(),
+            prompt: None,
+            generation: None,
+            voting: None,
+            hybrid: None,
+        })
+    }
+}
+
+pub struct AzureDocumentProvider {
+    client: reqwest::Client,
+    endpoint: String,
+    key: String,
+    // Upper bound for submit + polling, the analysis runs asynchronously on Azure's side
+    timeout: Duration,
+}
+
+impl AzureDocumentProvider {
+    pub fn new(client: reqwest::Client, endpoint: String, key: String, timeout: Duration) -> Self {
+        Self { client, endpoint, key, timeout }
+    }
+}
+
+#[async_trait]
+impl OcrProvider for AzureDocumentProvider {
+    fn name(&self) -> &'static str {
+        "azure-document"
+    }
+
+    // PDFs and multi-page TIFFs are analyzed in one call, `?pages=` is forwarded
+    fn accepts_documents(&self) -> bool {
+        true
+    }
+
+    async fn analyze(&self, image: Bytes, options: &OcrOptions) -> Result<OcrResult, AppError> {
+        // The counter type's model, Prebuilt Receipt otherwise
+        let model = options.counter.as_ref().map(|c| c.azure_model.as_str()).unwrap_or(DOCUMENT_MODEL);
+        let mut url = format!(
+            "{}/documentintelligence/documentModels/{}:analyze?api-version=2024-11-30",
+            self.endpoint.trim_end_matches('/'),
+            model
+        );
+        if let Some(pages) = &options.pages {
+            url.push_str(&format!("&pages={}", pages));
+        }
+
+        // 1. Send the request
+        let deadline = Instant::now() + self.timeout;
+        let response = self.client.post(&url)
+            .header("Ocp-Apim-Subscription-Key", &self.key)
+            .header("Content-Type", "application/octet-stream")
+            .body(image)
+            .send()
+            .await
+            .map_err(|e| AppError::request("Failed to reach Azure", e))?;
+
+        // 2. Extract the header FIRST (while response still exists)
+        let operation_location = response.headers()
+            .get("Operation-Location")
+            .and_then(|h| h.to_str().ok())
+            .map(|h| h.to_string()); // Clone it into a String
+
+        // 3. Now check status and consume the body if needed
+        let status = response.status();
+        if !status.is_success() {
+            let err_body = response.text().await.unwrap_or_default();
+            return Err(AppError::upstream(status.as_u16(), format!("Azure Error : {}", err_body)));
+        }
 
+        // 4. Use the saved header string
+        let operation_url = operation_location
+            .ok_or_else(|| AppError::Provider("Azure returned 202 but missing Operation-Location header".to_string()))?;
+
+        loop {
+            let status_res = self.client.get(&operation_url)
+                .header("Ocp-Apim-Subscription-Key", &self.key)
+                .send().await
+                .map_err(|e| AppError::request("Polling failed", e))?;
+            if !status_res.status().is_success() {
+                let code = status_res.status().as_u16();
+                let err_body = status_res.text().await.unwrap_or_default();
+                return Err(AppError::upstream(code, format!("Azure polling error : {}", err_body)));
+            }
+
+            let result: Value = status_res.json().await
+                .map_err(|e| AppError::request("JSON parse failed", e))?;
+            let status = result["status"].as_str().unwrap_or("failed");
+
+            match status {
+                "succeeded" => {
+                    let fields = result["analyzeResult"]["documents"].get(0).map(|doc| doc["fields"].clone());
+                    let (data, validation) = match &options.counter {
+                        // Types without a field mapping (layout models) only get text and tables
+                        Some(counter) => match counter.map_azure_fields(fields.as_ref().unwrap_or(&Value::Null)) {
+                            Some(data) => {
+                                let (data, validation) = counter.finish(Some(data));
+                                (data, Some(validation))
+                            }
+                            None => (None, None),
+                        },
+                        None => {
+                            let fields = fields.ok_or_else(|| AppError::Provider("Azure analysis returned no documents".to_string()))?;
+                            (Some(map_azure_to_atm_json(&fields)), None)
+                        }
+                    };
+
+                    return Ok(OcrResult {
+                        provider: self.name().to_string(),
+                        model: model.to_string(),
+                        text: result["analyzeResult"]["content"].as_str().unwrap_or("").to_string(),
+                        data,
+                        document: OcrDocument::from_document_intelligence(&result["analyzeResult"]),
+                        raw: result,
+                        cache: None,
+                        preprocess: None,
+                        validation,
+                        repairs: Vec::new(),
+                        prompt: None,
+                        generation: None,
+                        voting: None,
+                        hybrid: None,
+                    });
+                },
+                "failed" => {
+                    // InvalidRequest/InvalidContent mean the document itself was refused
+                    let message = format!("Azure analysis failed: {}", result["error"]["message"].as_str().unwrap_or("unknown error"));
+                    return Err(match result["error"]["code"].as_str() {
+                        Some("InvalidRequest") | Some("InvalidContent") | Some("InvalidArgument") => AppError::ProviderRejected(message),
+                        _ => AppError::Provider(message),
+                    });
+                },
+                _ => { // notStarted or running
+                    if Instant::now() >= deadline {
+                        return Err(AppError::ProviderTimeout(format!("Azure analysis did not finish within {}s", self.timeout.as_secs())));
+                    }
+                    tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
+                }
+            }
+        }
+    }
+}
+
+// Result of the legacy /ocr/azure-ocr endpoint
+#[derive(Serialize)]
+pub struct AzureReadResult {
+    pub counter_name: String,
+    // Untouched Image Analysis response
+    pub value: Value,
+    pub document: OcrDocument,
+}
+
+// Result of the legacy /ocr/azure-ocr-document-intelligence endpoint
+#[derive(Serialize)]
+pub struct AzureStructuredResult {
+    // Fields mapped to the `?counter_name=` type, or the original ATM-shaped mapping without one
+    pub structured: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub validation: Option<SchemaValidation>,
+    // Raw Document Intelligence fields
+    #[serde(rename = "unStructured")]
+    pub un_structured: Value,
+    pub document: OcrDocument,
+}
 
 #[axum::debug_handler]
 pub async fn azure_ocr(
     State(state): State<AppState>,
+    Extension(request): Extension<RequestInfo>,
+    Query(params): Query<HashMap<String, String>>,
+    headers: http::HeaderMap,
     body: Bytes,
-) -> impl IntoResponse {
-    println!("azure_vision_key:{}", state.azure_vision_key);
-    println!("azure_vision_endpoint:{}", state.azure_vision_endpoint);
-
+) -> Result<Response, AppError> {
     if body.is_empty() {
-        let var_name =  "Empty request body";
-        return (StatusCode::BAD_REQUEST, Json(json!({"error": var_name}))).into_response()
+        return Err(AppError::InvalidPayload("Empty request body".to_string()));
     }
-    
-
-    // 2. Prepare Azure Vision API Request
-    let client = reqwest::Client::new();
-    
-    // Construct the URL for Image Analysis 4.0 - Read (OCR) feature
-    let url = format!(
-        // "{}/computervision/imageanalysis:analyze?api-version=2023-02-01-preview&features=read",
-        "{}/computervision/imageanalysis:analyze?api-version=2024-02-01&features=read",
-        state.azure_vision_endpoint
-    );
-
-    let mut headers = HeaderMap::new();
-    headers.insert("Ocp-Apim-Subscription-Key", HeaderValue::from_str(&state.azure_vision_key).unwrap());
-    headers.insert("Content-Type", HeaderValue::from_static("application/octet-stream"));
-
-    // 3. Send image to Azure
-    let response = client
-        .post(url)
-        .headers(headers)
-        .body(body)
-        .send()
-        .await
-        .expect("Failed to call Azure Vision API");
-
-    let text = response.text().await.expect("Failed to read body");
-    // let result: serde_json::Value = response.json().await.expect("Failed to parse Azure response");
-    let result: serde_json::Value = serde_json::from_str(&text)
-    .expect("CRITICAL: Failed to parse token response");
-
-    // The response includes text blocks, lines, and words with coordinates
-    
-    (StatusCode::OK, Json(json!({
-        "counter_name": "",
-        "value": *Json(result),
-    }))).into_response()
+
+    let provider = state.providers.get("azure-read")
+        .ok_or_else(|| AppError::ProviderUnavailable("Azure Vision is not configured".to_string()))?;
+
+    // Only preprocessing applies here, the Azure models take no prompt
+    let preprocess = PreprocessOptions::from_query(&params).map_err(AppError::InvalidPayload)?;
+    let options = OcrOptions { preprocess, ..Default::default() };
+
+    let ctx = RequestContext::from_request(&headers, &params);
+    let res = match state.pipeline.analyze(provider.as_ref(), body, &options, &ctx).await? {
+        AnalyzeOutput::Single(res) => *res,
+        // PDF/TIFF: per-page results as on /ocr/analyze
+        pages => return Ok(analyze_response(&request, provider.name(), pages)),
+    };
+
+    let meta = request.meta(provider.name(), Some(res.model.clone()));
+    let result = AzureReadResult {
+        counter_name: String::new(),
+        value: res.raw,
+        document: res.document,
+    };
+    Ok((StatusCode::OK, Json(ApiResponse::success(result, "OCR completed").with_meta(meta))).into_response())
 }
 
 #[axum::debug_handler]
 pub async fn azure_structured_ocr(
     State(state): State<AppState>,
+    Extension(request): Extension<RequestInfo>,
+    Query(params): Query<HashMap<String, String>>,
+    headers: http::HeaderMap,
     body: Bytes,
-) -> impl IntoResponse {
-    println!("azure_document_key:{}", state.azure_document_key);
-    println!("azure_document_endpoint:{}", state.azure_document_endpoint);
-
+) -> Result<Response, AppError> {
     if body.is_empty() {
-        let var_name =  "Empty request body";
-        return (StatusCode::BAD_REQUEST, Json(json!({"error": var_name}))).into_response()
-    }
-    let client = reqwest::Client::new();
-    
-    // Use the Prebuilt Receipt model URL
-    let url = format!(
-        "{}/documentintelligence/documentModels/prebuilt-receipt:analyze?api-version=2024-11-30",        
-        state.azure_document_endpoint.trim_end_matches('/')
-    );
-
-    // 1. Send the request
-    let response = client.post(&url)
-        .header("Ocp-Apim-Subscription-Key", &state.azure_document_key)
-        .header("Content-Type", "application/octet-stream")
-        .body(body)
-        .send()
-        .await
-        .expect("Failed to reach Azure");
-
-    // 2. Extract the header FIRST (while response still exists)
-    let operation_location = response.headers()
-        .get("Operation-Location")
-        .map(|h| h.to_str().unwrap().to_string()); // Clone it into a String
-
-    // 3. Now check status and consume the body if needed
-    if !response.status().is_success() {
-        let err_body = response.text().await.unwrap_or_default();
-        panic!("Azure Error : {}",  err_body);
+        return Err(AppError::InvalidPayload("Empty request body".to_string()));
     }
 
-    // 4. Use the saved header string
-    let operation_url = operation_location
-        .expect("Azure returned 202 but missing Operation-Location header");
-
-
-    loop {
-        let status_res = client.get(&operation_url)
-            .header("Ocp-Apim-Subscription-Key", &state.azure_document_key)
-            .send().await.expect("Polling failed");
-            
-        let result: serde_json::Value = status_res.json().await.expect("JSON parse failed");
-        let status = result["status"].as_str().unwrap_or("failed");
-
-        match status {
-            "succeeded" => {
-                if let Some(doc) = result["analyzeResult"]["documents"].get(0) {
-                    let structured = map_azure_to_atm_json(&doc["fields"]);
-                    // return Json(structured).into_response();
-                    let result = json!({
-                        "structured": structured,
-                        "unStructured": &doc["fields"],
-                    });
-                    return Json(result).into_response();
-                }
-            },
-            "failed" => {
-                return (StatusCode::INTERNAL_SERVER_ERROR, "Azure analysis failed").into_response();
-            },
-            _ => { // notStarted or running
-                tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
-            }
-        }
-    }
-}
+    let provider = state.providers.get("azure-document")
+        .ok_or_else(|| AppError::ProviderUnavailable("Azure Document Intelligence is not configured".to_string()))?;
 
+    // Preprocessing and the counter type apply here, the Azure models take no prompt
+    let preprocess = PreprocessOptions::from_query(&params).map_err(AppError::InvalidPayload)?;
+    let counter = state.counters.lookup(&params)?;
+    let options = OcrOptions { preprocess, counter, ..Default::default() };
 
+    let ctx = RequestContext::from_request(&headers, &params);
+    let res = match state.pipeline.analyze(provider.as_ref(), body, &options, &ctx).await? {
+        AnalyzeOutput::Single(res) => *res,
+        // PDF/TIFF: per-page results as on /ocr/analyze
+        pages => return Ok(analyze_response(&request, provider.name(), pages)),
+    };
+
+    let meta = request.meta(provider.name(), Some(res.model.clone()));
+    let result = AzureStructuredResult {
+        structured: res.data,
+        validation: res.validation,
+        un_structured: res.raw["analyzeResult"]["documents"][0]["fields"].clone(),
+        document: res.document,
+    };
+    Ok((StatusCode::OK, Json(ApiResponse::success(result, "OCR completed").with_meta(meta))).into_response())
+}
+
+// Shape returned before counter types existed, kept for callers that send no `?counter_name=`
 fn map_azure_to_atm_json(fields: &Value) -> Value {
     let mut dispenser_totals = Vec::new();
 
@@ -139,7 +315,7 @@ fn map_azure_to_atm_json(fields: &Value) -> Value {
     if let Some(items) = fields["Items"]["valueArray"].as_array() {
         for (i, item) in items.iter().enumerate() {
             let content = item["content"].as_str().unwrap_or("");
-            
+
             // Extract numbers from content like "INC RS.100000 OUT RS.37000"
             // We use a simple helper to find numbers in the string
             let numbers: Vec<f64> = content
@@ -150,7 +326,7 @@ fn map_azure_to_atm_json(fields: &Value) -> Value {
             dispenser_totals.push(json!({
                 "num": i + 1,
                 "currency": "INR",
-                "total": numbers.get(0).unwrap_or(&0.0),      // First number (e.g. 100000)
+                "total": numbers.first().unwrap_or(&0.0),      // First number (e.g. 100000)
                 "deposited": numbers.get(1).unwrap_or(&0.0),  // Second number
                 "left": numbers.get(3).unwrap_or(&0.0),       // Fourth number
                 "dispensed": numbers.get(2).unwrap_or(&0.0),  // Third number
@@ -0,0 +1,210 @@
+use std::{num::NonZeroUsize, sync::Arc};
+use axum::http::HeaderMap;
+use bb8::Pool;
+use bb8_tiberius::ConnectionManager;
+use lru::LruCache;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::sync::Mutex;
+
+use crate::{db::execute_sp_dynamic, model::SqlParam, ocr::provider::{OcrOptions, OcrProvider, OcrResult}};
+
+// How a request wants the cache to be used, from `Cache-Control` or `?cache=`
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum CacheMode {
+    // Serve a cached result when one exists
+    #[default]
+    Use,
+    // `no-cache`: always re-run the provider but refresh the cache with the new result
+    Refresh,
+    // `no-store`: neither read nor write the cache
+    Bypass,
+}
+
+impl CacheMode {
+    pub fn from_request(headers: &HeaderMap, cache_param: Option<&String>) -> Self {
+        let directives = headers.get("Cache-Control")
+            .and_then(|h| h.to_str().ok())
+            .unwrap_or("")
+            .to_ascii_lowercase();
+
+        if directives.contains("no-store") || cache_param.is_some_and(|v| v == "false" || v == "bypass") {
+            CacheMode::Bypass
+        } else if directives.contains("no-cache") || cache_param.is_some_and(|v| v == "refresh") {
+            CacheMode::Refresh
+        } else {
+            CacheMode::Use
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum CacheStatus {
+    Hit,
+    Miss,
+    Bypass,
+}
+
+// Reported on every OcrResult so callers can see where the answer came from
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct CacheInfo {
+    pub status: CacheStatus,
+    // `memory` or `sql` on a hit
+    pub source: Option<String>,
+    pub key: String,
+}
+
+// Same image + provider + model + prompt + preprocessing + page range + counter type => same key.
+// The prompt is the rendered template when one was picked, so each version is cached separately;
+// providers that ignore the prompt get the same key whatever prompt was sent.
+// Generation options are part of it too: another seed or temperature is another answer,
+// and so is a vote over several runs or sending the image with the hybrid provider's lines.
+pub fn cache_key(provider: &dyn OcrProvider, options: &OcrOptions, input: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    let preprocess = options.preprocess.describe();
+    let prompt = options.prompt().filter(|_| provider.takes_prompt()).unwrap_or_default();
+    let generation = serde_json::to_string(&options.generation).unwrap_or_default();
+    let voting = options.voting.as_ref().map(|v| serde_json::to_string(v).unwrap_or_default()).unwrap_or_default();
+    let pages = options.pages.as_ref().map(|p| p.to_string()).unwrap_or_default();
+    // The whole definition, a changed prompt or post-processing step must not hit old entries
+    let counter = options.counter.as_ref().map(|c| serde_json::to_string(c.as_ref()).unwrap_or_default()).unwrap_or_default();
+    let hybrid_image = if options.hybrid_image { "hybrid_image" } else { "" };
+    for part in [provider.name(), options.model.as_deref().unwrap_or(""), prompt.as_str(), preprocess.as_str(), pages.as_str(), counter.as_str(), generation.as_str(), voting.as_str(), hybrid_image] {
+        hasher.update(part.as_bytes());
+        hasher.update([0u8]);
+    }
+    hasher.update(input);
+    hex::encode(hasher.finalize())
+}
+
+// In-memory LRU in front of an optional SQL Server table (sql/ocr_cache.sql)
+#[derive(Clone)]
+pub struct ResultCache {
+    memory: Arc<Mutex<LruCache<String, OcrResult>>>,
+    sql: Option<Arc<Pool<ConnectionManager>>>,
+}
+
+impl ResultCache {
+    pub fn new(capacity: usize, sql: Option<Arc<Pool<ConnectionManager>>>) -> Self {
+        let capacity = NonZeroUsize::new(capacity).unwrap_or(NonZeroUsize::MIN);
+        Self {
+            memory: Arc::new(Mutex::new(LruCache::new(capacity))),
+            sql,
+        }
+    }
+
+    pub async fn get(&self, key: &str) -> Option<(OcrResult, &'static str)> {
+        if let Some(hit) = self.memory.lock().await.get(key) {
+            return Some((hit.clone(), "memory"));
+        }
+
+        let pool = self.sql.as_ref()?;
+        let mut client = pool.get().await.ok()?;
+        let params = vec![("cacheKey", SqlParam::String(key.to_string()))];
+        let sp = match execute_sp_dynamic(&mut client, "usp_Get_Ocr_Cache", &params).await {
+            Ok(sp) if sp.status => sp,
+            Ok(sp) => { tracing::warn!("OCR cache lookup failed: {}", sp.message); return None; }
+            Err(e) => { tracing::warn!("OCR cache lookup failed: {}", e); return None; }
+        };
+
+        let row = sp.first_row()?;
+        let result: OcrResult = serde_json::from_str(row.get("resultJson")?.as_str()?).ok()?;
+        // Promote SQL hits so the next lookup stays in memory
+        self.memory.lock().await.put(key.to_string(), result.clone());
+        Some((result, "sql"))
+    }
+
+    pub async fn put(&self, key: &str, result: &OcrResult) {
+        let mut stored = result.clone();
+        stored.cache = None;
+
+        if let Some(pool) = &self.sql {
+            let pool = pool.clone();
+            let params = vec![
+                ("cacheKey", SqlParam::String(key.to_string())),
+                ("provider", SqlParam::String(stored.provider.clone())),
+                ("resultJson", SqlParam::String(serde_json::to_string(&stored).unwrap_or_default())),
+            ];
+            tokio::spawn(async move {
+                let Ok(mut client) = pool.get().await else { return };
+                match execute_sp_dynamic(&mut client, "usp_Upsert_Ocr_Cache", &params).await {
+                    Ok(sp) if sp.status => {}
+                    Ok(sp) => tracing::warn!("OCR cache not saved: {}", sp.message),
+                    Err(e) => tracing::warn!("OCR cache not saved: {}", e),
+                }
+            });
+        }
+
+        self.memory.lock().await.put(key.to_string(), stored);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_trait::async_trait;
+    use axum::{body::Bytes, http::HeaderValue};
+
+    use crate::error::AppError;
+
+    struct Stub(&'static str, bool);
+
+    #[async_trait]
+    impl OcrProvider for Stub {
+        fn name(&self) -> &'static str {
+            self.0
+        }
+
+        fn takes_prompt(&self) -> bool {
+            self.1
+        }
+
+        async fn analyze(&self, _image: Bytes, _options: &OcrOptions) -> Result<OcrResult, AppError> {
+            unreachable!("cache keys never call the provider")
+        }
+    }
+
+    fn prompted(prompt: &str) -> OcrOptions {
+        OcrOptions { prompt: Some(prompt.to_string()), ..Default::default() }
+    }
+
+    #[test]
+    fn the_prompt_only_counts_for_providers_that_take_one() {
+        let llm = Stub("deepseek", true);
+        let read = Stub("azure_read", false);
+        let image = b"image";
+
+        assert_ne!(cache_key(&llm, &prompted("a"), image), cache_key(&llm, &prompted("b"), image));
+        assert_eq!(cache_key(&read, &prompted("a"), image), cache_key(&read, &prompted("b"), image));
+        assert_eq!(cache_key(&read, &prompted("a"), image), cache_key(&read, &OcrOptions::default(), image));
+
+        // Anything else that changes the answer changes the key
+        let base = cache_key(&llm, &prompted("a"), image);
+        assert_ne!(base, cache_key(&Stub("ollama", true), &prompted("a"), image));
+        assert_ne!(base, cache_key(&llm, &OcrOptions { model: Some("m".to_string()), ..prompted("a") }, image));
+        assert_ne!(base, cache_key(&llm, &OcrOptions { hybrid_image: true, ..prompted("a") }, image));
+        assert_ne!(base, cache_key(&llm, &prompted("a"), b"other image"));
+        assert_eq!(base, cache_key(&llm, &prompted("a"), image));
+    }
+
+    #[test]
+    fn cache_mode_comes_from_the_header_or_the_parameter() {
+        let mode = |header: Option<&'static str>, param: Option<&str>| {
+            let mut headers = HeaderMap::new();
+            if let Some(value) = header {
+                headers.insert("Cache-Control", HeaderValue::from_static(value));
+            }
+            CacheMode::from_request(&headers, param.map(str::to_string).as_ref())
+        };
+
+        assert_eq!(mode(None, None), CacheMode::Use);
+        assert_eq!(mode(Some("No-Cache"), None), CacheMode::Refresh);
+        assert_eq!(mode(Some("no-store"), None), CacheMode::Bypass);
+        assert_eq!(mode(Some("max-age=0, no-store"), Some("refresh")), CacheMode::Bypass);
+        assert_eq!(mode(None, Some("refresh")), CacheMode::Refresh);
+        assert_eq!(mode(None, Some("false")), CacheMode::Bypass);
+        assert_eq!(mode(None, Some("bypass")), CacheMode::Bypass);
+        assert_eq!(mode(None, Some("true")), CacheMode::Use);
+    }
+}
@@ -1,53 +1,146 @@
-use axum::{Json, extract::{Multipart, State},  response::IntoResponse};
-use reqwest::header::{AUTHORIZATION};
+use std::{collections::HashMap, sync::Arc};
+use async_trait::async_trait;
+use axum::{Json, body::Bytes, extract::{Extension, Multipart, Query, State}, http::{HeaderMap, StatusCode}, response::{IntoResponse, Response}};
+use serde::Serialize;
+use serde_json::{Value, json};
 
-use crate::{ state::AppState};
+use crate::{ constant::ApiResponse, error::AppError, request::RequestInfo, ocr::{analyze::analyze_response, document::OcrDocument, graph_token::GraphTokenManager, history::RequestContext, pipeline::AnalyzeOutput, provider::{OcrOptions, OcrProvider, OcrResult}}, state::AppState};
+
+pub struct CopilotProvider {
+    client: reqwest::Client,
+    tokens: Arc<GraphTokenManager>,
+}
+
+impl CopilotProvider {
+    pub fn new(client: reqwest::Client, tokens: Arc<GraphTokenManager>) -> Self {
+        Self { client, tokens }
+    }
+
+    // Sends a Graph request; a 401 means the token was revoked or expired early,
+    // so it is replaced and the request retried once
+    async fn send(&self, context: &str, build: impl Fn(&str) -> reqwest::RequestBuilder) -> Result<reqwest::Response, AppError> {
+        let token = self.tokens.token().await?;
+        let response = build(&token).send().await.map_err(|e| AppError::request(context, e))?;
+        if response.status() != reqwest::StatusCode::UNAUTHORIZED {
+            return Ok(response);
+        }
+
+        self.tokens.invalidate(&token).await;
+        let token = self.tokens.token().await?;
+        build(&token).send().await.map_err(|e| AppError::request(context, e))
+    }
+}
+
+#[async_trait]
+impl OcrProvider for CopilotProvider {
+    fn name(&self) -> &'static str {
+        "copilot"
+    }
+
+    fn takes_prompt(&self) -> bool {
+        true
+    }
+
+    async fn analyze(&self, image: Bytes, options: &OcrOptions) -> Result<OcrResult, AppError> {
+        // 1. Upload image to OneDrive (Required for Copilot to "see" the file)
+        // Endpoint: https://graph.microsoft.com
+        let upload_url = "https://graph.microsoft.com";
+
+        let upload_res = self.send("Upload failed", |token| self.client.put(upload_url)
+            .bearer_auth(token)
+            .body(image.clone())
+        ).await?;
+        if !upload_res.status().is_success() {
+            return Err(AppError::upstream(upload_res.status().as_u16(), format!("Upload failed with status {}", upload_res.status())));
+        }
+
+        let drive_item: Value = upload_res.json().await
+            .map_err(|e| AppError::request("Invalid upload response", e))?;
+        let web_url = drive_item["webUrl"].as_str()
+            .ok_or_else(|| AppError::ParseFailure("Upload response missing webUrl".to_string()))?;
+
+        // 2. Call Copilot Chat with the prompt
+        let chat_url = "https://graph.microsoft.com";
+        let ocr_prompt = format!(
+            "Using the image at {}, {}",
+            web_url,
+            options.prompt().as_deref().unwrap_or("extract text from image and convert into json format.")
+        );
+
+        let response = self.send("Copilot call failed", |token| self.client.post(chat_url)
+            .bearer_auth(token)
+            .json(&json!({
+                "message": { "text": ocr_prompt }
+            }))
+        ).await?;
+        if !response.status().is_success() {
+            return Err(AppError::upstream(response.status().as_u16(), format!("Copilot call failed with status {}", response.status())));
+        }
+
+        let result: Value = response.json().await
+            .map_err(|e| AppError::request("Invalid Copilot response", e))?;
+        let text = result["message"]["text"].as_str().unwrap_or("").to_string();
+
+        Ok(OcrResult {
+            provider: self.name().to_string(),
+            model: "copilot".to_string(),
+            document: OcrDocument::from_text(&text, None),
+            text,
+            data: None,
+            raw: result,
+            cache: None,
+            preprocess: None,
+            validation: None,
+            repairs: Vec::new(),
+            prompt: None,
+            generation: None,
+            voting: None,
+            hybrid: None,
+        })
+    }
+}
+
+// Result of the legacy /ocr/ask-copilot endpoint
+#[derive(Serialize)]
+pub struct CopilotOcrResult {
+    pub text: String,
+    // Untouched Copilot chat response
+    pub response: Value,
+}
 
 #[axum::debug_handler]
 pub async fn ocr_image(
     State(state): State<AppState>,
+    Extension(request): Extension<RequestInfo>,
+    Query(params): Query<HashMap<String, String>>,
+    headers: HeaderMap,
     mut multipart: Multipart,
-) -> impl IntoResponse {
+) -> Result<Response, AppError> {
     // 1. Extract image bytes from multipart
-    let mut image_bytes = Vec::new();
-    while let Some(field) = multipart.next_field().await.unwrap() {
+    let mut image_bytes = Bytes::new();
+    while let Some(field) = multipart.next_field().await.map_err(|e| AppError::InvalidPayload(e.to_string()))? {
         if field.name() == Some("image") {
-            image_bytes = field.bytes().await.unwrap().to_vec();
+            image_bytes = field.bytes().await.map_err(|e| AppError::InvalidPayload(e.to_string()))?;
             break;
         }
     }
+    if image_bytes.is_empty() {
+        return Err(AppError::InvalidPayload("Missing image field".to_string()));
+    }
 
-    // 2. Upload image to OneDrive (Required for Copilot to "see" the file)
-    // Endpoint: https://graph.microsoft.com
-    let upload_url = "https://graph.microsoft.com";
-    let client = reqwest::Client::new();
-    
-    let upload_res = client.put(upload_url)
-        .header(AUTHORIZATION, format!("Bearer {}", state.copilot_token))
-        .body(image_bytes)
-        .send()
-        .await
-        .expect("Upload failed");
-
-    let drive_item: serde_json::Value = upload_res.json().await.unwrap();
-    let web_url = drive_item["webUrl"].as_str().unwrap();
-
-    // 3. Call Copilot Chat with the prompt
-    let chat_url = "https://graph.microsoft.com"; 
-    let ocr_prompt = format!(
-        "Using the image at {}, extract text from image and convert into json format.", 
-        web_url
-    );
-
-    let response = client.post(chat_url)
-        .header(AUTHORIZATION, format!("Bearer {}", state.copilot_token))
-        .json(&serde_json::json!({
-            "message": { "text": ocr_prompt }
-        }))
-        .send()
-        .await
-        .expect("Copilot call failed");
-
-    Json(response.json::<serde_json::Value>().await.unwrap())
-}
+    let provider = state.providers.get("copilot")
+        .ok_or_else(|| AppError::ProviderUnavailable("Copilot is not configured".to_string()))?;
+
+    let options = OcrOptions::from_query(&params, provider.as_ref(), &state.counters, &state.prompts)?;
 
+    // 2. Upload + chat through the shared provider
+    let ctx = RequestContext::from_request(&headers, &params);
+    let res = match state.pipeline.analyze(provider.as_ref(), image_bytes, &options, &ctx).await? {
+        AnalyzeOutput::Single(res) => *res,
+        // PDF/TIFF: per-page results as on /ocr/analyze
+        pages => return Ok(analyze_response(&request, provider.name(), pages)),
+    };
+    let meta = request.meta(provider.name(), Some(res.model.clone()));
+    let result = CopilotOcrResult { text: res.text, response: res.raw };
+    Ok((StatusCode::OK, Json(ApiResponse::success(result, "OCR completed").with_meta(meta))).into_response())
+}
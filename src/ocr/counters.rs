@@ -0,0 +1,435 @@
+use std::{collections::{BTreeMap, HashMap}, sync::Arc};
+use axum::{Json, extract::State, http::StatusCode, response::{IntoResponse, Response}};
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Number, Value, json};
+
+use crate::{constant::ApiResponse, error::AppError, ocr::schema::{self, SchemaValidation}, state::AppState};
+
+// Types known without any configuration, see the file for the format
+const BUILT_IN: &str = include_str!("counters.toml");
+
+// Layouts seen on slips, two-digit years first so `05/12/24` is not read as year 24
+const DATE_FORMATS: &[&str] = &[
+    "%d/%m/%y", "%d-%m-%y", "%d.%m.%y", "%d-%b-%y",
+    "%Y-%m-%d", "%Y/%m/%d", "%d/%m/%Y", "%d-%m-%Y", "%d.%m.%Y", "%d-%b-%Y", "%d %b %Y", "%d %B %Y",
+];
+
+// Clean-up applied to extracted data before it is validated
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum PostProcess {
+    // Amount strings such as "Rs. 1,00,000.00" become numbers where the schema expects one
+    Numbers,
+    // Dates in the usual slip layouts become YYYY-MM-DD where the schema has `format = "date"`
+    Dates,
+    // Fills total_dispensed / total_remaining from the cassette rows
+    CassetteTotals,
+    // Fills the amount of each `denominations` row and total_amount from the note counts
+    DenominationAmounts,
+    // Turns the printed lines of `cassettes` (text, e.g. from `Items.content`) into cassette rows
+    ItemsToCassettes,
+}
+
+// A kind of document the service knows how to extract, selected with `?counter_name=`
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct CounterType {
+    // Taken from the table key
+    #[serde(skip_deserializing)]
+    pub name: String,
+    pub description: String,
+    pub prompt: String,
+    pub schema: Value,
+    pub azure_model: String,
+    #[serde(default)]
+    pub azure_fields: BTreeMap<String, String>,
+    #[serde(default)]
+    pub post_process: Vec<PostProcess>,
+}
+
+impl CounterType {
+    // Output of a Document Intelligence analysis in this type's shape; None when the type maps no fields
+    pub fn map_azure_fields(&self, fields: &Value) -> Option<Value> {
+        if self.azure_fields.is_empty() {
+            return None;
+        }
+        let mut data = Map::new();
+        for (output, path) in &self.azure_fields {
+            let (path, text) = match path.strip_suffix(".content") {
+                Some(path) => (path, true),
+                None => (path.as_str(), false),
+            };
+            let mut keys = path.split('.');
+            let mut field = &fields[keys.next().unwrap_or_default()];
+            for key in keys {
+                field = &field["valueObject"][key];
+            }
+            if !field.is_null() {
+                data.insert(output.clone(), if text { azure_text(field) } else { azure_value(field) });
+            }
+        }
+        Some(Value::Object(data))
+    }
+
+    // Runs the post-processing steps, then checks the result against the schema
+    pub fn finish(&self, mut data: Option<Value>) -> (Option<Value>, SchemaValidation) {
+        if let Some(data) = &mut data {
+            for step in &self.post_process {
+                step.apply(&self.schema, data);
+            }
+        }
+        let validation = schema::validate_output(&self.schema, data.as_ref());
+        (data, validation)
+    }
+
+    fn check(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        if self.name.is_empty() || self.name.chars().any(char::is_whitespace) {
+            problems.push("name must be non-empty and contain no whitespace".to_string());
+        }
+        for (key, value) in [("description", &self.description), ("prompt", &self.prompt), ("azure_model", &self.azure_model)] {
+            if value.trim().is_empty() {
+                problems.push(format!("{} must not be empty", key));
+            }
+        }
+
+        if self.schema["type"] != "object" {
+            problems.push("schema must describe an object (type = \"object\")".to_string());
+        }
+        if let Err(e) = jsonschema::validator_for(&self.schema) {
+            problems.push(format!("schema is not a valid JSON schema: {}", e));
+        }
+        for output in self.azure_fields.keys() {
+            if self.schema["properties"].get(output).is_none() {
+                problems.push(format!("azure_fields.{} is not a property of the schema", output));
+            }
+        }
+        problems
+    }
+}
+
+// Typed value of a Document Intelligence field, or its recognized text when there is no JSON equivalent
+fn azure_value(field: &Value) -> Value {
+    let value = match field["type"].as_str() {
+        Some("array") => return Value::Array(
+            field["valueArray"].as_array().map(|items| items.iter().map(azure_value).collect()).unwrap_or_default()
+        ),
+        Some("object") => return Value::Object(
+            field["valueObject"].as_object().map(|o| o.iter().map(|(k, v)| (k.clone(), azure_value(v))).collect()).unwrap_or_default()
+        ),
+        Some("string") => &field["valueString"],
+        Some("date") => &field["valueDate"],
+        Some("time") => &field["valueTime"],
+        Some("number") => &field["valueNumber"],
+        Some("integer") => &field["valueInteger"],
+        Some("boolean") => &field["valueBoolean"],
+        Some("currency") => &field["valueCurrency"]["amount"],
+        _ => &Value::Null,
+    };
+    if value.is_null() { field["content"].clone() } else { value.clone() }
+}
+
+// Recognized text of a Document Intelligence field, of each item for an array
+fn azure_text(field: &Value) -> Value {
+    match field["valueArray"].as_array() {
+        Some(items) => Value::Array(items.iter().map(|item| item["content"].clone()).collect()),
+        None => field["content"].clone(),
+    }
+}
+
+impl PostProcess {
+    fn apply(self, schema: &Value, data: &mut Value) {
+        match self {
+            Self::Numbers => visit(schema, data, &|schema, value| {
+                let (integer, float) = (allows(schema, "integer"), allows(schema, "number"));
+                if let Value::String(text) = value
+                    && (integer || float)
+                    && let Some(number) = parse_number(text, !float)
+                {
+                    *value = number;
+                }
+            }),
+            Self::Dates => visit(schema, data, &|schema, value| {
+                if schema["format"] == "date"
+                    && let Value::String(text) = value
+                    && let Some(date) = parse_date(text)
+                {
+                    *value = Value::String(date);
+                }
+            }),
+            Self::CassetteTotals => cassette_totals(data),
+            Self::DenominationAmounts => denomination_amounts(data),
+            Self::ItemsToCassettes => items_to_cassettes(data),
+        }
+    }
+}
+
+// Calls `f` on every scalar in `data` with the schema node describing it. `$ref`s are not followed.
+fn visit(schema: &Value, data: &mut Value, f: &impl Fn(&Value, &mut Value)) {
+    match data {
+        Value::Object(fields) => for (key, value) in fields.iter_mut() {
+            if let Some(property) = schema["properties"].get(key) {
+                visit(property, value, f);
+            }
+        },
+        Value::Array(items) => for item in items {
+            visit(&schema["items"], item, f);
+        },
+        _ => f(schema, data),
+    }
+}
+
+// Whether the `type` of a schema node includes `kind`
+fn allows(schema: &Value, kind: &str) -> bool {
+    match &schema["type"] {
+        Value::String(t) => t == kind,
+        Value::Array(types) => types.iter().any(|t| t.as_str() == Some(kind)),
+        _ => false,
+    }
+}
+
+// Whole numbers become integers so `37000` does not turn into `37000.0`
+fn number(value: f64) -> Value {
+    if value.fract() == 0.0 && value.abs() < 9.0e15 {
+        json!(value as i64)
+    } else {
+        Number::from_f64(value).map(Value::Number).unwrap_or(Value::Null)
+    }
+}
+
+// The single number in `text`, ignoring currency labels and grouping commas. None when there
+// is no number, more than one, or a fraction where only integers are allowed.
+fn parse_number(text: &str, integer_only: bool) -> Option<Value> {
+    let tokens: Vec<&str> = text
+        .split(|c: char| !(c.is_ascii_digit() || matches!(c, '.' | ',' | '-')))
+        .map(|t| t.trim_matches(|c| matches!(c, '.' | ',')))
+        .filter(|t| t.chars().any(|c| c.is_ascii_digit()))
+        .collect();
+    let [token] = tokens[..] else { return None };
+
+    let value: f64 = token.replace(',', "").parse().ok()?;
+    if integer_only && value.fract() != 0.0 {
+        return None;
+    }
+    Some(number(value))
+}
+
+fn parse_date(text: &str) -> Option<String> {
+    let text = text.trim();
+    DATE_FORMATS.iter()
+        .find_map(|format| NaiveDate::parse_from_str(text, format).ok())
+        .map(|date| date.format("%Y-%m-%d").to_string())
+}
+
+// Sum of denomination × count over the cassette rows; left alone when a row misses either
+fn cassette_totals(data: &mut Value) {
+    let Some(cassettes) = data["cassettes"].as_array().filter(|c| !c.is_empty()) else { return };
+    let totals: Vec<(&str, Option<f64>)> = [("total_dispensed", "dispensed"), ("total_remaining", "remaining")]
+        .into_iter()
+        .map(|(total, count)| (total, cassettes.iter().map(|c| Some(c["denomination"].as_f64()? * c[count].as_f64()?)).sum()))
+        .collect();
+
+    for (total, sum) in totals {
+        if data[total].is_null() && let Some(sum) = sum && let Some(fields) = data.as_object_mut() {
+            fields.insert(total.to_string(), number(sum));
+        }
+    }
+}
+
+// Each text row becomes a cassette numbered in order, its numbers read as denomination, loaded,
+// dispensed, remaining and rejected. Lines without a number (headings) are dropped, rows that are
+// already objects are kept as they are.
+fn items_to_cassettes(data: &mut Value) {
+    let Some(rows) = data.get_mut("cassettes").and_then(Value::as_array_mut) else { return };
+    let mut cassette = 0;
+    let mut cassettes = Vec::with_capacity(rows.len());
+    for row in rows.drain(..) {
+        let Value::String(line) = row else {
+            cassettes.push(row);
+            continue;
+        };
+        let numbers = line_numbers(&line);
+        if numbers.is_empty() {
+            continue;
+        }
+        cassette += 1;
+        let column = |i: usize| numbers.get(i).cloned().unwrap_or(Value::Null);
+        cassettes.push(json!({
+            "cassette": cassette,
+            "denomination": column(0),
+            "loaded": column(1),
+            "dispensed": column(2),
+            "remaining": column(3),
+            "rejected": column(4),
+        }));
+    }
+    *rows = cassettes;
+}
+
+// Numbers printed on a line. A label such as "CASS1" is not one, an amount such as "RS.500" is.
+fn line_numbers(line: &str) -> Vec<Value> {
+    line.split_whitespace()
+        .filter_map(|word| {
+            let amount = word.trim_start_matches(char::is_alphabetic);
+            let amount = if amount.len() < word.len() { amount.strip_prefix(['.', ':'])? } else { amount };
+            parse_number(amount, false)
+        })
+        .collect()
+}
+
+fn denomination_amounts(data: &mut Value) {
+    let Some(rows) = data.get_mut("denominations").and_then(Value::as_array_mut).filter(|r| !r.is_empty()) else { return };
+    let mut total = Some(0.0);
+    for row in rows.iter_mut() {
+        if row["amount"].is_null()
+            && let (Some(denomination), Some(count)) = (row["denomination"].as_f64(), row["count"].as_f64())
+            && let Some(fields) = row.as_object_mut()
+        {
+            fields.insert("amount".to_string(), number(denomination * count));
+        }
+        total = total.zip(row["amount"].as_f64()).map(|(total, amount)| total + amount);
+    }
+
+    if data["total_amount"].is_null() && let Some(total) = total && let Some(fields) = data.as_object_mut() {
+        fields.insert("total_amount".to_string(), number(total));
+    }
+}
+
+// Built-in counter types overlaid with the ones from config.toml
+pub struct CounterRegistry {
+    types: BTreeMap<String, Arc<CounterType>>,
+}
+
+impl CounterRegistry {
+    // Every invalid type is reported, prefixed with its config key
+    pub fn new(configured: &BTreeMap<String, CounterType>) -> Result<Self, Vec<String>> {
+        let mut types: BTreeMap<String, CounterType> = toml::from_str(BUILT_IN).expect("Invalid built-in counter types");
+        types.extend(configured.clone());
+
+        let mut problems = Vec::new();
+        for (name, counter) in &mut types {
+            counter.name = name.clone();
+            problems.extend(counter.check().into_iter().map(|p| format!("counters.{}: {}", name, p)));
+        }
+        if !problems.is_empty() {
+            return Err(problems);
+        }
+        Ok(Self { types: types.into_iter().map(|(name, counter)| (name, Arc::new(counter))).collect() })
+    }
+
+    // `?counter_name=`, optional; an unknown name lists the available ones
+    pub fn lookup(&self, params: &HashMap<String, String>) -> Result<Option<Arc<CounterType>>, AppError> {
+        let Some(name) = params.get("counter_name").map(|v| v.trim()).filter(|v| !v.is_empty()) else { return Ok(None) };
+        self.types.get(name).cloned().map(Some).ok_or_else(|| AppError::InvalidPayload(
+            format!("Unknown counter_name '{}'. Available: {}", name, self.types.keys().cloned().collect::<Vec<_>>().join(", "))
+        ))
+    }
+}
+
+pub async fn list_counters(State(state): State<AppState>) -> Response {
+    let counters: Vec<CounterType> = state.counters.types.values().map(|c| c.as_ref().clone()).collect();
+    (StatusCode::OK, Json(ApiResponse::success(counters, "Counter types"))).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn built_in(name: &str) -> Arc<CounterType> {
+        let registry = CounterRegistry::new(&BTreeMap::new()).unwrap();
+        registry.types[name].clone()
+    }
+
+    #[test]
+    fn built_in_types_are_valid() {
+        let registry = CounterRegistry::new(&BTreeMap::new()).unwrap();
+        let names: Vec<_> = registry.types.keys().map(String::as_str).collect();
+        assert_eq!(names, ["ATM", "CDM", "CHEQUE", "CIT_MANIFEST", "DEPOSIT_SLIP"]);
+        for counter in registry.types.values() {
+            assert!(schemars::Schema::try_from(counter.schema.clone()).is_ok(), "{}", counter.name);
+        }
+    }
+
+    #[test]
+    fn configured_types_are_added_and_checked() {
+        let config: BTreeMap<String, CounterType> = toml::from_str(r#"
+            [FX_RECEIPT]
+            description = "Currency exchange receipt"
+            prompt = "Extract the receipt."
+            azure_model = "prebuilt-receipt"
+            azure_fields = { total = "Total", shop = "MerchantName" }
+            schema = { type = "object", properties = { total = { type = "number" } } }
+        "#).unwrap();
+
+        let problems = CounterRegistry::new(&config).err().unwrap();
+        assert_eq!(problems, ["counters.FX_RECEIPT: azure_fields.shop is not a property of the schema"]);
+
+        let mut config = config;
+        config.get_mut("FX_RECEIPT").unwrap().azure_fields.remove("shop");
+        let registry = CounterRegistry::new(&config).unwrap();
+        let params = HashMap::from([("counter_name".to_string(), "FX_RECEIPT".to_string())]);
+        assert_eq!(registry.lookup(&params).unwrap().unwrap().name, "FX_RECEIPT");
+    }
+
+    #[test]
+    fn post_processing_fixes_amounts_dates_and_totals() {
+        let atm = built_in("ATM");
+        let data = json!({
+            "bank": "HDFC", "atm_id": "S1AW001", "date": "05/12/24", "time": null,
+            "cassettes": [
+                { "cassette": 1, "denomination": "Rs.500", "dispensed": "20", "remaining": "1,180" },
+                { "cassette": 2, "denomination": 100, "dispensed": 15, "remaining": 485 }
+            ]
+        });
+
+        let (data, validation) = atm.finish(Some(data));
+        let data = data.unwrap();
+        assert!(validation.valid, "{:?}", validation.errors);
+        assert_eq!(data["date"], "2024-12-05");
+        assert_eq!(data["cassettes"][0]["remaining"], 1180);
+        assert_eq!(data["total_dispensed"], 11500);
+        assert_eq!(data["total_remaining"], 638500);
+    }
+
+    #[test]
+    fn maps_document_intelligence_fields() {
+        let cheque = built_in("CHEQUE");
+        let fields = json!({
+            "BankName": { "type": "string", "valueString": "State Bank", "content": "STATE BANK" },
+            "CheckDate": { "type": "date", "valueDate": "2024-03-01" },
+            "NumberAmount": { "type": "number", "valueNumber": 2500.5 },
+            "MICR": { "type": "object", "valueObject": { "AccountNumber": { "type": "string", "content": "0012345" } } }
+        });
+
+        let data = cheque.map_azure_fields(&fields).unwrap();
+        assert_eq!(data, json!({ "bank": "State Bank", "date": "2024-03-01", "amount": 2500.5, "account_number": "0012345" }));
+        assert!(built_in("CIT_MANIFEST").map_azure_fields(&fields).is_none());
+    }
+
+    #[test]
+    fn receipt_items_become_cassettes() {
+        let atm = built_in("ATM");
+        let item = |content: &str| json!({ "type": "object", "valueObject": {}, "content": content });
+        let fields = json!({
+            "MerchantName": { "type": "string", "valueString": "HDFC" },
+            "MerchantAddress": { "type": "string", "content": "S1AW001" },
+            "TransactionDate": { "type": "date", "valueDate": "2024-12-05" },
+            "Items": { "type": "array", "valueArray": [
+                item("TYPE DENOM LOADED DISP LEFT"),
+                item("CASS1 500 1,200 20 1,180"),
+                item("CASS2 RS.100 500 15 485 0"),
+            ] }
+        });
+
+        let (data, validation) = atm.finish(atm.map_azure_fields(&fields));
+        let data = data.unwrap();
+        assert!(validation.valid, "{:?}", validation.errors);
+        assert_eq!(data["atm_id"], "S1AW001");
+        assert_eq!(data["cassettes"], json!([
+            { "cassette": 1, "denomination": 500, "loaded": 1200, "dispensed": 20, "remaining": 1180, "rejected": null },
+            { "cassette": 2, "denomination": 100, "loaded": 500, "dispensed": 15, "remaining": 485, "rejected": 0 }
+        ]));
+        assert_eq!(data["total_dispensed"], 11500);
+    }
+}
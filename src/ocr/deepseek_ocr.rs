@@ -1,54 +1,49 @@
-use std::collections::HashMap;
-use axum::{Json, body::Bytes, extract::{Query, State}, http::StatusCode, response::{IntoResponse, Response}};
+use std::{collections::HashMap, sync::Arc};
+use async_trait::async_trait;
+use axum::{Json, body::Bytes, extract::{Extension, Query, State}, http::{HeaderMap, StatusCode}, response::{IntoResponse, Response}};
 use base64::{Engine, engine::general_purpose};
-use futures_util::TryStreamExt;
-use ollama_rs::{ generation::{completion::request::GenerationRequest, images::Image}};
-use serde_json::{Map, Value, json};
-use tiberius::{Query as SqlQuery,  QueryItem };
+use futures_util::future::join_all;
+use ollama_rs::generation::{completion::request::GenerationRequest, images::Image, parameters::{FormatType, JsonStructure}};
+use serde::Serialize;
+use serde_json::{Value, json};
 use tokio::{fs, io::AsyncWriteExt};
 
-use crate::{constant::ApiResponse, model::SqlParam, state::AppState, status_code::AppStatusCode};
+use crate::{constant::ApiResponse, db::execute_sp_dynamic, error::AppError, model::SqlParam, request::RequestInfo, ocr::{analyze::analyze_response, document::OcrDocument, history::RequestContext, pipeline::AnalyzeOutput, ollama_options::{GenerationInfo, ModelDefaults}, ollama_pool::OllamaPool, provider::{OcrOptions, OcrProvider, OcrResult}, json_repair::{self, JsonRepair}, schema::SchemaValidation, voting::{self, VoteMode, VoteReport, VoteRun}}, state::AppState};
+
 
 pub async fn mark_complete(
     State(state): State<AppState>,
     Query(params): Query<HashMap<String, String>>,
-) -> Response {
+) -> Result<Response, AppError> {
     // 1. Validation
-    let daily_run_atm_id = match params.get("dailyRunAtmId") {
-        Some(v) => v.to_string(),
-        None => {
-            let res = ApiResponse::<Value>::error("Missing Daily Run Atm Id".to_string(), AppStatusCode::InvalidPayload, None);
-            return (StatusCode::BAD_REQUEST, Json(res)).into_response();
-        }
-    };
+    let daily_run_atm_id = params.get("dailyRunAtmId")
+        .ok_or_else(|| AppError::InvalidPayload("Missing Daily Run Atm Id".to_string()))?
+        .to_string();
 
     let atm_id: i64 = daily_run_atm_id
     .parse()                // Parse to i64
     .unwrap_or(0); 
 
     if atm_id == 0 {
-        let res = ApiResponse::<Value>::error("invalid daily run atm id".to_string(), AppStatusCode::InvalidPayload, None);
-        return (StatusCode::BAD_REQUEST, Json(res)).into_response();
+        return Err(AppError::InvalidPayload("invalid daily run atm id".to_string()));
     }
 
-    let chunk_dir = format!("{}/{}", "", daily_run_atm_id);
-    let output_dir = format!("{}", "");
+    // The route is disabled unless both directories are configured
+    let (Some(chunk_root), Some(output_dir)) = (&state.config.video.chunk_dir, &state.config.video.output_dir) else {
+        return Err(state.capabilities.video.unavailable());
+    };
+    let chunk_dir = format!("{}/{}", chunk_root, daily_run_atm_id);
     let output_path = format!("{}/{}.mp4", output_dir, daily_run_atm_id);
 
     // 2. File Processing (Merging Chunks)
-    if let Err(e) = fs::create_dir_all(&output_dir).await {
-        return (StatusCode::INTERNAL_SERVER_ERROR, Json(ApiResponse::<Value>::error(format!("Dir Error: {}", e), AppStatusCode::PathCreation, None))).into_response();
-    }
+    fs::create_dir_all(output_dir).await
+        .map_err(|e| AppError::FileSystem(format!("Dir Error: {}", e)))?;
 
-    let mut output_file = match tokio::fs::File::create(&output_path).await {
-        Ok(f) => f,
-        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, Json(ApiResponse::<Value>::error(e.to_string(), AppStatusCode::PathCreation, None))).into_response(),
-    };
+    let mut output_file = tokio::fs::File::create(&output_path).await
+        .map_err(|e| AppError::FileSystem(e.to_string()))?;
 
-    let mut entries = match tokio::fs::read_dir(&chunk_dir).await {
-        Ok(e) => e,
-        Err(e) => return (StatusCode::NOT_FOUND, Json(ApiResponse::<Value>::error(format!("Chunks not found. {}", e.to_string()), AppStatusCode::PathCreation, None))).into_response(),
-    };
+    let mut entries = tokio::fs::read_dir(&chunk_dir).await
+        .map_err(|e| AppError::NotFound(format!("Chunks not found. {}", e)))?;
 
     let mut chunks = vec![];
     while let Ok(Some(entry)) = entries.next_entry().await {
@@ -65,10 +60,7 @@ pub async fn mark_complete(
     let url = "";
 
     // 4. DB Update
-    let mut client = match state.db_pool.get().await {
-        Ok(c) => c,
-        Err(_) => return (StatusCode::INTERNAL_SERVER_ERROR, Json(ApiResponse::<Value>::error("DB Pool Error".to_string(), AppStatusCode::DbPoolError, None))).into_response(),
-    };
+    let mut client = state.db()?.get().await.map_err(|_| AppError::DbPool)?;
 
     
     let params = vec![
@@ -78,224 +70,281 @@ pub async fn mark_complete(
         ("url", SqlParam::String(url.to_string())),
     ];
 
-    let response = execute_sp_dynamic(&mut client, "usp_Complete_Chunk_Video", &params).await;
+    let sp = execute_sp_dynamic(&mut client, "usp_Complete_Chunk_Video", &params).await
+        .map_err(AppError::Database)?;
 
     // 5. Finalize & Cleanup
-    match response {
-        Ok((true, msg, data)) => {
-            // Delete temporary chunks
-            // let _ = tokio::fs::remove_dir_all(chunk_dir).await;
+    let result_data = sp.first_row().map(Value::Object).unwrap_or_else(|| json!({})); 
+    if !sp.status {
+        return Err(AppError::SpExecution { message: sp.message, data: Some(result_data) });
+    }
+
+    // Delete temporary chunks
+    // let _ = tokio::fs::remove_dir_all(chunk_dir).await;
+
+    Ok((StatusCode::OK, Json(ApiResponse::success(result_data, &sp.message))).into_response())
+}
 
-            let result_data = data.unwrap_or_else(|| json!({})); 
-            (StatusCode::OK, Json(ApiResponse::success(result_data, &msg))).into_response()
+pub struct OllamaProvider {
+    pool: Arc<OllamaPool>,
+    defaults: ModelDefaults,
+}
+
+// One generation and what could be read out of its answer
+struct Generation {
+    model: String,
+    response: String,
+    instance: String,
+    data: Option<Value>,
+    validation: Option<SchemaValidation>,
+    repairs: Vec<JsonRepair>,
+    info: GenerationInfo,
+}
+
+impl OllamaProvider {
+    pub fn new(pool: Arc<OllamaPool>, defaults: ModelDefaults) -> Self {
+        Self { pool, defaults }
+    }
+
+    // Reads `image`, or answers from the prompt alone without one (text models)
+    pub async fn run(&self, image: Option<&Bytes>, options: &OcrOptions) -> Result<OcrResult, AppError> {
+        // Base64 encoding is required for Ollama's vision API
+        let b64_image = image.map(|image| general_purpose::STANDARD.encode(image));
+        if let Some(mode) = &options.voting {
+            return self.vote(b64_image.as_deref(), mode, options).await;
         }
-        Ok((false, msg, data)) => {
-            let result_data = data.unwrap_or_else(|| json!({})); 
-            (StatusCode::BAD_REQUEST, Json(ApiResponse::error(msg, AppStatusCode::SpExecutionFailed, Some(result_data)))).into_response()
+
+        let model = options.model.clone()
+            .ok_or_else(|| AppError::InvalidPayload("Invalid or missing model_name".to_string()))?;
+        let res = self.generate(b64_image.as_deref(), &model, None, options).await?;
+
+        Ok(OcrResult {
+            provider: self.name().to_string(),
+            model,
+            raw: json!({ "response": &res.response, "instance": res.instance }),
+            document: OcrDocument::from_text(&res.response, res.data.as_ref()),
+            text: res.response,
+            data: res.data,
+            cache: None,
+            preprocess: None,
+            validation: res.validation,
+            repairs: res.repairs,
+            prompt: None,
+            generation: Some(res.info),
+            voting: None,
+            hybrid: None,
+        })
+    }
+
+    // `seed` replaces the effective seed, for voting runs
+    async fn generate(&self, image_b64: Option<&str>, model: &str, seed: Option<i32>, options: &OcrOptions) -> Result<Generation, AppError> {
+        // `?prompt=` or the template picked by the prompt registry
+        let prompt = options.prompt()
+            .ok_or_else(|| AppError::InvalidPayload("Missing prompt or prompt_name".to_string()))?;
+        let mut request = GenerationRequest::new(model.to_string(), prompt);
+        if let Some(image_b64) = image_b64 {
+            request = request.add_image(Image::from_base64(image_b64));
         }
-        Err(e) => {
-            (StatusCode::INTERNAL_SERVER_ERROR, Json(ApiResponse::<Value>::error(e.to_string(), AppStatusCode::SpKnownFailed, None))).into_response()
+        // Structured outputs: Ollama constrains the answer to the schema, no fences to scrape
+        if let Some(counter) = &options.counter {
+            let schema = schemars::Schema::try_from(counter.schema.clone())
+                .map_err(|e| AppError::Internal(format!("Invalid counter schema: {}", e)))?;
+            request = request.format(FormatType::StructuredJson(Box::new(JsonStructure::new_for_schema(schema))));
         }
-    }
-}
 
-async fn execute_sp_dynamic(
-    client: &mut bb8::PooledConnection<'_, bb8_tiberius::ConnectionManager>,
-    sp_name: &str,
-    params: &[(&str, SqlParam)], 
-) -> Result<(bool, String, Option<Value>), String> {
-
-    let param_bindings = params.iter()
-    .map(|(name, value)| {
-        let formatted_value = match value {
-            SqlParam::String(v) => format!("'{}'", v), // Wrap strings in single quotes
-            // SqlParam::I32(v) => v.to_string(),
-            SqlParam::I64(v) => v.to_string(),
-            // SqlParam::F32(v) => v.to_string(),
-            // SqlParam::F64(v) => v.to_string(),
-            // SqlParam::Bool(v) => if *v { "1".to_string() } else { "0".to_string() },
-            // SqlParam::Null => "NULL".to_string(),
-            // SqlParam::DateTime(v) => format!("'{}'", v.format("%Y-%m-%d %H:%M:%S")),
-            // SqlParam::DateTime(v) => format!("'{}'", v.format("%Y-%m-%d %H:%M:%S.%f")),
-        };
-        format!("@{} = {}", name, formatted_value)
-    })
-    .collect::<Vec<_>>()
-    .join(", ");
+        // Request options over the model defaults from config, echoed back with the result
+        let mut generation = self.defaults.effective(model, &options.generation);
+        if seed.is_some() {
+            generation.seed = seed;
+        }
+        request = generation.apply(request);
 
+        let (res, instance) = self.pool.generate(request).await?;
+        let info = GenerationInfo::new(generation, &res);
 
+        // Models still wrap, comment or truncate their JSON; take what can be recovered
+        let (data, repairs) = match json_repair::recover(&res.response) {
+            Some(recovered) => (Some(recovered.value), recovered.repairs),
+            None => (None, Vec::new()),
+        };
+        let (data, validation) = match &options.counter {
+            Some(counter) => {
+                let (data, validation) = counter.finish(data);
+                (data, Some(validation))
+            }
+            None => (data, None),
+        };
 
-    // println!("param binding:{}" ,param_bindings);
-    // 2. Add semicolons and ensure 'EXEC' isn't jammed against the DECLARE
-    let sql = format!(
-        "DECLARE @status BIT, @msg VARCHAR(1000); \
-        EXEC {} {}, @retStatus = @status OUTPUT, @retMessage = @msg OUTPUT; \
-        SELECT @status AS retStatus, @msg AS retMessage;",
-        sp_name, param_bindings
-    );
+        Ok(Generation { model: model.to_string(), response: res.response, instance, data, validation, repairs, info })
+    }
 
+    // Runs the image several times and keeps the majority value of every field
+    async fn vote(&self, image_b64: Option<&str>, mode: &VoteMode, options: &OcrOptions) -> Result<OcrResult, AppError> {
+        // 1. Plan the runs: one model with successive seeds, or one run per model
+        let runs: Vec<(String, Option<i32>)> = match mode {
+            VoteMode::Seeds(n) => {
+                let model = options.model.clone()
+                    .ok_or_else(|| AppError::InvalidPayload("Invalid or missing model_name".to_string()))?;
+                let base = self.defaults.effective(&model, &options.generation).seed.unwrap_or(0);
+                (0..*n).map(|i| (model.clone(), Some(base.wrapping_add(i as i32)))).collect()
+            }
+            VoteMode::Models(models) => models.iter().map(|m| (m.clone(), None)).collect(),
+        };
 
-    let mut query = SqlQuery::new(sql);
-    
-    // 3. Bind the Rust values to the @P placeholder markers
-    for (_, value) in params.iter() {
-        value.bind_to_query(&mut query);
-    }
-    
-    // let mut stream = query.query(client).await.map_err(|e| e.to_string())?;
-    let mut stream = match query.query(client).await {
-        Ok(s) => s,
-        Err(e) => {
-            return Ok((false, e.to_string(), None));
+        // 2. Generate concurrently, the pool spreads the runs over the instances
+        let outcomes = join_all(runs.iter().map(|(model, seed)| self.generate(image_b64, model, *seed, options))).await;
+        if outcomes.iter().all(Result::is_err) {
+            return outcomes.into_iter().find_map(Result::err).map(Err).expect("at least two runs");
         }
-    };
-    
-    // let mut data_json = None;
-    // let mut status = false;
-    // let mut msg = "Unknown".to_string();
-
-    let mut data_json: Option<Value> = None;
-    let mut status = false;
-    let mut msg = String::new();
-
-    loop {
-    match stream.try_next().await {
-        Ok(Some(item)) => {
-            match item {
-                QueryItem::Row(row) => {
-                    // Check if the FIRST column is named "retStatus"
-                    let first_col_name = row.columns().get(0).map(|c| c.name()).unwrap_or("");
-
-                    if first_col_name == "retStatus" {
-                        // This is your standard footer (Result Index 0 OR 1)
-                        status = row.get::<bool, _>(0).unwrap_or(false);
-                        msg = row.get::<&str, _>(1).unwrap_or("Unknown").to_string();
-                    } else {
-                        // This is dynamic data from inside the SP
-                        let mut map = Map::new();
-                        for (i, column) in row.columns().iter().enumerate() {
-                            let name = column.name().to_string();
-                            let val = match row.try_get::<&str, _>(i) {
-                                Ok(Some(s)) => json!(s),
-                                _ => match row.try_get::<i32, _>(i) {
-                                    Ok(Some(n)) => json!(n),
-                                    _ => match row.try_get::<i64, _>(i) { // Added i64 for BigInt support
-                                        Ok(Some(n)) => json!(n),
-                                        _ => Value::Null,
-                                    },
-                                },
-                            };
-                            map.insert(name, val);
-                        }
-                        data_json = Some(Value::Object(map));
-                    }
-                }
-                QueryItem::Metadata(_) => continue,
+
+        // 3. Vote field by field, failed runs count as no answer
+        let answers: Vec<Option<Value>> = outcomes.iter().map(|o| o.as_ref().ok().and_then(|g| g.data.clone())).collect();
+        let consensus = voting::vote(&answers);
+        let (data, validation) = match &options.counter {
+            Some(counter) => {
+                let (data, validation) = counter.finish(consensus.data.clone());
+                (data, Some(validation))
             }
-        }
-        Ok(None) => break, 
-        Err(e) => return Ok((false, e.to_string(), None)),
-        }
+            None => (consensus.data.clone(), None),
+        };
+
+        // 4. The text comes from the run closest to the majority
+        let best = outcomes.iter().enumerate()
+            .filter_map(|(i, o)| o.as_ref().ok().map(|g| (i, g)))
+            // The earliest run on ties
+            .max_by_key(|(i, g)| (consensus.matches(g.data.as_ref()), std::cmp::Reverse(*i)))
+            .map(|(_, g)| g)
+            .expect("one run succeeded");
+        let mut repairs: Vec<JsonRepair> = outcomes.iter().filter_map(|o| o.as_ref().ok()).flat_map(|g| g.repairs.iter().copied()).collect();
+        repairs.sort();
+        repairs.dedup();
+
+        let vote_runs: Vec<VoteRun> = runs.iter().zip(&outcomes)
+            .map(|((model, seed), outcome)| match outcome {
+                Ok(g) => VoteRun {
+                    model: model.clone(),
+                    seed: g.info.options.seed,
+                    instance: Some(g.instance.clone()),
+                    valid: g.validation.as_ref().map(|v| v.valid),
+                    generation: Some(g.info.clone()),
+                    error: None,
+                },
+                Err(e) => VoteRun { model: model.clone(), seed: *seed, instance: None, valid: None, generation: None, error: Some(e.to_string()) },
+            })
+            .collect();
+        let report = VoteReport {
+            mode: mode.clone(),
+            runs: vote_runs,
+            agreement: consensus.agreement(),
+            flagged: consensus.flagged(),
+            fields: consensus.fields,
+        };
+        let answers: Vec<Value> = outcomes.iter().map(|o| match o {
+            Ok(g) => json!({ "model": g.model, "instance": g.instance, "response": g.response }),
+            Err(e) => json!({ "error": e.to_string() }),
+        }).collect();
+
+        Ok(OcrResult {
+            provider: self.name().to_string(),
+            model: match mode {
+                VoteMode::Seeds(_) => best.model.clone(),
+                VoteMode::Models(models) => models.join(","),
+            },
+            raw: json!({ "runs": answers, "voting": &report }),
+            document: OcrDocument::from_text(&best.response, data.as_ref()),
+            text: best.response.clone(),
+            data,
+            cache: None,
+            preprocess: None,
+            validation,
+            repairs,
+            prompt: None,
+            generation: Some(best.info.clone()),
+            voting: Some(report),
+            hybrid: None,
+        })
     }
+}
 
-    // Return the values collected during the loop
-    Ok((status, msg, data_json))
+#[async_trait]
+impl OcrProvider for OllamaProvider {
+    fn name(&self) -> &'static str {
+        "ollama"
+    }
 
-    // Ok((status, msg, if status { data_json } else { None }))
+    fn takes_prompt(&self) -> bool {
+        true
+    }
+
+    async fn analyze(&self, image: Bytes, options: &OcrOptions) -> Result<OcrResult, AppError> {
+        self.run(Some(&image), options).await
+    }
 }
 
+// Result of the legacy /ocr/deepseek-ocr endpoint, field names kept for existing clients
+#[derive(Serialize)]
+pub struct DeepseekOcrResult {
+    pub counter_name: String,
+    pub ocr_data_json: Value,
+    pub ocr_data: String,
+    pub ocr_data_json_text: String,
+    pub document: OcrDocument,
+    // Field-level schema errors in ocr_data_json
+    pub validation: Option<SchemaValidation>,
+    // Syntax fixes applied to the model answer to read ocr_data_json
+    pub json_repairs: Vec<JsonRepair>,
+    // Per-field agreement when several runs were merged (`?votes=` / `?vote_models=`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub voting: Option<VoteReport>,
+}
 
 pub async fn deepseek_ocr(
     State(state): State<AppState>, 
+    Extension(request): Extension<RequestInfo>,
     Query(params): Query<HashMap<String, String>>,
+    headers: HeaderMap,
     body: Bytes,
-) -> impl IntoResponse {
+) -> Result<Response, AppError> {
     // 1. Validation Logic
-    let counter_name = match params.get("counter_name") {
-        Some(v) if v == "ATM" => v,
-        _ => return (StatusCode::BAD_REQUEST, Json(json!({"error": "Invalid or missing counter_name"}))).into_response(),
+    let provider = state.providers.get("ollama")
+        .ok_or_else(|| AppError::ProviderUnavailable("Ollama is not configured".to_string()))?;
+    let options = OcrOptions::from_query(&params, provider.as_ref(), &state.counters, &state.prompts)?;
+    let Some(counter) = &options.counter else {
+        return Err(AppError::InvalidPayload("Invalid or missing counter_name".to_string()));
     };
-    let model = match params.get("model_name") {
-        Some(v) if !v.trim().is_empty() => v,
-        _ => return (StatusCode::BAD_REQUEST, Json(json!({"error": "Invalid or missing model_name"}))).into_response(),
-    };
-
-    // 2. Setup Ollama (Assuming default localhost:11434)
-    let ollama = state.ollama;
-    // let model = "deepseek-ocr"; // Ensure this matches your downloaded model name
-    //let model = "qwen2.5vl:3b-q4_K_M"; // Ensure this matches your downloaded model name
-
-    // 3. Prepare Image (Base64 encoding is required for Ollama's vision API)
-    let b64_image = general_purpose::STANDARD.encode(&body);
-
-    // Wrap the Base64 string in the Image struct expected by the SDK
-    let image_obj = Image::from_base64(&b64_image);
-
-    // 4. Construct Prompt
-    // let prompt = "Extract all text from this image and format it as Markdown.";
-    let prompt = "extract image text and convert into json.";
-    let request = GenerationRequest::new(model.to_string(), prompt.to_string())
-    .add_image(image_obj); // Now passing the correct Type
-
-    // 5. Execute OCR
-    match ollama.generate(request).await {
-        Ok(res) => {
-            let ocr_text = res.response;
-
-            // 1. Clean the string to extract only the JSON part
-            let json_text = ocr_text
-                .split("```json")
-                .nth(1)
-                .and_then(|c| c.split("```").next())
-                .ok_or("No JSON found").unwrap()
-                .replace('\n', "") // Remove all newlines
-                .replace('\r', "")
-                .trim()
-                .to_string();
-            
-            let json_object: Value = serde_json::from_str(&json_text).unwrap_or(json!({"error": "parse_failed"}));
-
-            if let Some(bank) = json_object.get("bank") {
-                println!("Extracted Bank: {}", bank);
-            }
-
-            (StatusCode::OK, Json(json!({
-                "counter_name": counter_name,
-                "ocr_data_json": json_object, 
-                "ocr_data": ocr_text, 
-                "ocr_data_json_text": json_text, 
-                "status": "success"
-            }))).into_response()
-        }
-        Err(e) => {
-            (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({
-                "error": format!("Ollama error: {}", e)
-            }))).into_response()
-        }
+    let counter_name = counter.name.clone();
+    // `?vote_models=` names the models itself
+    if options.model.is_none() && !matches!(options.voting, Some(VoteMode::Models(_))) {
+        return Err(AppError::InvalidPayload("Invalid or missing model_name".to_string()));
     }
 
+    // 2. Execute OCR through the shared provider
+    let ctx = RequestContext::from_request(&headers, &params);
+    let res = match state.pipeline.analyze(provider.as_ref(), body, &options, &ctx).await? {
+        AnalyzeOutput::Single(res) => *res,
+        // PDF/TIFF: per-page results as on /ocr/analyze
+        pages => return Ok(analyze_response(&request, provider.name(), pages)),
+    };
 
-    // match ollama.generate(request).await {
-    // Ok(res) => {
-    //     // 1. Clean the string (remove markdown block if the model adds it)
-    //     let cleaned_text = res.response
-    //         .replace("```json", "")
-    //         .replace("```", "")
-    //         .trim()
-    //         .to_string();
-
-    //     // 2. Parse the string into a Value to ensure it's valid JSON
-    //     let ocr_json: Value = serde_json::from_str(&cleaned_text)
-    //         .unwrap_or_else(|_| json!({ "error": "Failed to parse OCR result", "raw": cleaned_text }));
-
-    //     // 3. Return as a proper nested JSON object
-    //     (StatusCode::OK, Json(json!({
-    //         "counter_name": counter_name,
-    //         "ocr_data": ocr_json, 
-    //         "status": "success"
-    //     }))).into_response()
-    // }
-    // Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({"error": e.to_string()}))).into_response(),
-    // }
-
+    // Null when nothing could be recovered from the model answer
+    let json_object = res.data.unwrap_or(Value::Null);
+    let json_text = if json_object.is_null() { String::new() } else { json_object.to_string() };
+
+    let meta = request.meta(provider.name(), Some(res.model.clone())).with_generation(res.generation);
+    let result = DeepseekOcrResult {
+        counter_name,
+        ocr_data_json: json_object,
+        ocr_data: res.text,
+        ocr_data_json_text: json_text,
+        document: res.document,
+        validation: res.validation,
+        json_repairs: res.repairs,
+        voting: res.voting,
+    };
+    let message = match &result.validation {
+        Some(v) if !v.valid => "OCR completed with validation errors",
+        _ if result.voting.as_ref().is_some_and(|v| !v.flagged.is_empty()) => "OCR completed, runs disagree on some fields",
+        _ => "OCR completed",
+    };
+    Ok((StatusCode::OK, Json(ApiResponse::success(result, message).with_meta(meta))).into_response())
 }
@@ -0,0 +1,436 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+// Provider-neutral document model: pages -> blocks -> lines -> words, plus key/value fields.
+// Coordinates are kept in the unit reported by the backend (`pixel` for images, `inch` for DI PDFs).
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct OcrDocument {
+    pub pages: Vec<Page>,
+    pub fields: Vec<DocumentField>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct Page {
+    pub number: u32,
+    pub width: Option<f64>,
+    pub height: Option<f64>,
+    pub unit: Option<String>,
+    pub blocks: Vec<Block>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct Block {
+    pub lines: Vec<Line>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct Line {
+    pub text: String,
+    pub polygon: Vec<Point>,
+    pub confidence: Option<f64>,
+    pub words: Vec<Word>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct Word {
+    pub text: String,
+    pub polygon: Vec<Point>,
+    pub confidence: Option<f64>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct Point {
+    pub x: f64,
+    pub y: f64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct DocumentField {
+    pub key: String,
+    #[serde(rename = "type")]
+    pub field_type: String,
+    pub value: Value,
+    pub content: Option<String>,
+    pub confidence: Option<f64>,
+}
+
+impl OcrDocument {
+    pub fn text(&self) -> String {
+        self.pages.iter()
+            .flat_map(|p| p.blocks.iter())
+            .flat_map(|b| b.lines.iter())
+            .map(|l| l.text.as_str())
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    // Image Analysis 4.0 `features=read` response
+    pub fn from_azure_read(result: &Value) -> Self {
+        let blocks = result["readResult"]["blocks"].as_array()
+            .into_iter()
+            .flatten()
+            .map(|block| Block {
+                lines: block["lines"].as_array()
+                    .into_iter()
+                    .flatten()
+                    .map(|line| Line {
+                        text: str_of(&line["text"]),
+                        polygon: point_objects(&line["boundingPolygon"]),
+                        confidence: None,
+                        words: line["words"].as_array()
+                            .into_iter()
+                            .flatten()
+                            .map(|word| Word {
+                                text: str_of(&word["text"]),
+                                polygon: point_objects(&word["boundingPolygon"]),
+                                confidence: word["confidence"].as_f64(),
+                            })
+                            .collect(),
+                    })
+                    .collect(),
+            })
+            .collect();
+
+        Self {
+            pages: vec![Page {
+                number: 1,
+                width: result["metadata"]["width"].as_f64(),
+                height: result["metadata"]["height"].as_f64(),
+                unit: Some("pixel".to_string()),
+                blocks,
+            }],
+            fields: Vec::new(),
+        }
+    }
+
+    // Document Intelligence `analyzeResult` (pages, paragraphs and the first document's fields)
+    pub fn from_document_intelligence(analyze_result: &Value) -> Self {
+        let paragraphs = analyze_result["paragraphs"].as_array().cloned().unwrap_or_default();
+
+        let pages = analyze_result["pages"].as_array()
+            .into_iter()
+            .flatten()
+            .map(|page| {
+                let number = page["pageNumber"].as_u64().unwrap_or(1) as u32;
+                let words: Vec<(usize, Word)> = page["words"].as_array()
+                    .into_iter()
+                    .flatten()
+                    .map(|word| (
+                        word["span"]["offset"].as_u64().unwrap_or(0) as usize,
+                        Word {
+                            text: str_of(&word["content"]),
+                            polygon: point_pairs(&word["polygon"]),
+                            confidence: word["confidence"].as_f64(),
+                        },
+                    ))
+                    .collect();
+
+                // Words belong to the line whose span covers their offset
+                let lines: Vec<((usize, usize), Line)> = page["lines"].as_array()
+                    .into_iter()
+                    .flatten()
+                    .map(|line| {
+                        let range = span_range(&line["spans"]);
+                        let words = words.iter()
+                            .filter(|(offset, _)| *offset >= range.0 && *offset < range.1)
+                            .map(|(_, w)| w.clone())
+                            .collect();
+                        (range, Line {
+                            text: str_of(&line["content"]),
+                            polygon: point_pairs(&line["polygon"]),
+                            confidence: None,
+                            words,
+                        })
+                    })
+                    .collect();
+
+                Page {
+                    number,
+                    width: page["width"].as_f64(),
+                    height: page["height"].as_f64(),
+                    unit: page["unit"].as_str().map(|u| u.to_string()),
+                    blocks: group_into_blocks(lines, &paragraphs, number),
+                }
+            })
+            .collect();
+
+        let fields = analyze_result["documents"][0]["fields"].as_object()
+            .map(|fields| fields.iter().map(|(key, field)| di_field(key, field)).collect())
+            .unwrap_or_default();
+
+        Self { pages, fields }
+    }
+
+    // Free text from generative backends: one page, blank-line separated blocks, no geometry.
+    // Structured answers (if any) are flattened into dotted-path fields.
+    pub fn from_text(text: &str, data: Option<&Value>) -> Self {
+        let mut blocks = Vec::new();
+        let mut current = Block::default();
+
+        for raw_line in text.lines() {
+            let line = raw_line.trim();
+            if line.is_empty() {
+                if !current.lines.is_empty() {
+                    blocks.push(std::mem::take(&mut current));
+                }
+                continue;
+            }
+            current.lines.push(Line {
+                text: line.to_string(),
+                words: line.split_whitespace()
+                    .map(|w| Word { text: w.to_string(), ..Default::default() })
+                    .collect(),
+                ..Default::default()
+            });
+        }
+        if !current.lines.is_empty() {
+            blocks.push(current);
+        }
+
+        let mut fields = Vec::new();
+        if let Some(data) = data {
+            flatten_fields("", data, &mut fields);
+        }
+
+        Self {
+            pages: vec![Page { number: 1, blocks, ..Default::default() }],
+            fields,
+        }
+    }
+}
+
+fn str_of(value: &Value) -> String {
+    value.as_str().unwrap_or("").to_string()
+}
+
+// [{ "x": 1, "y": 2 }, ...]
+fn point_objects(value: &Value) -> Vec<Point> {
+    value.as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|p| Some(Point { x: p["x"].as_f64()?, y: p["y"].as_f64()? }))
+        .collect()
+}
+
+// [x1, y1, x2, y2, ...]
+fn point_pairs(value: &Value) -> Vec<Point> {
+    let coords: Vec<f64> = value.as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|v| v.as_f64())
+        .collect();
+    coords.chunks_exact(2).map(|c| Point { x: c[0], y: c[1] }).collect()
+}
+
+// [start, end) covered by a list of DI spans
+fn span_range(spans: &Value) -> (usize, usize) {
+    let ranges: Vec<(usize, usize)> = spans.as_array()
+        .into_iter()
+        .flatten()
+        .map(|s| {
+            let offset = s["offset"].as_u64().unwrap_or(0) as usize;
+            (offset, offset + s["length"].as_u64().unwrap_or(0) as usize)
+        })
+        .collect();
+    let start = ranges.iter().map(|r| r.0).min().unwrap_or(0);
+    let end = ranges.iter().map(|r| r.1).max().unwrap_or(0);
+    (start, end)
+}
+
+// Lines are grouped by the DI paragraph that contains them; leftovers form a trailing block
+fn group_into_blocks(lines: Vec<((usize, usize), Line)>, paragraphs: &[Value], page_number: u32) -> Vec<Block> {
+    let mut blocks = Vec::new();
+    let mut assigned = vec![false; lines.len()];
+
+    for paragraph in paragraphs {
+        let on_page = paragraph["boundingRegions"].as_array()
+            .into_iter()
+            .flatten()
+            .any(|r| r["pageNumber"].as_u64() == Some(page_number as u64));
+        if !on_page {
+            continue;
+        }
+
+        let (start, end) = span_range(&paragraph["spans"]);
+        let mut block = Block::default();
+        for (i, ((line_start, line_end), line)) in lines.iter().enumerate() {
+            if !assigned[i] && *line_start >= start && *line_end <= end {
+                assigned[i] = true;
+                block.lines.push(line.clone());
+            }
+        }
+        if !block.lines.is_empty() {
+            blocks.push(block);
+        }
+    }
+
+    let leftovers: Vec<Line> = lines.into_iter()
+        .zip(assigned)
+        .filter(|(_, used)| !used)
+        .map(|((_, line), _)| line)
+        .collect();
+    if !leftovers.is_empty() {
+        blocks.push(Block { lines: leftovers });
+    }
+
+    blocks
+}
+
+fn di_field(key: &str, field: &Value) -> DocumentField {
+    DocumentField {
+        key: key.to_string(),
+        field_type: field["type"].as_str().unwrap_or("string").to_string(),
+        value: di_value(field),
+        content: field["content"].as_str().map(|c| c.to_string()),
+        confidence: field["confidence"].as_f64(),
+    }
+}
+
+// DI stores the typed value under `value<Type>` (valueString, valueCurrency, valueArray, ...)
+fn di_value(field: &Value) -> Value {
+    let field_type = field["type"].as_str().unwrap_or("string");
+    match field_type {
+        "array" => Value::Array(
+            field["valueArray"].as_array()
+                .into_iter()
+                .flatten()
+                .map(di_value)
+                .collect(),
+        ),
+        "object" => Value::Object(
+            field["valueObject"].as_object()
+                .into_iter()
+                .flatten()
+                .map(|(k, v)| (k.clone(), di_value(v)))
+                .collect::<Map<_, _>>(),
+        ),
+        _ => {
+            let mut chars = field_type.chars();
+            let key = match chars.next() {
+                Some(first) => format!("value{}{}", first.to_ascii_uppercase(), chars.as_str()),
+                None => "valueString".to_string(),
+            };
+            match field.get(&key) {
+                Some(v) => v.clone(),
+                None => field.get("content").cloned().unwrap_or(Value::Null),
+            }
+        }
+    }
+}
+
+fn flatten_fields(prefix: &str, value: &Value, out: &mut Vec<DocumentField>) {
+    match value {
+        Value::Object(map) => {
+            for (k, v) in map {
+                let key = if prefix.is_empty() { k.clone() } else { format!("{}.{}", prefix, k) };
+                flatten_fields(&key, v, out);
+            }
+        }
+        Value::Array(items) => {
+            for (i, v) in items.iter().enumerate() {
+                flatten_fields(&format!("{}[{}]", prefix, i), v, out);
+            }
+        }
+        leaf => out.push(DocumentField {
+            key: prefix.to_string(),
+            field_type: match leaf {
+                Value::Number(_) => "number",
+                Value::Bool(_) => "boolean",
+                Value::Null => "null",
+                _ => "string",
+            }.to_string(),
+            value: leaf.clone(),
+            content: None,
+            confidence: None,
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn line_texts(page: &Page) -> Vec<Vec<&str>> {
+        page.blocks.iter().map(|b| b.lines.iter().map(|l| l.text.as_str()).collect()).collect()
+    }
+
+    #[test]
+    fn azure_read_keeps_blocks_lines_and_words() {
+        let result = json!({
+            "metadata": { "width": 800, "height": 600 },
+            "readResult": { "blocks": [{ "lines": [{
+                "text": "TOTAL 500",
+                "boundingPolygon": [{ "x": 1, "y": 2 }, { "x": 3, "y": 4 }],
+                "words": [
+                    { "text": "TOTAL", "boundingPolygon": [{ "x": 1, "y": 2 }], "confidence": 0.99 },
+                    { "text": "500", "boundingPolygon": [], "confidence": 0.5 }
+                ]
+            }] }] }
+        });
+        let document = OcrDocument::from_azure_read(&result);
+
+        let page = &document.pages[0];
+        assert_eq!((page.width, page.height, page.unit.as_deref()), (Some(800.0), Some(600.0), Some("pixel")));
+        let line = &page.blocks[0].lines[0];
+        assert_eq!(line.polygon, [Point { x: 1.0, y: 2.0 }, Point { x: 3.0, y: 4.0 }]);
+        assert_eq!(line.words.iter().map(|w| (w.text.as_str(), w.confidence)).collect::<Vec<_>>(), [("TOTAL", Some(0.99)), ("500", Some(0.5))]);
+        assert_eq!(document.text(), "TOTAL 500");
+    }
+
+    #[test]
+    fn document_intelligence_groups_words_into_lines_and_lines_into_paragraphs() {
+        // Content: "HDFC BANK\nATM 42\nTOTAL 500"
+        let result = json!({
+            "pages": [{
+                "pageNumber": 1, "width": 8.5, "height": 11, "unit": "inch",
+                "words": [
+                    { "content": "HDFC", "span": { "offset": 0, "length": 4 }, "polygon": [0, 0, 1, 0], "confidence": 0.9 },
+                    { "content": "BANK", "span": { "offset": 5, "length": 4 }, "polygon": [], "confidence": 0.8 },
+                    { "content": "ATM", "span": { "offset": 10, "length": 3 }, "polygon": [] },
+                    { "content": "42", "span": { "offset": 14, "length": 2 }, "polygon": [] },
+                    { "content": "TOTAL", "span": { "offset": 17, "length": 5 }, "polygon": [] },
+                    { "content": "500", "span": { "offset": 23, "length": 3 }, "polygon": [] }
+                ],
+                "lines": [
+                    { "content": "HDFC BANK", "spans": [{ "offset": 0, "length": 9 }], "polygon": [0, 0, 2, 0, 2, 1, 0, 1] },
+                    { "content": "ATM 42", "spans": [{ "offset": 10, "length": 6 }], "polygon": [] },
+                    { "content": "TOTAL 500", "spans": [{ "offset": 17, "length": 9 }], "polygon": [] }
+                ]
+            }],
+            "paragraphs": [
+                { "spans": [{ "offset": 0, "length": 16 }], "boundingRegions": [{ "pageNumber": 1 }] },
+                { "spans": [{ "offset": 17, "length": 9 }], "boundingRegions": [{ "pageNumber": 2 }] }
+            ],
+            "documents": [{ "fields": {
+                "Total": { "type": "currency", "valueCurrency": { "amount": 500 }, "content": "500", "confidence": 0.7 },
+                "Items": { "type": "array", "valueArray": [{ "type": "object", "valueObject": { "Name": { "type": "string", "content": "Cassette 1" } } }] }
+            } }]
+        });
+        let document = OcrDocument::from_document_intelligence(&result);
+
+        let page = &document.pages[0];
+        assert_eq!(page.unit.as_deref(), Some("inch"));
+        // The second paragraph is on another page, its line ends up in the trailing block
+        assert_eq!(line_texts(page), [vec!["HDFC BANK", "ATM 42"], vec!["TOTAL 500"]]);
+        let first = &page.blocks[0].lines[0];
+        assert_eq!(first.words.iter().map(|w| w.text.as_str()).collect::<Vec<_>>(), ["HDFC", "BANK"]);
+        assert_eq!(first.polygon.len(), 4);
+
+        let total = document.fields.iter().find(|f| f.key == "Total").unwrap();
+        assert_eq!((total.field_type.as_str(), &total.value, total.confidence), ("currency", &json!({ "amount": 500 }), Some(0.7)));
+        let items = document.fields.iter().find(|f| f.key == "Items").unwrap();
+        assert_eq!(items.value, json!([{ "Name": "Cassette 1" }]));
+    }
+
+    #[test]
+    fn text_is_split_into_blocks_and_data_flattened_into_fields() {
+        let document = OcrDocument::from_text("HDFC BANK\n  ATM 42 \n\n\nTOTAL 500\n", Some(&json!({ "bank": "HDFC", "cassettes": [{ "count": 3 }], "time": null })));
+
+        let page = &document.pages[0];
+        assert_eq!(line_texts(page), [vec!["HDFC BANK", "ATM 42"], vec!["TOTAL 500"]]);
+        assert_eq!(page.blocks[0].lines[1].words.len(), 2);
+        let fields: Vec<(&str, &str, &Value)> = document.fields.iter().map(|f| (f.key.as_str(), f.field_type.as_str(), &f.value)).collect();
+        assert_eq!(fields, [("bank", "string", &json!("HDFC")), ("cassettes[0].count", "number", &json!(3)), ("time", "null", &Value::Null)]);
+    }
+}
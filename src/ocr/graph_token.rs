@@ -0,0 +1,198 @@
+use std::time::{Duration, Instant};
+use tokio::sync::{Mutex, RwLock};
+
+use crate::{error::AppError, model::TokenResponse};
+
+pub const DEFAULT_AUTHORITY: &str = "https://login.microsoftonline.com";
+const GRAPH_SCOPE: &str = "https://graph.microsoft.com/.default";
+// Refresh this long before expiry so a token never dies mid-request
+const REFRESH_MARGIN: Duration = Duration::from_secs(300);
+// Used when the identity platform omits expires_in
+const DEFAULT_EXPIRES_IN: u64 = 3599;
+
+struct CachedToken {
+    access_token: String,
+    refresh_at: Instant,
+}
+
+// Client-credentials token for Microsoft Graph, fetched on first use and refreshed before it expires
+pub struct GraphTokenManager {
+    client: reqwest::Client,
+    token_url: String,
+    client_id: String,
+    client_secret: String,
+    cached: RwLock<Option<CachedToken>>,
+    // Held while fetching, so concurrent callers wait for one request instead of sending their own
+    refresh: Mutex<()>,
+}
+
+impl GraphTokenManager {
+    // `authority` is the identity platform base URL, a local mock in tests
+    pub fn new(client: reqwest::Client, authority: &str, tenant_id: &str, client_id: String, client_secret: String) -> Self {
+        Self {
+            client,
+            token_url: format!("{}/{}/oauth2/v2.0/token", authority.trim_end_matches('/'), tenant_id),
+            client_id,
+            client_secret,
+            cached: RwLock::new(None),
+            refresh: Mutex::new(()),
+        }
+    }
+
+    pub async fn token(&self) -> Result<String, AppError> {
+        if let Some(token) = self.valid_token().await {
+            return Ok(token);
+        }
+
+        let _guard = self.refresh.lock().await;
+        // Another caller may have refreshed while we waited for the lock
+        if let Some(token) = self.valid_token().await {
+            return Ok(token);
+        }
+        self.fetch().await
+    }
+
+    // Drops a token Graph refused with 401, unless it was already replaced
+    pub async fn invalidate(&self, rejected: &str) {
+        let mut cached = self.cached.write().await;
+        if cached.as_ref().is_some_and(|c| c.access_token == rejected) {
+            *cached = None;
+        }
+    }
+
+    async fn valid_token(&self) -> Option<String> {
+        self.cached.read().await
+            .as_ref()
+            .filter(|c| Instant::now() < c.refresh_at)
+            .map(|c| c.access_token.clone())
+    }
+
+    async fn fetch(&self) -> Result<String, AppError> {
+        let params = [
+            ("client_id", self.client_id.as_str()),
+            ("client_secret", self.client_secret.as_str()),
+            ("grant_type", "client_credentials"),
+            ("scope", GRAPH_SCOPE),
+        ];
+
+        let response = self.client.post(&self.token_url)
+            .form(&params)
+            .send()
+            .await
+            .map_err(|e| AppError::request("Token request failed", e))?;
+        if !response.status().is_success() {
+            let status = response.status();
+            let body = response.text().await.unwrap_or_default();
+            // Refused credentials are a server configuration problem, never the caller's
+            return Err(AppError::Provider(format!("Token request failed with status {}: {}", status, body)));
+        }
+
+        let token: TokenResponse = response.json().await
+            .map_err(|e| AppError::request("Invalid token response", e))?;
+        let lifetime = Duration::from_secs(token.expires_in.unwrap_or(DEFAULT_EXPIRES_IN));
+        // Short-lived tokens (tests, mocks) are refreshed halfway instead
+        let refresh_at = Instant::now() + lifetime - REFRESH_MARGIN.min(lifetime / 2);
+
+        *self.cached.write().await = Some(CachedToken { access_token: token.access_token.clone(), refresh_at });
+        Ok(token.access_token)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{collections::HashMap, sync::{Arc, atomic::{AtomicUsize, Ordering}}};
+    use axum::{Form, Json, Router, extract::State, http::StatusCode, response::{IntoResponse, Response}, routing::post};
+    use serde_json::json;
+
+    #[derive(Clone)]
+    struct MockIdentity {
+        hits: Arc<AtomicUsize>,
+        expires_in: u64,
+        status: StatusCode,
+    }
+
+    async fn issue_token(State(mock): State<MockIdentity>, Form(form): Form<HashMap<String, String>>) -> Response {
+        assert_eq!(form.get("grant_type").map(String::as_str), Some("client_credentials"));
+        assert_eq!(form.get("client_id").map(String::as_str), Some("client"));
+        let n = mock.hits.fetch_add(1, Ordering::SeqCst) + 1;
+        // Slow enough for concurrent callers to pile up behind the first request
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        if !mock.status.is_success() {
+            return (mock.status, Json(json!({ "error": "invalid_client" }))).into_response();
+        }
+        Json(json!({ "access_token": format!("token-{}", n), "expires_in": mock.expires_in, "token_type": "Bearer" })).into_response()
+    }
+
+    // Local identity endpoint on a random port; returns the manager pointed at it and the hit counter
+    async fn mock_manager(expires_in: u64, status: StatusCode) -> (Arc<GraphTokenManager>, Arc<AtomicUsize>) {
+        let hits = Arc::new(AtomicUsize::new(0));
+        let app = Router::new()
+            .route("/tenant/oauth2/v2.0/token", post(issue_token))
+            .with_state(MockIdentity { hits: hits.clone(), expires_in, status });
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let authority = format!("http://{}/", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let manager = GraphTokenManager::new(reqwest::Client::new(), &authority, "tenant", "client".to_string(), "secret".to_string());
+        (Arc::new(manager), hits)
+    }
+
+    #[tokio::test]
+    async fn token_is_cached_until_refresh() {
+        let (manager, hits) = mock_manager(3600, StatusCode::OK).await;
+
+        assert_eq!(manager.token().await.unwrap(), "token-1");
+        assert_eq!(manager.token().await.unwrap(), "token-1");
+        assert_eq!(hits.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn concurrent_callers_share_one_fetch() {
+        let (manager, hits) = mock_manager(3600, StatusCode::OK).await;
+
+        let tasks: Vec<_> = (0..20).map(|_| {
+            let manager = manager.clone();
+            tokio::spawn(async move { manager.token().await.unwrap() })
+        }).collect();
+        for task in tasks {
+            assert_eq!(task.await.unwrap(), "token-1");
+        }
+        assert_eq!(hits.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn token_is_refreshed_before_expiry() {
+        let (manager, hits) = mock_manager(1, StatusCode::OK).await;
+
+        assert_eq!(manager.token().await.unwrap(), "token-1");
+        tokio::time::sleep(Duration::from_millis(600)).await;
+        assert_eq!(manager.token().await.unwrap(), "token-2");
+        assert_eq!(hits.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn rejected_token_is_replaced_once() {
+        let (manager, hits) = mock_manager(3600, StatusCode::OK).await;
+
+        let first = manager.token().await.unwrap();
+        manager.invalidate(&first).await;
+        assert_eq!(manager.token().await.unwrap(), "token-2");
+
+        // A stale rejection must not drop the token that already replaced it
+        manager.invalidate(&first).await;
+        assert_eq!(manager.token().await.unwrap(), "token-2");
+        assert_eq!(hits.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn refused_credentials_are_a_provider_error() {
+        let (manager, _) = mock_manager(3600, StatusCode::UNAUTHORIZED).await;
+
+        match manager.token().await {
+            Err(AppError::Provider(msg)) => assert!(msg.contains("401"), "{}", msg),
+            other => panic!("unexpected {:?}", other.map(|_| ())),
+        }
+    }
+}
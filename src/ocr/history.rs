@@ -0,0 +1,195 @@
+use std::{collections::HashMap, sync::Arc, time::Instant};
+use axum::{Json, body::Bytes, extract::{Query, State}, http::{HeaderMap, StatusCode}, response::{IntoResponse, Response}};
+use bb8::Pool;
+use bb8_tiberius::ConnectionManager;
+use chrono::{NaiveDate, NaiveDateTime};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+
+use crate::{constant::ApiResponse, db::execute_sp_dynamic, error::AppError, model::SqlParam, ocr::{cache::CacheMode, provider::{OcrOptions, OcrProvider, OcrResult}}, state::AppState};
+
+const MAX_PAGE_SIZE: i32 = 500;
+
+// Who asked for the analysis (stored next to every result) and how the cache may be used
+#[derive(Clone, Default, Debug)]
+pub struct RequestContext {
+    pub atm_id: Option<i64>,
+    pub caller: Option<String>,
+    pub cache: CacheMode,
+}
+
+impl RequestContext {
+    pub fn from_request(headers: &HeaderMap, params: &HashMap<String, String>) -> Self {
+        let caller = headers.get("X-Caller")
+            .and_then(|h| h.to_str().ok())
+            .map(|h| h.to_string())
+            .or_else(|| params.get("caller").cloned())
+            .filter(|c| !c.trim().is_empty());
+
+        Self {
+            atm_id: params.get("atm_id").and_then(|v| v.trim().parse().ok()),
+            caller,
+            cache: CacheMode::from_request(headers, params.get("cache")),
+        }
+    }
+}
+
+pub fn input_hash(input: &[u8]) -> String {
+    hex::encode(Sha256::digest(input))
+}
+
+// Writes analyses to SQL Server through usp_Insert_Ocr_Result (sql/ocr_results.sql).
+// Without a database nothing is recorded.
+#[derive(Clone)]
+pub struct ResultStore {
+    pool: Option<Arc<Pool<ConnectionManager>>>,
+}
+
+impl ResultStore {
+    pub fn new(pool: Option<Arc<Pool<ConnectionManager>>>) -> Self {
+        Self { pool }
+    }
+
+    // Fire and forget: a failed insert is logged but never fails the OCR request
+    pub fn save(&self, params: Vec<(&'static str, SqlParam)>) {
+        let Some(pool) = self.pool.clone() else { return };
+        tokio::spawn(async move {
+            let mut client = match pool.get().await {
+                Ok(c) => c,
+                Err(e) => return tracing::warn!("OCR history not saved, DB pool error: {}", e),
+            };
+            match execute_sp_dynamic(&mut client, "usp_Insert_Ocr_Result", &params).await {
+                Ok(sp) if sp.status => {}
+                Ok(sp) => tracing::warn!("OCR history not saved: {}", sp.message),
+                Err(e) => tracing::warn!("OCR history not saved: {}", e),
+            }
+        });
+    }
+}
+
+// Runs the provider, measures it and stores the outcome (success or failure).
+// `hash` is the hash of the image as submitted, before any preprocessing.
+pub async fn analyze_and_record(
+    store: &ResultStore,
+    provider: &dyn OcrProvider,
+    image: Bytes,
+    hash: String,
+    options: &OcrOptions,
+    ctx: &RequestContext,
+) -> Result<OcrResult, AppError> {
+    let started = Instant::now();
+    let outcome = provider.analyze(image, options).await;
+    let latency_ms = started.elapsed().as_millis().min(i32::MAX as u128) as i32;
+
+    let optional = |v: Option<String>| v.map(SqlParam::String).unwrap_or(SqlParam::Null);
+    let (model, raw, parsed, valid, error) = match &outcome {
+        Ok(res) => (Some(res.model.clone()), Some(res.raw.to_string()), res.data.as_ref().map(|d| d.to_string()), res.validation.as_ref().map(|v| v.valid), None),
+        Err(e) => (options.model.clone(), None, None, None, Some(e.to_string())),
+    };
+
+    store.save(vec![
+        ("inputHash", SqlParam::String(hash)),
+        ("provider", SqlParam::String(provider.name().to_string())),
+        ("model", optional(model)),
+        ("prompt", optional(options.prompt())),
+        // Template version and schema check, compared per counter type by /ocr/prompts/stats
+        ("counterName", optional(options.counter.as_ref().map(|c| c.name.clone()))),
+        ("promptName", optional(options.template.as_ref().map(|t| t.name.clone()))),
+        ("promptVersion", options.template.as_ref().map(|t| SqlParam::I32(t.version as i32)).unwrap_or(SqlParam::Null)),
+        ("isValid", valid.map(SqlParam::Bool).unwrap_or(SqlParam::Null)),
+        ("rawResponse", optional(raw)),
+        ("parsedJson", optional(parsed)),
+        ("isSuccess", SqlParam::Bool(outcome.is_ok())),
+        ("errorMessage", optional(error)),
+        ("latencyMs", SqlParam::I32(latency_ms)),
+        ("atmId", ctx.atm_id.map(SqlParam::I64).unwrap_or(SqlParam::Null)),
+        ("caller", optional(ctx.caller.clone())),
+    ]);
+
+    outcome
+}
+
+// Accepts `2024-05-01` or `2024-05-01T10:30:00`
+pub fn parse_date(value: &str) -> Option<NaiveDateTime> {
+    NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M:%S").ok()
+        .or_else(|| NaiveDate::parse_from_str(value, "%Y-%m-%d").ok().and_then(|d| d.and_hms_opt(0, 0, 0)))
+}
+
+// For the exclusive @dateTo: a date without a time means up to and including that day
+pub fn parse_date_to(value: &str) -> Option<NaiveDateTime> {
+    match NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        Ok(date) => date.succ_opt()?.and_hms_opt(0, 0, 0),
+        Err(_) => parse_date(value),
+    }
+}
+
+pub async fn list_results(
+    State(state): State<AppState>,
+    Query(params): Query<HashMap<String, String>>,
+) -> Result<Response, AppError> {
+    // 1. Validation
+    let atm_id = match params.get("atm_id").filter(|v| !v.is_empty()) {
+        Some(v) => SqlParam::I64(v.parse().map_err(|_| AppError::InvalidPayload("invalid atm_id".to_string()))?),
+        None => SqlParam::Null,
+    };
+
+    let date_param = |key: &str, parse: fn(&str) -> Option<NaiveDateTime>| match params.get(key).filter(|v| !v.is_empty()) {
+        Some(v) => parse(v)
+            .map(SqlParam::DateTime)
+            .ok_or_else(|| AppError::InvalidPayload(format!("invalid {} date, expected YYYY-MM-DD", key))),
+        None => Ok(SqlParam::Null),
+    };
+    let date_from = date_param("from", parse_date)?;
+    let date_to = date_param("to", parse_date_to)?;
+
+    let provider = params.get("provider")
+        .filter(|v| !v.is_empty())
+        .map(|v| SqlParam::String(v.clone()))
+        .unwrap_or(SqlParam::Null);
+    let page: i32 = params.get("page").and_then(|v| v.parse().ok()).filter(|p| *p > 0).unwrap_or(1);
+    let page_size: i32 = params.get("pageSize").and_then(|v| v.parse().ok()).filter(|p| *p > 0).unwrap_or(50).min(MAX_PAGE_SIZE);
+
+    // 2. DB Query
+    let mut client = state.db()?.get().await.map_err(|_| AppError::DbPool)?;
+
+    let sp_params = vec![
+        ("atmId", atm_id),
+        ("dateFrom", date_from),
+        ("dateTo", date_to),
+        ("provider", provider),
+        ("pageNumber", SqlParam::I32(page)),
+        ("pageSize", SqlParam::I32(page_size)),
+    ];
+
+    let sp = execute_sp_dynamic(&mut client, "usp_Get_Ocr_Results", &sp_params).await
+        .map_err(AppError::Database)?;
+    if !sp.status {
+        return Err(AppError::SpExecution { message: sp.message, data: None });
+    }
+
+    let rows = sp.result_sets.into_iter().next().unwrap_or_default();
+    let result = json!({ "page": page, "pageSize": page_size, "items": rows });
+    Ok((StatusCode::OK, Json(ApiResponse::success(result, &sp.message))).into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_date_only_upper_bound_covers_the_whole_day() {
+        let at = |s: &str| NaiveDateTime::parse_from_str(s, "%Y-%m-%dT%H:%M:%S").unwrap();
+        assert_eq!(parse_date("2024-05-01"), Some(at("2024-05-01T00:00:00")));
+        assert_eq!(parse_date_to("2024-05-01"), Some(at("2024-05-02T00:00:00")));
+        assert_eq!(parse_date_to("2024-12-31"), Some(at("2025-01-01T00:00:00")));
+        // An explicit time is kept as given
+        assert_eq!(parse_date_to("2024-05-01T10:30:00"), Some(at("2024-05-01T10:30:00")));
+        assert_eq!(parse_date_to("01/05/2024"), None);
+    }
+
+    #[test]
+    fn atm_id_is_read_from_snake_case() {
+        let params = HashMap::from([("atm_id".to_string(), " 42 ".to_string())]);
+        assert_eq!(RequestContext::from_request(&HeaderMap::new(), &params).atm_id, Some(42));
+    }
+}
@@ -0,0 +1,149 @@
+use std::sync::Arc;
+use async_trait::async_trait;
+use axum::body::Bytes;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tokio::time::Instant;
+
+use crate::{error::AppError, ocr::{azure_service::AzureReadProvider, deepseek_ocr::OllamaProvider, document::OcrDocument, provider::{OcrOptions, OcrProvider, OcrResult}, voting::VoteMode}};
+
+// Introduces the Read lines appended to the prompt
+const LINES_HEADER: &str = "Text recognized on the document by OCR, one line per row in reading order. \
+Take every value, digits in particular, from these lines.";
+const IMAGE_HINT: &str = "Use the image only to see the layout.";
+
+// One stage of a hybrid run
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct HybridStage {
+    pub provider: String,
+    pub model: String,
+    pub latency_ms: u64,
+    // Read: the recognized text; structuring: the model answer
+    pub text: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct HybridStages {
+    pub read: HybridStage,
+    pub structure: HybridStage,
+    // Whether the image was sent to the model along with the lines
+    pub with_image: bool,
+}
+
+// Azure Read for the text, then an Ollama model (`?model_name=`) turns the lines into the
+// counter type's JSON. `?hybrid_image=true` also sends the image, which needs a vision model.
+pub struct HybridProvider {
+    read: Arc<AzureReadProvider>,
+    ollama: Arc<OllamaProvider>,
+}
+
+impl HybridProvider {
+    pub fn new(read: Arc<AzureReadProvider>, ollama: Arc<OllamaProvider>) -> Self {
+        Self { read, ollama }
+    }
+}
+
+// The prompt followed by the Read lines, pages marked when there are several
+fn prompt_with_lines(prompt: &str, document: &OcrDocument, with_image: bool) -> String {
+    let hint = if with_image { format!(" {}", IMAGE_HINT) } else { String::new() };
+    let mut text = format!("{}\n\n{}{}\n", prompt.trim_end(), LINES_HEADER, hint);
+    for page in &document.pages {
+        if document.pages.len() > 1 {
+            text.push_str(&format!("--- page {} ---\n", page.number));
+        }
+        for line in page.blocks.iter().flat_map(|b| &b.lines) {
+            text.push_str(&line.text);
+            text.push('\n');
+        }
+    }
+    text
+}
+
+#[async_trait]
+impl OcrProvider for HybridProvider {
+    fn name(&self) -> &'static str {
+        "hybrid"
+    }
+
+    fn takes_prompt(&self) -> bool {
+        true
+    }
+
+    async fn analyze(&self, image: Bytes, options: &OcrOptions) -> Result<OcrResult, AppError> {
+        // 1. Validation, before Read is paid for
+        let prompt = options.prompt()
+            .ok_or_else(|| AppError::InvalidPayload("Missing prompt or prompt_name".to_string()))?;
+        // `?vote_models=` names the models itself
+        if options.model.is_none() && !matches!(options.voting, Some(VoteMode::Models(_))) {
+            return Err(AppError::InvalidPayload("Invalid or missing model_name".to_string()));
+        }
+
+        // 2. Read: text and positions
+        let started = Instant::now();
+        let read = self.read.analyze(image.clone(), options).await?;
+        let read_ms = started.elapsed().as_millis() as u64;
+        if read.text.trim().is_empty() {
+            return Err(AppError::ParseFailure("Azure Read found no text to structure".to_string()));
+        }
+
+        // 3. Structure: the Ollama model gets the lines, and the image when asked for
+        let structure_options = OcrOptions { prompt: Some(prompt_with_lines(&prompt, &read.document, options.hybrid_image)), ..options.clone() };
+        let started = Instant::now();
+        let structured = self.ollama.run(options.hybrid_image.then_some(&image), &structure_options).await?;
+        let structure_ms = started.elapsed().as_millis() as u64;
+
+        // 4. Read's positioned lines with the fields of the structured data
+        let mut document = read.document.clone();
+        document.fields = structured.document.fields.clone();
+        let stages = HybridStages {
+            read: HybridStage { provider: read.provider, model: read.model.clone(), latency_ms: read_ms, text: read.text },
+            structure: HybridStage { provider: structured.provider, model: structured.model.clone(), latency_ms: structure_ms, text: structured.text.clone() },
+            with_image: options.hybrid_image,
+        };
+
+        Ok(OcrResult {
+            provider: self.name().to_string(),
+            model: format!("{}+{}", read.model, structured.model),
+            text: structured.text,
+            data: structured.data,
+            document,
+            raw: json!({ "read": read.raw, "structure": structured.raw }),
+            cache: None,
+            preprocess: None,
+            validation: structured.validation,
+            repairs: structured.repairs,
+            prompt: options.template.as_ref().map(|t| t.id()),
+            generation: structured.generation,
+            voting: structured.voting,
+            hybrid: Some(stages),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ocr::document::{Block, Line, Page};
+
+    fn page(number: u32, lines: &[&str]) -> Page {
+        let lines = lines.iter().map(|text| Line { text: text.to_string(), ..Default::default() }).collect();
+        Page { number, blocks: vec![Block { lines }], ..Default::default() }
+    }
+
+    #[test]
+    fn lines_follow_the_prompt() {
+        let one = OcrDocument { pages: vec![page(1, &["HDFC BANK", "ATM ID S1AW001"])], fields: Vec::new() };
+        assert_eq!(
+            prompt_with_lines("Extract the slip.\n", &one, false),
+            format!("Extract the slip.\n\n{}\nHDFC BANK\nATM ID S1AW001\n", LINES_HEADER)
+        );
+
+        let two = OcrDocument { pages: vec![page(1, &["first"]), page(2, &["second"])], fields: Vec::new() };
+        assert_eq!(
+            prompt_with_lines("Extract.", &two, true),
+            format!("Extract.\n\n{} {}\n--- page 1 ---\nfirst\n--- page 2 ---\nsecond\n", LINES_HEADER, IMAGE_HINT)
+        );
+    }
+}
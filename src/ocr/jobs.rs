@@ -0,0 +1,375 @@
+use std::{collections::HashMap, sync::Arc};
+use axum::{Json, body::Bytes, extract::{Path, Query, State}, http::{HeaderMap, StatusCode}, response::{IntoResponse, Response}};
+use chrono::{DateTime, Duration, Utc};
+use serde::Serialize;
+use serde_json::json;
+use tokio::sync::{Mutex, RwLock, mpsc};
+use uuid::Uuid;
+
+use crate::{constant::ApiResponse, error::AppError, ocr::{history::RequestContext, pipeline::{AnalyzeOutput, OcrPipeline}, provider::{OcrOptions, OcrProvider}, webhook::{DeliveryAttempt, WebhookSender}}, state::AppState};
+
+// Finished jobs are kept this long for polling, then dropped
+const JOB_RETENTION_MINUTES: i64 = 60;
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum JobStatus {
+    Queued,
+    Running,
+    Succeeded,
+    Failed,
+}
+
+#[derive(Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct OcrJob {
+    pub id: String,
+    pub provider: String,
+    pub status: JobStatus,
+    pub created_at: DateTime<Utc>,
+    pub started_at: Option<DateTime<Utc>>,
+    pub finished_at: Option<DateTime<Utc>>,
+    pub result: Option<AnalyzeOutput>,
+    pub error: Option<String>,
+    pub callback_url: Option<String>,
+    pub deliveries: Vec<DeliveryAttempt>,
+}
+
+struct QueuedJob {
+    id: String,
+    provider: Arc<dyn OcrProvider>,
+    image: Bytes,
+    options: OcrOptions,
+    ctx: RequestContext,
+}
+
+// In-memory job table plus a bounded channel drained by a fixed pool of workers
+#[derive(Clone)]
+pub struct JobQueue {
+    sender: mpsc::Sender<QueuedJob>,
+    jobs: Arc<RwLock<HashMap<String, OcrJob>>>,
+    webhooks: Option<WebhookSender>,
+    pipeline: OcrPipeline,
+}
+
+impl JobQueue {
+    pub fn start(workers: usize, capacity: usize, webhooks: Option<WebhookSender>, pipeline: OcrPipeline) -> Self {
+        let (sender, receiver) = mpsc::channel::<QueuedJob>(capacity.max(1));
+        let receiver = Arc::new(Mutex::new(receiver));
+        let queue = Self {
+            sender,
+            jobs: Arc::new(RwLock::new(HashMap::new())),
+            webhooks,
+            pipeline,
+        };
+
+        for worker_id in 0..workers.max(1) {
+            let receiver = receiver.clone();
+            let queue = queue.clone();
+            tokio::spawn(async move {
+                loop {
+                    // Hold the lock only while waiting for the next job
+                    let next = receiver.lock().await.recv().await;
+                    let Some(job) = next else { break };
+                    tracing::debug!("worker {} picked job {}", worker_id, job.id);
+                    let id = job.id.clone();
+                    queue.run(job).await;
+
+                    // Deliver in the background so a slow receiver does not hold a worker
+                    let queue = queue.clone();
+                    tokio::spawn(async move { queue.deliver(&id).await });
+                }
+            });
+        }
+
+        queue
+    }
+
+    pub async fn enqueue(
+        &self,
+        provider: Arc<dyn OcrProvider>,
+        image: Bytes,
+        options: OcrOptions,
+        ctx: RequestContext,
+        callback_url: Option<String>,
+    ) -> Result<OcrJob, AppError> {
+        self.prune().await;
+
+        let job = OcrJob {
+            id: Uuid::new_v4().to_string(),
+            provider: provider.name().to_string(),
+            status: JobStatus::Queued,
+            created_at: Utc::now(),
+            started_at: None,
+            finished_at: None,
+            result: None,
+            error: None,
+            callback_url,
+            deliveries: Vec::new(),
+        };
+        self.jobs.write().await.insert(job.id.clone(), job.clone());
+
+        let queued = QueuedJob { id: job.id.clone(), provider, image, options, ctx };
+        if let Err(e) = self.sender.try_send(queued) {
+            self.jobs.write().await.remove(&job.id);
+            return Err(AppError::QueueFull(match e {
+                mpsc::error::TrySendError::Full(_) => "OCR queue is full, try again later".to_string(),
+                mpsc::error::TrySendError::Closed(_) => "OCR workers are not running".to_string(),
+            }));
+        }
+
+        Ok(job)
+    }
+
+    // None when callbacks are disabled
+    pub fn webhooks(&self) -> Option<&WebhookSender> {
+        self.webhooks.as_ref()
+    }
+
+    pub async fn get(&self, id: &str) -> Option<OcrJob> {
+        self.jobs.read().await.get(id).cloned()
+    }
+
+    // POSTs the finished job to its callback URL, retrying with exponential backoff.
+    // Every attempt is recorded on the job so it can be inspected and replayed.
+    pub async fn deliver(&self, id: &str) {
+        let Some(webhooks) = &self.webhooks else { return };
+        let Some(job) = self.get(id).await else { return };
+        let Some(url) = job.callback_url.clone() else { return };
+
+        let payload = json!({
+            "jobId": job.id,
+            "provider": job.provider,
+            "status": job.status,
+            "finishedAt": job.finished_at,
+            "result": job.result,
+            "error": job.error,
+        });
+        let body = serde_json::to_vec(&payload).unwrap_or_default();
+
+        webhooks.deliver(&url, &body, |attempt| {
+            let jobs = self.jobs.clone();
+            async move {
+                if let Some(entry) = jobs.write().await.get_mut(id) {
+                    entry.deliveries.push(attempt);
+                }
+            }
+        }).await;
+    }
+
+    async fn run(&self, job: QueuedJob) {
+        if let Some(entry) = self.jobs.write().await.get_mut(&job.id) {
+            entry.status = JobStatus::Running;
+            entry.started_at = Some(Utc::now());
+        }
+
+        let outcome = self.pipeline.analyze(job.provider.as_ref(), job.image, &job.options, &job.ctx).await;
+
+        if let Some(entry) = self.jobs.write().await.get_mut(&job.id) {
+            entry.finished_at = Some(Utc::now());
+            match outcome {
+                Ok(result) => {
+                    entry.status = JobStatus::Succeeded;
+                    entry.result = Some(result);
+                }
+                Err(e) => {
+                    entry.status = JobStatus::Failed;
+                    entry.error = Some(e.to_string());
+                }
+            }
+        }
+    }
+
+    async fn prune(&self) {
+        let cutoff = Utc::now() - Duration::minutes(JOB_RETENTION_MINUTES);
+        self.jobs.write().await
+            .retain(|_, job| job.finished_at.is_none_or(|finished| finished > cutoff));
+    }
+}
+
+pub async fn submit_job(
+    State(state): State<AppState>,
+    Query(params): Query<HashMap<String, String>>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Response, AppError> {
+    // 1. Validation
+    let provider_name = params.get("provider").map(|p| p.trim()).unwrap_or("");
+    let provider = state.provider(provider_name)?;
+
+    if body.is_empty() {
+        return Err(AppError::InvalidPayload("Empty request body".to_string()));
+    }
+
+    let callback_url = match params.get("callback_url").map(|u| u.trim()).filter(|u| !u.is_empty()) {
+        Some(url) => {
+            let webhooks = state.jobs.webhooks()
+                .ok_or_else(|| AppError::InvalidPayload("Callbacks are disabled: webhooks.secret is not configured".to_string()))?;
+            Some(webhooks.validate_callback_url(url).map_err(AppError::InvalidPayload)?)
+        }
+        None => None,
+    };
+
+    let options = OcrOptions::from_query(&params, provider.as_ref(), &state.counters, &state.prompts)?;
+
+    // 2. Enqueue and return immediately
+    let job = state.jobs.enqueue(provider, body, options, RequestContext::from_request(&headers, &params), callback_url).await?;
+    let result = json!({ "jobId": job.id, "status": job.status });
+    Ok((StatusCode::ACCEPTED, Json(ApiResponse::success(result, "OCR job queued"))).into_response())
+}
+
+pub async fn get_job(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Result<Response, AppError> {
+    let job = state.jobs.get(&id).await.ok_or(AppError::JobNotFound)?;
+    Ok((StatusCode::OK, Json(ApiResponse::success(job, "OCR job found"))).into_response())
+}
+
+pub async fn get_job_deliveries(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Result<Response, AppError> {
+    let job = state.jobs.get(&id).await.ok_or(AppError::JobNotFound)?;
+    let result = json!({ "jobId": job.id, "callbackUrl": job.callback_url, "deliveries": job.deliveries });
+    Ok((StatusCode::OK, Json(ApiResponse::success(result, "Delivery attempts found"))).into_response())
+}
+
+pub async fn replay_job_delivery(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Result<Response, AppError> {
+    let job = state.jobs.get(&id).await.ok_or(AppError::JobNotFound)?;
+    if job.callback_url.is_none() {
+        return Err(AppError::InvalidPayload("Job has no callback_url".to_string()));
+    }
+    if job.finished_at.is_none() {
+        return Err(AppError::Conflict("Job has not finished yet".to_string()));
+    }
+
+    let queue = state.jobs.clone();
+    tokio::spawn(async move { queue.deliver(&id).await });
+
+    Ok((StatusCode::ACCEPTED, Json(ApiResponse::success(json!({ "jobId": job.id }), "Callback delivery replay started"))).into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_trait::async_trait;
+    use tokio::sync::Semaphore;
+    use crate::ocr::{cache::{CacheMode, ResultCache}, document::OcrDocument, history::ResultStore, provider::OcrResult};
+
+    // Finishes one analysis per permit; `fail` as the image makes it fail
+    struct GatedProvider {
+        gate: Arc<Semaphore>,
+    }
+
+    #[async_trait]
+    impl OcrProvider for GatedProvider {
+        fn name(&self) -> &'static str {
+            "stub"
+        }
+
+        async fn analyze(&self, image: Bytes, _options: &OcrOptions) -> Result<OcrResult, AppError> {
+            self.gate.acquire().await.expect("gate closed").forget();
+            if image.as_ref() == b"fail" {
+                return Err(AppError::Provider("stub failure".to_string()));
+            }
+            Ok(OcrResult {
+                provider: "stub".to_string(),
+                model: "stub".to_string(),
+                text: String::from_utf8_lossy(&image).to_string(),
+                data: None,
+                document: OcrDocument::default(),
+                raw: serde_json::Value::Null,
+                cache: None,
+                preprocess: None,
+                validation: None,
+                repairs: Vec::new(),
+                prompt: None,
+                generation: None,
+                voting: None,
+                hybrid: None,
+            })
+        }
+    }
+
+    fn queue(workers: usize, capacity: usize) -> (JobQueue, Arc<dyn OcrProvider>, Arc<Semaphore>) {
+        let gate = Arc::new(Semaphore::new(0));
+        let pipeline = OcrPipeline::new(ResultStore::new(None), ResultCache::new(8, None));
+        let provider: Arc<dyn OcrProvider> = Arc::new(GatedProvider { gate: gate.clone() });
+        (JobQueue::start(workers, capacity, None, pipeline), provider, gate)
+    }
+
+    async fn submit(queue: &JobQueue, provider: &Arc<dyn OcrProvider>, image: &'static [u8]) -> Result<OcrJob, AppError> {
+        let ctx = RequestContext { cache: CacheMode::Bypass, ..Default::default() };
+        queue.enqueue(provider.clone(), Bytes::from_static(image), OcrOptions::default(), ctx, None).await
+    }
+
+    async fn wait_for(queue: &JobQueue, id: &str, status: JobStatus) -> OcrJob {
+        for _ in 0..200 {
+            let job = queue.get(id).await.expect("job exists");
+            if job.status == status {
+                return job;
+            }
+            tokio::time::sleep(tokio::time::Duration::from_millis(5)).await;
+        }
+        panic!("job {} never became {:?}", id, status);
+    }
+
+    #[tokio::test]
+    async fn jobs_move_from_queued_to_running_to_done() {
+        let (queue, provider, gate) = queue(1, 4);
+
+        let ok = submit(&queue, &provider, b"slip").await.unwrap();
+        let failing = submit(&queue, &provider, b"fail").await.unwrap();
+        assert_eq!(ok.status, JobStatus::Queued);
+
+        // One worker: the first job holds it while the second waits in the queue
+        let running = wait_for(&queue, &ok.id, JobStatus::Running).await;
+        assert!(running.started_at.is_some() && running.finished_at.is_none());
+        assert_eq!(queue.get(&failing.id).await.unwrap().status, JobStatus::Queued);
+
+        gate.add_permits(2);
+        let done = wait_for(&queue, &ok.id, JobStatus::Succeeded).await;
+        assert!(done.finished_at.is_some() && done.error.is_none());
+        assert!(matches!(done.result, Some(AnalyzeOutput::Single(ref r)) if r.text == "slip"));
+
+        let failed = wait_for(&queue, &failing.id, JobStatus::Failed).await;
+        assert!(failed.result.is_none());
+        assert!(failed.error.unwrap().contains("stub failure"));
+    }
+
+    #[tokio::test]
+    async fn a_full_queue_rejects_the_job_and_forgets_it() {
+        let (queue, provider, _gate) = queue(1, 1);
+
+        let running = submit(&queue, &provider, b"first").await.unwrap();
+        wait_for(&queue, &running.id, JobStatus::Running).await;
+        submit(&queue, &provider, b"second").await.unwrap();
+
+        let rejected = submit(&queue, &provider, b"third").await.unwrap_err();
+        assert!(matches!(rejected, AppError::QueueFull(_)));
+        assert_eq!(queue.jobs.read().await.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn prune_drops_only_jobs_finished_before_the_retention() {
+        let (queue, provider, gate) = queue(1, 4);
+        let old = submit(&queue, &provider, b"old").await.unwrap();
+        let recent = submit(&queue, &provider, b"recent").await.unwrap();
+        gate.add_permits(2);
+        wait_for(&queue, &old.id, JobStatus::Succeeded).await;
+        wait_for(&queue, &recent.id, JobStatus::Succeeded).await;
+        let pending = submit(&queue, &provider, b"pending").await.unwrap();
+
+        queue.jobs.write().await.get_mut(&old.id).unwrap().finished_at = Some(Utc::now() - Duration::minutes(JOB_RETENTION_MINUTES + 1));
+        queue.prune().await;
+
+        assert!(queue.get(&old.id).await.is_none());
+        assert!(queue.get(&recent.id).await.is_some());
+        // Unfinished jobs are never pruned
+        assert!(queue.get(&pending.id).await.is_some());
+    }
+}
@@ -0,0 +1,612 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+// Candidate start positions tried before giving up, prose rarely has more braces than this
+const MAX_CANDIDATES: usize = 32;
+
+// Fixes applied to get a parseable document out of a model answer
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum JsonRepair {
+    // Text (prose, code fences) around the JSON was dropped
+    ExtractedFromText,
+    RemovedComments,
+    SingleQuotes,
+    // Unescaped newlines/tabs inside strings, or unknown escapes like `\d`
+    StringEscapes,
+    QuotedKeys,
+    // Barewords used as values became strings
+    QuotedValues,
+    // True/False/None and other casings of JSON literals
+    Literals,
+    // NaN, Infinity, undefined
+    NonJsonValues,
+    // `+1`, `.5`, `1.`, `007`
+    NumberFormat,
+    TrailingCommas,
+    MissingCommas,
+    MissingColons,
+    // A key with nothing after it, set to null
+    MissingValues,
+    // `]` closing an object or the other way round
+    MismatchedBrackets,
+    // Open strings, objects and arrays closed at the end of the text
+    ClosedTruncated,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Recovered {
+    pub value: Value,
+    pub repairs: Vec<JsonRepair>,
+}
+
+// Finds the outermost JSON object (or array) anywhere in `text` and repairs it until it parses.
+// Valid JSON comes back untouched with no repairs.
+pub fn recover(text: &str) -> Option<Recovered> {
+    let trimmed = text.trim();
+    if let Ok(value) = serde_json::from_str::<Value>(trimmed)
+        && (value.is_object() || value.is_array())
+    {
+        return Some(Recovered { value, repairs: Vec::new() });
+    }
+
+    // Objects first: prose is more likely to contain a stray `[` than a stray `{`
+    let mut starts: Vec<usize> = text.match_indices('{').map(|(i, _)| i).collect();
+    starts.extend(text.match_indices('[').map(|(i, _)| i));
+
+    let mut best: Option<(usize, Recovered)> = None;
+    let mut covered_until = 0;
+    for start in starts.into_iter().take(MAX_CANDIDATES) {
+        // Nested in a span already recovered, it can only be smaller
+        if start < covered_until && best.is_some() {
+            continue;
+        }
+        let Some((mut recovered, end)) = repair_from(text, start) else { continue };
+
+        if !text[..start].trim().is_empty() || !text[end..].trim().is_empty() {
+            recovered.repairs.push(JsonRepair::ExtractedFromText);
+        }
+        recovered.repairs.sort();
+        recovered.repairs.dedup();
+
+        let len = end - start;
+        let better = match &best {
+            None => true,
+            Some((best_len, b)) => len > *best_len || (len == *best_len && recovered.repairs.len() < b.repairs.len()),
+        };
+        if better {
+            covered_until = covered_until.max(end);
+            best = Some((len, recovered));
+        }
+    }
+
+    best.map(|(_, recovered)| recovered)
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Open(char),
+    Close(char),
+    Colon,
+    Comma,
+    Str(String),
+    // Unquoted run: number, literal or bareword
+    Atom(String),
+}
+
+// Tokenizes from `start` until the opening bracket is balanced (or the text ends).
+// Returns the tokens and the byte offset just past the last one used.
+fn lex(text: &str, start: usize, repairs: &mut Vec<JsonRepair>) -> (Vec<Token>, usize) {
+    let bytes = text.as_bytes();
+    let mut tokens = Vec::new();
+    let mut depth = 0usize;
+    let mut i = start;
+
+    while i < bytes.len() {
+        let c = bytes[i];
+        match c {
+            b' ' | b'\t' | b'\r' | b'\n' => i += 1,
+            b'{' | b'[' => {
+                depth += 1;
+                tokens.push(Token::Open(c as char));
+                i += 1;
+            }
+            b'}' | b']' => {
+                tokens.push(Token::Close(c as char));
+                i += 1;
+                depth = depth.saturating_sub(1);
+                if depth == 0 {
+                    return (tokens, i);
+                }
+            }
+            b':' => { tokens.push(Token::Colon); i += 1; }
+            b',' => { tokens.push(Token::Comma); i += 1; }
+            b'/' if bytes.get(i + 1) == Some(&b'/') => {
+                repairs.push(JsonRepair::RemovedComments);
+                i = text[i..].find('\n').map(|n| i + n).unwrap_or(bytes.len());
+            }
+            b'/' if bytes.get(i + 1) == Some(&b'*') => {
+                repairs.push(JsonRepair::RemovedComments);
+                i = text[i + 2..].find("*/").map(|n| i + 2 + n + 2).unwrap_or(bytes.len());
+            }
+            b'"' | b'\'' => {
+                if c == b'\'' {
+                    repairs.push(JsonRepair::SingleQuotes);
+                }
+                let (value, next) = lex_string(text, i, c as char, repairs);
+                tokens.push(Token::Str(value));
+                i = next;
+            }
+            _ => {
+                let mut end = i;
+                for (offset, ch) in text[i..].char_indices() {
+                    let rest = &text[i + offset..];
+                    let boundary = ch.is_whitespace() || "{}[]:,\"'".contains(ch) || rest.starts_with("//") || rest.starts_with("/*");
+                    if offset > 0 && boundary {
+                        break;
+                    }
+                    end = i + offset + ch.len_utf8();
+                }
+                tokens.push(Token::Atom(text[i..end].to_string()));
+                i = end;
+            }
+        }
+    }
+
+    (tokens, bytes.len())
+}
+
+// Reads a quoted string starting at `start` (the quote). Returns the decoded value and the offset
+// after the closing quote; an unclosed string runs to the end of the text.
+fn lex_string(text: &str, start: usize, quote: char, repairs: &mut Vec<JsonRepair>) -> (String, usize) {
+    let mut value = String::new();
+    let mut chars = text[start + 1..].char_indices().peekable();
+
+    while let Some((offset, ch)) = chars.next() {
+        match ch {
+            c if c == quote => return (value, start + 1 + offset + c.len_utf8()),
+            '\\' => {
+                let Some((_, esc)) = chars.next() else { break };
+                match esc {
+                    '"' | '\\' | '/' | '\'' => value.push(esc),
+                    'b' => value.push('\u{8}'),
+                    'f' => value.push('\u{c}'),
+                    'n' => value.push('\n'),
+                    'r' => value.push('\r'),
+                    't' => value.push('\t'),
+                    'u' => {
+                        let read_hex = |chars: &mut std::iter::Peekable<std::str::CharIndices>| {
+                            let hex: String = (0..4).filter_map(|_| chars.next_if(|(_, h)| h.is_ascii_hexdigit()).map(|(_, h)| h)).collect();
+                            u32::from_str_radix(&hex, 16).ok().filter(|_| hex.len() == 4)
+                        };
+                        let mut code = read_hex(&mut chars);
+                        // High surrogate: the low half follows as its own \uXXXX
+                        if let Some(high @ 0xD800..=0xDBFF) = code {
+                            let mut ahead = chars.clone();
+                            if ahead.next().map(|(_, c)| c) == Some('\\') && ahead.next().map(|(_, c)| c) == Some('u')
+                                && let Some(low @ 0xDC00..=0xDFFF) = read_hex(&mut ahead)
+                            {
+                                chars = ahead;
+                                code = Some(0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00));
+                            }
+                        }
+                        match code.and_then(char::from_u32) {
+                            Some(c) => value.push(c),
+                            // Broken escape or lone surrogate
+                            None => {
+                                repairs.push(JsonRepair::StringEscapes);
+                                value.push('\u{fffd}');
+                            }
+                        }
+                    }
+                    other => {
+                        repairs.push(JsonRepair::StringEscapes);
+                        value.push('\\');
+                        value.push(other);
+                    }
+                }
+            }
+            c if (c as u32) < 0x20 => {
+                repairs.push(JsonRepair::StringEscapes);
+                value.push(c);
+            }
+            c => value.push(c),
+        }
+    }
+
+    repairs.push(JsonRepair::ClosedTruncated);
+    (value, text.len())
+}
+
+// Turns an unquoted run into JSON: number, literal, or a quoted string as last resort
+fn atom_json(atom: &str, repairs: &mut Vec<JsonRepair>) -> String {
+    match atom {
+        "true" | "false" | "null" => return atom.to_string(),
+        _ => {}
+    }
+    match atom.to_ascii_lowercase().as_str() {
+        "true" | "false" | "null" => {
+            repairs.push(JsonRepair::Literals);
+            return atom.to_ascii_lowercase();
+        }
+        "none" => {
+            repairs.push(JsonRepair::Literals);
+            return "null".to_string();
+        }
+        "nan" | "infinity" | "-infinity" | "+infinity" | "undefined" => {
+            repairs.push(JsonRepair::NonJsonValues);
+            return "null".to_string();
+        }
+        _ => {}
+    }
+
+    if let Some(number) = number_json(atom) {
+        if number != atom {
+            repairs.push(JsonRepair::NumberFormat);
+        }
+        return number;
+    }
+    repairs.push(JsonRepair::QuotedValues);
+    Value::String(atom.to_string()).to_string()
+}
+
+// Normalizes number-like atoms; `None` when it is not a number at all
+fn number_json(atom: &str) -> Option<String> {
+    let body = atom.strip_prefix('+').unwrap_or(atom);
+    let (sign, digits) = match body.strip_prefix('-') {
+        Some(rest) => ("-", rest),
+        None => ("", body),
+    };
+    if digits.is_empty() || !digits.starts_with(|c: char| c.is_ascii_digit() || c == '.') {
+        return None;
+    }
+    if digits.len() > 1 && digits.starts_with('0') && digits.as_bytes()[1].is_ascii_digit() {
+        // Leading zeros are identifiers (ATM ids, account numbers), keep them as text
+        return Some(Value::String(atom.to_string()).to_string());
+    }
+
+    // Cut partial exponents and dangling dots left by truncation
+    let trimmed = digits.trim_end_matches(['e', 'E', '+', '-', '.']);
+    let normalized = match trimmed.strip_prefix('.') {
+        Some(frac) => format!("{}0.{}", sign, frac),
+        None => format!("{}{}", sign, trimmed),
+    };
+    serde_json::from_str::<serde_json::Number>(&normalized).ok().map(|_| normalized)
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Expect {
+    Key,
+    Colon,
+    Value,
+    CommaOrEnd,
+}
+
+struct Frame {
+    close: char,
+    expect: Expect,
+}
+
+// Re-emits the token stream as valid JSON, fixing structure as it goes
+fn repair_from(text: &str, start: usize) -> Option<(Recovered, usize)> {
+    let mut repairs = Vec::new();
+    let (tokens, end) = lex(text, start, &mut repairs);
+
+    let mut out = String::new();
+    let mut stack: Vec<Frame> = Vec::new();
+    for token in tokens {
+        let Some(frame) = stack.last_mut() else {
+            // Only the very first token can land here, and it is the opening bracket
+            match token {
+                Token::Open(open) => {
+                    out.push(open);
+                    stack.push(Frame { close: closer(open), expect: if open == '{' { Expect::Key } else { Expect::Value } });
+                    continue;
+                }
+                _ => return None,
+            }
+        };
+
+        match (frame.expect, token) {
+            // Object keys
+            (Expect::Key, Token::Str(key)) => {
+                out.push_str(&Value::String(key).to_string());
+                frame.expect = Expect::Colon;
+            }
+            (Expect::Key, Token::Atom(key)) => {
+                repairs.push(JsonRepair::QuotedKeys);
+                out.push_str(&Value::String(key).to_string());
+                frame.expect = Expect::Colon;
+            }
+            (Expect::Key, Token::Comma) => repairs.push(JsonRepair::TrailingCommas),
+            (Expect::Key, Token::Close(c)) => {
+                if out.ends_with(',') {
+                    out.pop();
+                    repairs.push(JsonRepair::TrailingCommas);
+                }
+                close_frame(&mut out, &mut stack, c, &mut repairs);
+            }
+            (Expect::Key, Token::Colon) => {
+                // `{: 1}`, nothing sensible to key it by
+                return None;
+            }
+            (Expect::Key, Token::Open(_)) => return None,
+
+            // Between key and value
+            (Expect::Colon, Token::Colon) => {
+                out.push(':');
+                frame.expect = Expect::Value;
+            }
+            (Expect::Colon, Token::Close(c)) => {
+                repairs.push(JsonRepair::MissingValues);
+                out.push_str(":null");
+                close_frame(&mut out, &mut stack, c, &mut repairs);
+            }
+            (Expect::Colon, Token::Comma) => {
+                repairs.push(JsonRepair::MissingValues);
+                out.push_str(":null,");
+                frame.expect = Expect::Key;
+            }
+            (Expect::Colon, value) => {
+                repairs.push(JsonRepair::MissingColons);
+                out.push(':');
+                frame.expect = Expect::Value;
+                emit_value(&mut out, &mut stack, value, &mut repairs);
+            }
+
+            // Values
+            (Expect::Value, Token::Comma) => {
+                if frame.close == '}' {
+                    // `"a": ,` lost its value
+                    out.push_str("null,");
+                    frame.expect = Expect::Key;
+                    repairs.push(JsonRepair::MissingValues);
+                } else {
+                    repairs.push(JsonRepair::TrailingCommas);
+                }
+            }
+            (Expect::Value, Token::Close(c)) => {
+                if frame.close == '}' {
+                    out.push_str("null");
+                    repairs.push(JsonRepair::MissingValues);
+                } else if out.ends_with(',') {
+                    out.pop();
+                    repairs.push(JsonRepair::TrailingCommas);
+                }
+                close_frame(&mut out, &mut stack, c, &mut repairs);
+            }
+            (Expect::Value, Token::Colon) => return None,
+            (Expect::Value, value) => emit_value(&mut out, &mut stack, value, &mut repairs),
+
+            // After a value
+            (Expect::CommaOrEnd, Token::Comma) => {
+                out.push(',');
+                frame.expect = if frame.close == '}' { Expect::Key } else { Expect::Value };
+            }
+            (Expect::CommaOrEnd, Token::Close(c)) => close_frame(&mut out, &mut stack, c, &mut repairs),
+            (Expect::CommaOrEnd, Token::Colon) => return None,
+            (Expect::CommaOrEnd, token) => {
+                repairs.push(JsonRepair::MissingCommas);
+                out.push(',');
+                if frame.close == '}' {
+                    frame.expect = Expect::Key;
+                    match token {
+                        Token::Str(key) | Token::Atom(key) => {
+                            out.push_str(&Value::String(key).to_string());
+                            frame.expect = Expect::Colon;
+                        }
+                        _ => return None,
+                    }
+                } else {
+                    emit_value(&mut out, &mut stack, token, &mut repairs);
+                }
+            }
+        }
+
+        if stack.is_empty() {
+            break;
+        }
+    }
+
+    // Truncated: finish whatever was open, innermost first
+    if !stack.is_empty() {
+        repairs.push(JsonRepair::ClosedTruncated);
+        while let Some(frame) = stack.pop() {
+            match frame.expect {
+                Expect::Colon => out.push_str(":null"),
+                Expect::Value if frame.close == '}' => out.push_str("null"),
+                Expect::Key | Expect::Value if out.ends_with(',') => { out.pop(); }
+                _ => {}
+            }
+            out.push(frame.close);
+        }
+    }
+
+    let value = serde_json::from_str::<Value>(&out).ok()?;
+    Some((Recovered { value, repairs }, end))
+}
+
+fn closer(open: char) -> char {
+    if open == '{' { '}' } else { ']' }
+}
+
+fn emit_value(out: &mut String, stack: &mut Vec<Frame>, token: Token, repairs: &mut Vec<JsonRepair>) {
+    if let Some(frame) = stack.last_mut() {
+        frame.expect = Expect::CommaOrEnd;
+    }
+    match token {
+        Token::Str(s) => out.push_str(&Value::String(s).to_string()),
+        Token::Atom(a) => out.push_str(&atom_json(&a, repairs)),
+        Token::Open(open) => {
+            out.push(open);
+            stack.push(Frame { close: closer(open), expect: if open == '{' { Expect::Key } else { Expect::Value } });
+        }
+        // Callers handle the structural tokens themselves
+        Token::Close(_) | Token::Colon | Token::Comma => {}
+    }
+}
+
+fn close_frame(out: &mut String, stack: &mut Vec<Frame>, close: char, repairs: &mut Vec<JsonRepair>) {
+    let Some(frame) = stack.pop() else { return };
+    if frame.close != close {
+        repairs.push(JsonRepair::MismatchedBrackets);
+    }
+    out.push(frame.close);
+    if let Some(parent) = stack.last_mut() {
+        parent.expect = Expect::CommaOrEnd;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+    use serde_json::json;
+
+    fn repaired(text: &str) -> (Value, Vec<JsonRepair>) {
+        let r = recover(text).unwrap_or_else(|| panic!("not recovered: {}", text));
+        (r.value, r.repairs)
+    }
+
+    #[test]
+    fn valid_json_is_untouched() {
+        assert_eq!(repaired(r#"{"a": [1, 2.5, "x"], "b": null}"#), (json!({"a": [1, 2.5, "x"], "b": null}), vec![]));
+    }
+
+    #[test]
+    fn extracts_from_prose_and_fences() {
+        let text = "Sure! Here is the slip:\n```json\n{\"bank\": \"ABC\", \"total\": 100}\n```\nLet me know {if} you need more.";
+        assert_eq!(repaired(text), (json!({"bank": "ABC", "total": 100}), vec![JsonRepair::ExtractedFromText]));
+    }
+
+    #[test]
+    fn fixes_common_syntax() {
+        let text = "{\n  // header\n  bank: 'ABC',\n  \"ok\": True, /* inline */ \"n\": None,\n  \"list\": [1, 2,],\n}";
+        let (value, repairs) = repaired(text);
+        assert_eq!(value, json!({"bank": "ABC", "ok": true, "n": null, "list": [1, 2]}));
+        assert_eq!(repairs, vec![
+            JsonRepair::RemovedComments,
+            JsonRepair::SingleQuotes,
+            JsonRepair::QuotedKeys,
+            JsonRepair::Literals,
+            JsonRepair::TrailingCommas,
+        ]);
+    }
+
+    #[test]
+    fn closes_truncated_output() {
+        let (value, repairs) = repaired(r#"{"bank": "ABC", "cassettes": [{"cassette": 1, "count": 25"#);
+        assert_eq!(value, json!({"bank": "ABC", "cassettes": [{"cassette": 1, "count": 25}]}));
+        assert_eq!(repairs, vec![JsonRepair::ClosedTruncated]);
+
+        let (value, _) = repaired(r#"{"bank": "AB"#);
+        assert_eq!(value, json!({"bank": "AB"}));
+        let (value, _) = repaired(r#"{"bank": "ABC", "total":"#);
+        assert_eq!(value, json!({"bank": "ABC", "total": null}));
+        let (value, _) = repaired(r#"{"total": 12.5e"#);
+        assert_eq!(value, json!({"total": 12.5}));
+    }
+
+    #[test]
+    fn fixes_missing_commas_and_numbers() {
+        let (value, repairs) = repaired(r#"{"a": 1 "b": +2, "c": .5, "atm": 007, "d": [1 2]}"#);
+        assert_eq!(value, json!({"a": 1, "b": 2, "c": 0.5, "atm": "007", "d": [1, 2]}));
+        assert_eq!(repairs, vec![JsonRepair::NumberFormat, JsonRepair::MissingCommas]);
+    }
+
+    #[test]
+    fn keeps_raw_control_characters_in_strings() {
+        let (value, repairs) = repaired("{\"text\": \"line 1\nline 2\"}");
+        assert_eq!(value, json!({"text": "line 1\nline 2"}));
+        assert_eq!(repairs, vec![JsonRepair::StringEscapes]);
+    }
+
+    #[test]
+    fn decodes_surrogate_pairs_while_repairing() {
+        let (value, _) = repaired(r#"{'emoji': "\ud83d\ude00", 'lone': "\ud83d"}"#);
+        assert_eq!(value, json!({"emoji": "\u{1f600}", "lone": "\u{fffd}"}));
+    }
+
+    #[test]
+    fn no_json_at_all() {
+        assert_eq!(recover("The image is too blurry to read."), None);
+        assert_eq!(recover(""), None);
+    }
+
+    fn arb_json() -> impl Strategy<Value = Value> {
+        let leaf = prop_oneof![
+            Just(Value::Null),
+            any::<bool>().prop_map(Value::Bool),
+            any::<i64>().prop_map(|n| json!(n)),
+            (-1e6f64..1e6).prop_map(|f| json!(f)),
+            ".{0,12}".prop_map(Value::String),
+        ];
+        leaf.prop_recursive(4, 32, 6, |inner| prop_oneof![
+            prop::collection::vec(inner.clone(), 0..6).prop_map(Value::Array),
+            prop::collection::btree_map("[a-z_]{1,8}", inner, 0..6)
+                .prop_map(|m| Value::Object(m.into_iter().collect())),
+        ])
+    }
+
+    fn arb_document() -> impl Strategy<Value = Value> {
+        prop::collection::btree_map("[a-z_]{1,8}", arb_json(), 0..6)
+            .prop_map(|m| Value::Object(m.into_iter().collect()))
+    }
+
+    // Serializes with a comma after the last member of every non-empty object and array
+    fn with_trailing_commas(value: &Value) -> String {
+        match value {
+            Value::Array(items) if !items.is_empty() => {
+                format!("[{},]", items.iter().map(with_trailing_commas).collect::<Vec<_>>().join(","))
+            }
+            Value::Object(map) if !map.is_empty() => {
+                let members: Vec<String> = map.iter()
+                    .map(|(k, v)| format!("{}:{}", Value::String(k.clone()), with_trailing_commas(v)))
+                    .collect();
+                format!("{{{},}}", members.join(","))
+            }
+            other => other.to_string(),
+        }
+    }
+
+    proptest! {
+        // Arbitrary text never panics, and whatever comes back is a JSON container
+        #[test]
+        fn never_panics(text in ".{0,200}") {
+            if let Some(r) = recover(&text) {
+                prop_assert!(r.value.is_object() || r.value.is_array());
+            }
+        }
+
+        #[test]
+        fn never_panics_on_json_like_noise(text in r#"[\{\}\[\]:,"' a-z0-9./*\\\n-]{0,120}"#) {
+            let _ = recover(&text);
+        }
+
+        #[test]
+        fn finds_documents_inside_prose(doc in arb_document(), before in "[a-zA-Z .!\n]{0,40}", after in "[a-zA-Z .!\n]{0,40}") {
+            let text = format!("{}{}{}", before, serde_json::to_string_pretty(&doc).unwrap(), after);
+            prop_assert_eq!(recover(&text).map(|r| r.value), Some(doc));
+        }
+
+        #[test]
+        fn trailing_commas_are_dropped(doc in arb_document()) {
+            prop_assert_eq!(recover(&with_trailing_commas(&doc)).map(|r| r.value), Some(doc));
+        }
+
+        // Cut anywhere, the result still parses and is a prefix-shaped object
+        #[test]
+        fn truncated_documents_still_parse(doc in arb_document(), cut in 0.0f64..1.0) {
+            let full = serde_json::to_string(&doc).unwrap();
+            let mut at = ((full.len() as f64) * cut) as usize;
+            while !full.is_char_boundary(at) {
+                at -= 1;
+            }
+            if let Some(r) = recover(&full[..at]) {
+                prop_assert!(r.value.is_object());
+            } else {
+                prop_assert!(at == 0);
+            }
+        }
+    }
+}
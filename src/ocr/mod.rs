@@ -1,3 +1,22 @@
 pub mod deepseek_ocr;
 pub mod copilot;
 pub mod azure_service;
+pub mod provider;
+pub mod document;
+pub mod jobs;
+pub mod webhook;
+pub mod history;
+pub mod cache;
+pub mod pipeline;
+pub mod analyze;
+pub mod preprocess;
+pub mod pages;
+pub mod graph_token;
+pub mod ollama_pool;
+pub mod schema;
+pub mod json_repair;
+pub mod counters;
+pub mod prompts;
+pub mod ollama_options;
+pub mod voting;
+pub mod hybrid;
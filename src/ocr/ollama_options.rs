@@ -0,0 +1,200 @@
+use std::collections::{BTreeMap, HashMap};
+use ollama_rs::{generation::{completion::{GenerationResponse, request::GenerationRequest}, parameters::{KeepAlive, TimeUnit}}, models::ModelOptions};
+use serde::{Deserialize, Serialize};
+
+// Sampling and runtime options sent with every Ollama generation. Unset options fall back to
+// the model defaults from config, then to Ollama's own defaults.
+#[derive(Deserialize, Serialize, Clone, Debug, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct GenerationOptions {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seed: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub num_ctx: Option<u64>,
+    // -1 generates until the model stops, -2 until the context is full
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub num_predict: Option<i32>,
+    // How long the model stays loaded: `30s`, `5m`, `1h`, a number of seconds, `0` or `-1` (forever)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub keep_alive: Option<String>,
+}
+
+impl GenerationOptions {
+    // `?temperature=`, `?seed=`, `?top_p=`, `?num_ctx=`, `?num_predict=` and `?keep_alive=`
+    pub fn from_query(params: &HashMap<String, String>) -> Result<Self, String> {
+        fn parse<T: std::str::FromStr>(params: &HashMap<String, String>, key: &str) -> Result<Option<T>, String> {
+            match params.get(key).map(|v| v.trim()).filter(|v| !v.is_empty()) {
+                Some(v) => v.parse().map(Some).map_err(|_| format!("invalid {}", key)),
+                None => Ok(None),
+            }
+        }
+
+        let options = Self {
+            temperature: parse(params, "temperature")?,
+            seed: parse(params, "seed")?,
+            top_p: parse(params, "top_p")?,
+            num_ctx: parse(params, "num_ctx")?,
+            num_predict: parse(params, "num_predict")?,
+            keep_alive: parse(params, "keep_alive")?,
+        };
+        match options.problems().as_slice() {
+            [] => Ok(options),
+            problems => Err(problems.join("; ")),
+        }
+    }
+
+    // Out of range values, named as in the query string and the config file
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        if let Some(t) = self.temperature && !(0.0..=2.0).contains(&t) {
+            problems.push(format!("temperature must be between 0 and 2, got {}", t));
+        }
+        if let Some(p) = self.top_p && !(p > 0.0 && p <= 1.0) {
+            problems.push(format!("top_p must be greater than 0 and at most 1, got {}", p));
+        }
+        if let Some(n) = self.num_ctx && !(1..=1_048_576).contains(&n) {
+            problems.push(format!("num_ctx must be between 1 and 1048576, got {}", n));
+        }
+        if let Some(n) = self.num_predict && n < 1 && n != -1 && n != -2 {
+            problems.push(format!("num_predict must be at least 1, or -1 / -2, got {}", n));
+        }
+        if let Some(k) = &self.keep_alive && parse_keep_alive(k).is_none() {
+            problems.push(format!("keep_alive must look like 30s, 5m, 1h, 0 or -1, got '{}'", k));
+        }
+        problems
+    }
+
+    // Options set here win, the others come from `fallback`
+    pub fn or(&self, fallback: &Self) -> Self {
+        Self {
+            temperature: self.temperature.or(fallback.temperature),
+            seed: self.seed.or(fallback.seed),
+            top_p: self.top_p.or(fallback.top_p),
+            num_ctx: self.num_ctx.or(fallback.num_ctx),
+            num_predict: self.num_predict.or(fallback.num_predict),
+            keep_alive: self.keep_alive.clone().or_else(|| fallback.keep_alive.clone()),
+        }
+    }
+
+    pub fn apply<'a>(&self, mut request: GenerationRequest<'a>) -> GenerationRequest<'a> {
+        let mut model = ModelOptions::default();
+        if let Some(v) = self.temperature { model = model.temperature(v); }
+        if let Some(v) = self.seed { model = model.seed(v); }
+        if let Some(v) = self.top_p { model = model.top_p(v); }
+        if let Some(v) = self.num_ctx { model = model.num_ctx(v); }
+        if let Some(v) = self.num_predict { model = model.num_predict(v); }
+        request = request.options(model);
+
+        if let Some(keep_alive) = self.keep_alive.as_deref().and_then(parse_keep_alive) {
+            request = request.keep_alive(keep_alive);
+        }
+        request
+    }
+}
+
+fn parse_keep_alive(value: &str) -> Option<KeepAlive> {
+    let value = value.trim();
+    let (number, unit) = match value.char_indices().last()? {
+        (i, 's') => (&value[..i], TimeUnit::Seconds),
+        (i, 'm') => (&value[..i], TimeUnit::Minutes),
+        (i, 'h') => (&value[..i], TimeUnit::Hours),
+        _ => (value, TimeUnit::Seconds),
+    };
+    match number.parse::<i64>().ok()? {
+        0 => Some(KeepAlive::UnloadOnCompletion),
+        n if n < 0 => Some(KeepAlive::Indefinitely),
+        n => Some(KeepAlive::Until { time: n as u64, unit }),
+    }
+}
+
+// `[ollama.defaults]` for every model, overridden by `[ollama.models."<name>"]`
+#[derive(Clone, Debug, Default)]
+pub struct ModelDefaults {
+    defaults: GenerationOptions,
+    models: BTreeMap<String, GenerationOptions>,
+}
+
+impl ModelDefaults {
+    pub fn new(defaults: GenerationOptions, models: BTreeMap<String, GenerationOptions>) -> Self {
+        Self { defaults, models }
+    }
+
+    // What a request for `model` runs with. `deepseek-ocr` also covers `deepseek-ocr:3b`.
+    pub fn effective(&self, model: &str, request: &GenerationOptions) -> GenerationOptions {
+        let base = model.split(':').next().unwrap_or(model);
+        let per_model = self.models.get(model).or_else(|| self.models.get(base));
+        let defaults = match per_model {
+            Some(options) => options.or(&self.defaults),
+            None => self.defaults.clone(),
+        };
+        request.or(&defaults)
+    }
+}
+
+// Effective options and Ollama's token counts and timings for one generation
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct GenerationInfo {
+    pub options: GenerationOptions,
+    pub prompt_eval_count: Option<u64>,
+    pub eval_count: Option<u64>,
+    pub total_duration_ms: Option<u64>,
+    pub load_duration_ms: Option<u64>,
+    pub prompt_eval_duration_ms: Option<u64>,
+    pub eval_duration_ms: Option<u64>,
+}
+
+impl GenerationInfo {
+    pub fn new(options: GenerationOptions, response: &GenerationResponse) -> Self {
+        // Ollama reports durations in nanoseconds
+        let ms = |ns: Option<u64>| ns.map(|ns| ns / 1_000_000);
+        Self {
+            options,
+            prompt_eval_count: response.prompt_eval_count,
+            eval_count: response.eval_count,
+            total_duration_ms: ms(response.total_duration),
+            load_duration_ms: ms(response.load_duration),
+            prompt_eval_duration_ms: ms(response.prompt_eval_duration),
+            eval_duration_ms: ms(response.eval_duration),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn query(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    #[test]
+    fn request_options_are_parsed_and_validated() {
+        let options = GenerationOptions::from_query(&query(&[("temperature", "0"), ("seed", "42"), ("keep_alive", "10m"), ("num_predict", "-1")])).unwrap();
+        assert_eq!(options.temperature, Some(0.0));
+        assert_eq!(options.seed, Some(42));
+        assert!(matches!(parse_keep_alive("10m"), Some(KeepAlive::Until { time: 10, unit: TimeUnit::Minutes })));
+        assert!(matches!(parse_keep_alive("-1"), Some(KeepAlive::Indefinitely)));
+
+        assert_eq!(GenerationOptions::from_query(&query(&[("seed", "abc")])).unwrap_err(), "invalid seed");
+        let error = GenerationOptions::from_query(&query(&[("temperature", "3"), ("top_p", "0"), ("keep_alive", "soon")])).unwrap_err();
+        assert_eq!(error.matches(" must ").count(), 3, "{}", error);
+    }
+
+    #[test]
+    fn request_wins_over_model_and_global_defaults() {
+        let defaults = ModelDefaults::new(
+            GenerationOptions { temperature: Some(0.2), num_ctx: Some(4096), ..Default::default() },
+            BTreeMap::from([("deepseek-ocr".to_string(), GenerationOptions { num_ctx: Some(8192), seed: Some(7), ..Default::default() })]),
+        );
+        let request = GenerationOptions { seed: Some(1), ..Default::default() };
+
+        let effective = defaults.effective("deepseek-ocr:3b", &request);
+        assert_eq!(effective, GenerationOptions { temperature: Some(0.2), seed: Some(1), num_ctx: Some(8192), ..Default::default() });
+        assert_eq!(defaults.effective("llava", &request).num_ctx, Some(4096));
+    }
+}
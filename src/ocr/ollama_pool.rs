@@ -0,0 +1,331 @@
+use std::{sync::{Arc, Mutex, atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering}}, time::Duration};
+use axum::{Json, extract::State, http::StatusCode, response::{IntoResponse, Response}};
+use ollama_rs::{Ollama, error::OllamaError, generation::completion::{GenerationResponse, request::GenerationRequest}};
+use serde::{Deserialize, Serialize};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use crate::{constant::ApiResponse, error::AppError, state::AppState};
+
+// Health probes must answer quickly, a busy box still lists its models in milliseconds
+const HEALTH_CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum BalanceStrategy {
+    // Fewest requests in flight relative to the instance limit
+    #[default]
+    LeastBusy,
+    RoundRobin,
+}
+
+// One Ollama server with its own concurrency limit and health state
+pub struct OllamaInstance {
+    pub url: String,
+    client: Ollama,
+    max_concurrency: usize,
+    permits: Arc<Semaphore>,
+    in_flight: AtomicUsize,
+    healthy: AtomicBool,
+    requests: AtomicU64,
+    failures: AtomicU64,
+    last_error: Mutex<Option<String>>,
+}
+
+impl OllamaInstance {
+    pub fn new(url: &str, max_concurrency: usize) -> Result<Self, String> {
+        let client = Ollama::try_new(url).map_err(|e| format!("invalid Ollama URL '{}': {}", url, e))?;
+        Ok(Self {
+            url: url.to_string(),
+            client,
+            max_concurrency,
+            permits: Arc::new(Semaphore::new(max_concurrency)),
+            in_flight: AtomicUsize::new(0),
+            // Assumed up until the first health check says otherwise
+            healthy: AtomicBool::new(true),
+            requests: AtomicU64::new(0),
+            failures: AtomicU64::new(0),
+            last_error: Mutex::new(None),
+        })
+    }
+
+    fn is_healthy(&self) -> bool {
+        self.healthy.load(Ordering::Relaxed)
+    }
+
+    // Fraction of the limit in use, scaled to compare instances with different limits
+    fn load(&self) -> usize {
+        self.in_flight.load(Ordering::Relaxed) * 1000 / self.max_concurrency
+    }
+
+    fn mark(&self, healthy: bool, error: Option<String>) {
+        self.healthy.store(healthy, Ordering::Relaxed);
+        if let Some(error) = error {
+            *self.last_error.lock().unwrap() = Some(error);
+        }
+    }
+
+    async fn check_health(&self) {
+        match tokio::time::timeout(HEALTH_CHECK_TIMEOUT, self.client.list_local_models()).await {
+            Ok(Ok(_)) => self.mark(true, None),
+            Ok(Err(e)) => self.mark(false, Some(format!("health check failed: {}", e))),
+            Err(_) => self.mark(false, Some("health check timed out".to_string())),
+        }
+    }
+
+    fn status(&self) -> InstanceStatus {
+        InstanceStatus {
+            url: self.url.clone(),
+            healthy: self.is_healthy(),
+            in_flight: self.in_flight.load(Ordering::Relaxed),
+            max_concurrency: self.max_concurrency,
+            requests: self.requests.load(Ordering::Relaxed),
+            failures: self.failures.load(Ordering::Relaxed),
+            last_error: self.last_error.lock().unwrap().clone(),
+        }
+    }
+}
+
+// Counts a request against the instance until dropped
+struct Lease {
+    instance: Arc<OllamaInstance>,
+    _permit: OwnedSemaphorePermit,
+}
+
+impl Lease {
+    fn new(instance: Arc<OllamaInstance>, permit: OwnedSemaphorePermit) -> Self {
+        instance.in_flight.fetch_add(1, Ordering::Relaxed);
+        instance.requests.fetch_add(1, Ordering::Relaxed);
+        Self { instance, _permit: permit }
+    }
+}
+
+impl Drop for Lease {
+    fn drop(&mut self) {
+        self.instance.in_flight.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+#[derive(Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct InstanceStatus {
+    pub url: String,
+    pub healthy: bool,
+    pub in_flight: usize,
+    pub max_concurrency: usize,
+    pub requests: u64,
+    pub failures: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+}
+
+// Spreads generations over several Ollama servers and fails over when one errors or times out
+pub struct OllamaPool {
+    instances: Vec<Arc<OllamaInstance>>,
+    strategy: BalanceStrategy,
+    next: AtomicUsize,
+    timeout: Duration,
+    // Longest wait for a slot before the request is turned away
+    queue_timeout: Duration,
+}
+
+impl OllamaPool {
+    pub fn new(instances: Vec<OllamaInstance>, strategy: BalanceStrategy, timeout: Duration, queue_timeout: Duration) -> Self {
+        Self {
+            instances: instances.into_iter().map(Arc::new).collect(),
+            strategy,
+            next: AtomicUsize::new(0),
+            timeout,
+            queue_timeout,
+        }
+    }
+
+    // Probes every instance on a fixed interval for the lifetime of the process
+    pub fn start_health_checks(self: &Arc<Self>, interval: Duration) {
+        let pool = self.clone();
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+                futures_util::future::join_all(pool.instances.iter().map(|i| i.check_health())).await;
+            }
+        });
+    }
+
+    pub fn status(&self) -> Vec<InstanceStatus> {
+        self.instances.iter().map(|i| i.status()).collect()
+    }
+
+    // Instances in the order they should be tried: healthy ones by strategy, unhealthy ones last
+    fn candidates(&self) -> Vec<Arc<OllamaInstance>> {
+        let n = self.instances.len();
+        let start = self.next.fetch_add(1, Ordering::Relaxed) % n.max(1);
+        let mut ordered: Vec<_> = (0..n).map(|i| self.instances[(start + i) % n].clone()).collect();
+        if self.strategy == BalanceStrategy::LeastBusy {
+            // Stable sort keeps the rotation as tie-breaker between equally loaded instances
+            ordered.sort_by_key(|i| i.load());
+        }
+        ordered.sort_by_key(|i| !i.is_healthy());
+        ordered
+    }
+
+    // Takes a free slot on the first candidate that has one, otherwise waits for the preferred one,
+    // at most `queue_timeout`
+    async fn lease(&self, candidates: &[Arc<OllamaInstance>]) -> Result<Lease, AppError> {
+        for instance in candidates {
+            if let Ok(permit) = instance.permits.clone().try_acquire_owned() {
+                return Ok(Lease::new(instance.clone(), permit));
+            }
+        }
+        let preferred = candidates[0].clone();
+        match tokio::time::timeout(self.queue_timeout, preferred.permits.clone().acquire_owned()).await {
+            Ok(permit) => Ok(Lease::new(preferred, permit.expect("Ollama semaphore is never closed"))),
+            Err(_) => Err(AppError::QueueFull(format!(
+                "Every Ollama instance is busy, no slot freed up within {}s", self.queue_timeout.as_secs()
+            ))),
+        }
+    }
+
+    // Runs the request on one instance after another until one answers. Returns the answer and
+    // the URL of the instance that produced it.
+    pub async fn generate(&self, request: GenerationRequest<'static>) -> Result<(GenerationResponse, String), AppError> {
+        let mut candidates = self.candidates();
+        let mut last_error = None;
+
+        while !candidates.is_empty() {
+            let lease = self.lease(&candidates).await?;
+            let instance = lease.instance.clone();
+            candidates.retain(|c| !Arc::ptr_eq(c, &instance));
+
+            let outcome = tokio::time::timeout(self.timeout, instance.client.generate(request.clone())).await;
+            drop(lease);
+
+            let error = match outcome {
+                Ok(Ok(res)) => {
+                    instance.mark(true, None);
+                    return Ok((res, instance.url.clone()));
+                }
+                Err(_) => {
+                    instance.mark(false, Some("generation timed out".to_string()));
+                    AppError::ProviderTimeout(format!("Ollama at {} did not answer within {}s", instance.url, self.timeout.as_secs()))
+                }
+                Ok(Err(e)) => {
+                    let error = ollama_error(e);
+                    // A refused request says nothing about the health of the server
+                    let reachable = matches!(error, AppError::ProviderRejected(_) | AppError::ParseFailure(_));
+                    instance.mark(reachable, Some(error.to_string()));
+                    error
+                }
+            };
+            instance.failures.fetch_add(1, Ordering::Relaxed);
+            tracing::warn!("Ollama at {} failed, trying next instance: {}", instance.url, error);
+            last_error = Some(error);
+        }
+
+        Err(last_error.unwrap_or_else(|| AppError::ProviderUnavailable("No Ollama instance configured".to_string())))
+    }
+}
+
+fn ollama_error(e: OllamaError) -> AppError {
+    match e {
+        // Ollama reports unknown models and unreadable images this way
+        OllamaError::InternalError(inner) => AppError::ProviderRejected(format!("Ollama error: {}", inner.message)),
+        OllamaError::JsonError(e) => AppError::ParseFailure(format!("Ollama response could not be parsed: {}", e)),
+        other => AppError::Provider(format!("Ollama error: {}", other)),
+    }
+}
+
+pub async fn get_instances(State(state): State<AppState>) -> Response {
+    (StatusCode::OK, Json(ApiResponse::success(state.ollama.status(), "Ollama instances"))).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{Router, routing::post};
+    use serde_json::json;
+
+    fn pool(limits: &[usize], strategy: BalanceStrategy, queue_timeout: Duration) -> OllamaPool {
+        let instances = limits.iter().enumerate()
+            .map(|(i, limit)| OllamaInstance::new(&format!("http://ollama-{}:11434", i), *limit).unwrap())
+            .collect();
+        OllamaPool::new(instances, strategy, Duration::from_secs(5), queue_timeout)
+    }
+
+    fn order(candidates: &[Arc<OllamaInstance>]) -> Vec<&str> {
+        candidates.iter().map(|c| c.url.trim_start_matches("http://ollama-").trim_end_matches(":11434")).collect()
+    }
+
+    // An Ollama stand-in answering /api/generate with the given status
+    async fn ollama(status: StatusCode) -> String {
+        let app = Router::new().route("/api/generate", post(move || async move {
+            let body = json!({ "model": "m", "created_at": "2024-01-01T00:00:00Z", "response": "{}", "done": true });
+            (status, Json(body))
+        }));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://127.0.0.1:{}", listener.local_addr().unwrap().port());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        url
+    }
+
+    #[test]
+    fn least_busy_prefers_spare_capacity_and_unhealthy_instances_go_last() {
+        let pool = pool(&[2, 4, 1], BalanceStrategy::LeastBusy, Duration::from_secs(1));
+        pool.instances[0].in_flight.store(1, Ordering::Relaxed);
+        pool.instances[1].in_flight.store(1, Ordering::Relaxed);
+        // Loads: half, a quarter, idle
+        assert_eq!(order(&pool.candidates()), ["2", "1", "0"]);
+
+        pool.instances[2].mark(false, Some("down".to_string()));
+        assert_eq!(order(&pool.candidates()), ["1", "0", "2"]);
+    }
+
+    #[test]
+    fn round_robin_rotates_the_starting_instance() {
+        let pool = pool(&[1, 1, 1], BalanceStrategy::RoundRobin, Duration::from_secs(1));
+        assert_eq!(order(&pool.candidates()), ["0", "1", "2"]);
+        assert_eq!(order(&pool.candidates()), ["1", "2", "0"]);
+
+        pool.instances[0].mark(false, None);
+        assert_eq!(order(&pool.candidates()), ["2", "1", "0"]);
+    }
+
+    #[tokio::test]
+    async fn a_full_instance_falls_back_to_the_next_and_waiting_is_bounded() {
+        let pool = pool(&[1, 1], BalanceStrategy::RoundRobin, Duration::from_millis(50));
+        let candidates = pool.instances.clone();
+
+        let first = pool.lease(&candidates).await.unwrap();
+        let second = pool.lease(&candidates).await.unwrap();
+        assert_eq!(order(&[first.instance.clone(), second.instance.clone()]), ["0", "1"]);
+        assert_eq!(pool.status().iter().map(|s| s.in_flight).collect::<Vec<_>>(), [1, 1]);
+
+        let busy = pool.lease(&candidates).await.err().unwrap();
+        assert_eq!(busy.code().http_status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        drop(first);
+        let third = pool.lease(&candidates).await.unwrap();
+        assert_eq!(third.instance.url, candidates[0].url);
+        assert_eq!(pool.status()[0].requests, 2);
+    }
+
+    #[tokio::test]
+    async fn a_failing_instance_is_skipped_and_marked_down() {
+        let (broken, working) = (ollama(StatusCode::INTERNAL_SERVER_ERROR).await, ollama(StatusCode::OK).await);
+        let instances = vec![OllamaInstance::new(&broken, 1).unwrap(), OllamaInstance::new(&working, 1).unwrap()];
+        let pool = OllamaPool::new(instances, BalanceStrategy::RoundRobin, Duration::from_secs(5), Duration::from_secs(1));
+
+        let (response, url) = pool.generate(GenerationRequest::new("m".to_string(), "p")).await.unwrap();
+        assert_eq!((response.response.as_str(), url.as_str()), ("{}", working.as_str()));
+
+        let status = pool.status();
+        assert!(!status[0].healthy && status[1].healthy);
+        assert_eq!((status[0].failures, status[1].failures), (1, 0));
+        assert_eq!(status.iter().map(|s| s.in_flight).sum::<usize>(), 0);
+        // Down instances are tried last from now on
+        assert_eq!(pool.candidates()[0].url, working);
+
+        let only_broken = OllamaPool::new(vec![OllamaInstance::new(&broken, 1).unwrap()], BalanceStrategy::RoundRobin, Duration::from_secs(5), Duration::from_secs(1));
+        let error = only_broken.generate(GenerationRequest::new("m".to_string(), "p")).await.err().unwrap();
+        assert_eq!(error.code().http_status(), StatusCode::BAD_GATEWAY);
+    }
+}
@@ -0,0 +1,423 @@
+use std::{fmt, io::Cursor};
+use axum::body::Bytes;
+use image::{DynamicImage, GrayImage, ImageFormat, RgbImage};
+use serde::Serialize;
+use tiff::{ColorType, decoder::{Decoder, DecodingResult}};
+
+use crate::{error::AppError, ocr::provider::OcrResult};
+
+// Upper bound on pages sent through a provider for one request
+pub const MAX_PAGES: usize = 50;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum InputFormat {
+    Image,
+    Pdf,
+    Tiff,
+}
+
+impl InputFormat {
+    // Sniffed from the magic bytes, the Content-Type of raw uploads is not reliable
+    pub fn detect(input: &[u8]) -> Self {
+        if input.starts_with(b"%PDF-") {
+            InputFormat::Pdf
+        } else if input.starts_with(b"II*\0") || input.starts_with(b"MM\0*") {
+            InputFormat::Tiff
+        } else {
+            InputFormat::Image
+        }
+    }
+
+    pub fn is_multi_page(&self) -> bool {
+        *self != InputFormat::Image
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            InputFormat::Image => "image",
+            InputFormat::Pdf => "pdf",
+            InputFormat::Tiff => "tiff",
+        }
+    }
+}
+
+// `?pages=1-3,5` (1-based, inclusive ranges), same syntax Document Intelligence accepts
+#[derive(Clone, Debug, PartialEq)]
+pub struct PageSelection(Vec<(u32, u32)>);
+
+impl PageSelection {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let invalid = || format!("invalid pages '{}', expected e.g. 1-3,5", value);
+
+        let mut ranges = Vec::new();
+        for part in value.split(',').map(|p| p.trim()).filter(|p| !p.is_empty()) {
+            let (from, to) = match part.split_once('-') {
+                Some((from, to)) => (from.trim().parse::<u32>(), to.trim().parse::<u32>()),
+                None => (part.parse::<u32>(), part.parse::<u32>()),
+            };
+            match (from, to) {
+                (Ok(from), Ok(to)) if from >= 1 && from <= to => ranges.push((from, to)),
+                _ => return Err(invalid()),
+            }
+        }
+
+        if ranges.is_empty() {
+            return Err(invalid());
+        }
+        Ok(Self(ranges))
+    }
+
+    pub fn contains(&self, page: u32) -> bool {
+        self.0.iter().any(|(from, to)| page >= *from && page <= *to)
+    }
+}
+
+impl fmt::Display for PageSelection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let parts: Vec<String> = self.0.iter()
+            .map(|(from, to)| if from == to { from.to_string() } else { format!("{}-{}", from, to) })
+            .collect();
+        write!(f, "{}", parts.join(","))
+    }
+}
+
+// One entry per selected page; a page that could not be extracted or analyzed carries an error
+// instead of failing the whole document
+#[derive(Serialize, Clone, Debug)]
+pub struct PageResult {
+    pub page: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result: Option<OcrResult>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct MultiPageResult {
+    pub format: String,
+    // Pages in the uploaded file, before `?pages=` selection
+    pub page_count: u32,
+    // True when the whole file went to the provider in one call (Document Intelligence)
+    pub native: bool,
+    pub pages: Vec<PageResult>,
+    // Document-level extraction from a native call; it spans pages so it is not split
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data: Option<serde_json::Value>,
+}
+
+// Page number and its encoded image, or why it could not be extracted
+pub type ExtractedPage = (u32, Result<Bytes, String>);
+
+// Splits a PDF or TIFF into one encoded image per selected page.
+// Returns the total page count and, per selected page, its image or why it could not be extracted.
+pub fn split(input: &[u8], format: InputFormat, selection: Option<&PageSelection>) -> Result<(u32, Vec<ExtractedPage>), AppError> {
+    let (count, pages) = match format {
+        InputFormat::Pdf => split_pdf(input, selection)?,
+        InputFormat::Tiff => split_tiff(input, selection)?,
+        InputFormat::Image => (1, vec![(1, Ok(Bytes::copy_from_slice(input)))]),
+    };
+
+    if pages.is_empty() {
+        return Err(AppError::InvalidPayload(format!("No pages selected, the document has {} page(s)", count)));
+    }
+    Ok((count, pages))
+}
+
+// Total pages without extracting them, for documents sent to the provider natively
+pub fn count(input: &[u8], format: InputFormat) -> Result<u32, AppError> {
+    match format {
+        InputFormat::Image => Ok(1),
+        InputFormat::Pdf => lopdf::Document::load_mem(input)
+            .map(|doc| doc.get_pages().len() as u32)
+            .map_err(|e| AppError::UnsupportedMedia(format!("Invalid PDF: {}", e))),
+        InputFormat::Tiff => {
+            let mut decoder = Decoder::new(Cursor::new(input)).map_err(|e| AppError::UnsupportedMedia(format!("Invalid TIFF: {}", e)))?;
+            let mut count = 1;
+            while decoder.more_images() {
+                decoder.next_image().map_err(|e| AppError::UnsupportedMedia(format!("Invalid TIFF frame {}: {}", count + 1, e)))?;
+                count += 1;
+            }
+            Ok(count)
+        }
+    }
+}
+
+fn too_many_pages() -> AppError {
+    AppError::InvalidPayload(format!("Too many pages, select at most {} with ?pages=", MAX_PAGES))
+}
+
+// Splits a native multi-page result back into pages. Fields stay on the document (`data`).
+pub fn split_result(result: &OcrResult) -> Vec<PageResult> {
+    result.document.pages.iter()
+        .map(|page| {
+            let mut document = result.document.clone();
+            document.pages = vec![page.clone()];
+            document.fields = Vec::new();
+
+            PageResult {
+                page: page.number,
+                result: Some(OcrResult {
+                    text: document.text(),
+                    document,
+                    data: None,
+                    raw: serde_json::Value::Null,
+                    ..result.clone()
+                }),
+                error: None,
+            }
+        })
+        .collect()
+}
+
+fn split_tiff(input: &[u8], selection: Option<&PageSelection>) -> Result<(u32, Vec<ExtractedPage>), AppError> {
+    let mut decoder = Decoder::new(Cursor::new(input)).map_err(|e| AppError::UnsupportedMedia(format!("Invalid TIFF: {}", e)))?;
+
+    let mut pages = Vec::new();
+    let mut number = 1u32;
+    loop {
+        if selection.is_none_or(|s| s.contains(number)) {
+            if pages.len() == MAX_PAGES {
+                return Err(too_many_pages());
+            }
+            pages.push((number, tiff_frame(&mut decoder)));
+        }
+        if !decoder.more_images() {
+            break;
+        }
+        decoder.next_image().map_err(|e| AppError::UnsupportedMedia(format!("Invalid TIFF frame {}: {}", number + 1, e)))?;
+        number += 1;
+    }
+
+    Ok((number, pages))
+}
+
+fn tiff_frame(decoder: &mut Decoder<Cursor<&[u8]>>) -> Result<Bytes, String> {
+    let (width, height) = decoder.dimensions().map_err(|e| e.to_string())?;
+    let color = decoder.colortype().map_err(|e| e.to_string())?;
+    let data = decoder.read_image().map_err(|e| e.to_string())?;
+
+    // WhiteIsZero is already inverted by the decoder, 0 is always black here
+    let image = match (color, data) {
+        (ColorType::Gray(8), DecodingResult::U8(buf)) => GrayImage::from_raw(width, height, buf).map(DynamicImage::ImageLuma8),
+        (ColorType::Gray(16), DecodingResult::U16(buf)) => GrayImage::from_raw(width, height, buf.iter().map(|v| (v >> 8) as u8).collect()).map(DynamicImage::ImageLuma8),
+        (ColorType::Gray(1), DecodingResult::U8(buf)) => Some(DynamicImage::ImageLuma8(unpack_bits(&buf, width, height))),
+        (ColorType::RGB(8), DecodingResult::U8(buf)) => RgbImage::from_raw(width, height, buf).map(DynamicImage::ImageRgb8),
+        (ColorType::RGBA(8), DecodingResult::U8(buf)) => image::RgbaImage::from_raw(width, height, buf).map(DynamicImage::ImageRgba8),
+        (color, _) => return Err(format!("Unsupported TIFF color type {:?}", color)),
+    };
+
+    encode_png(&image.ok_or("TIFF frame size does not match its dimensions")?)
+}
+
+fn split_pdf(input: &[u8], selection: Option<&PageSelection>) -> Result<(u32, Vec<ExtractedPage>), AppError> {
+    let doc = lopdf::Document::load_mem(input).map_err(|e| AppError::UnsupportedMedia(format!("Invalid PDF: {}", e)))?;
+    let page_ids = doc.get_pages();
+
+    let selected: Vec<_> = page_ids.iter()
+        .filter(|(number, _)| selection.is_none_or(|s| s.contains(**number)))
+        .collect();
+    if selected.len() > MAX_PAGES {
+        return Err(too_many_pages());
+    }
+
+    let pages = selected.into_iter()
+        .map(|(number, id)| (*number, pdf_page_image(&doc, *id)))
+        .collect();
+
+    Ok((page_ids.len() as u32, pages))
+}
+
+// Scanned PDFs carry each page as one image XObject; the largest image on the page is the scan.
+// Pages with only vector text are not rendered here, Document Intelligence reads those natively.
+fn pdf_page_image(doc: &lopdf::Document, page_id: lopdf::ObjectId) -> Result<Bytes, String> {
+    let images = doc.get_page_images(page_id).map_err(|e| format!("Unreadable page: {}", e))?;
+    let scan = images.iter()
+        .max_by_key(|img| img.width * img.height)
+        .ok_or("Page has no scanned image; text PDFs are only supported with provider=azure-document")?;
+
+    let filters = scan.filters.clone().unwrap_or_default();
+    match filters.last().map(|f| f.as_str()) {
+        // JPEG stream, usable as is
+        Some("DCTDecode") if filters.len() == 1 => return Ok(Bytes::copy_from_slice(scan.content)),
+        None | Some("FlateDecode") | Some("LZWDecode") => {}
+        Some(other) => return Err(format!("Unsupported page image encoding {}", other)),
+    }
+
+    let (width, height) = (scan.width as u32, scan.height as u32);
+    let stream = doc.get_object(scan.id)
+        .and_then(|o| o.as_stream())
+        .map_err(|e| format!("Unreadable page image: {}", e))?;
+    // Never inflate past what an 8-bit RGB bitmap of this size needs
+    let limit = width as usize * height as usize * 3 + 1024;
+    let data = if filters.is_empty() {
+        stream.content.clone()
+    } else {
+        stream.decompressed_content_with_limit(limit).map_err(|e| format!("Unreadable page image: {}", e))?
+    };
+
+    let pixels = width as usize * height as usize;
+    let image = match (scan.bits_per_component, components(doc, scan)?) {
+        _ if pixels == 0 => None,
+        (Some(1), 1) => Some(DynamicImage::ImageLuma8(unpack_bits(&data, width, height))),
+        (Some(8), 3) if data.len() >= pixels * 3 => RgbImage::from_raw(width, height, data[..pixels * 3].to_vec()).map(DynamicImage::ImageRgb8),
+        (Some(8), 1) if data.len() >= pixels => GrayImage::from_raw(width, height, data[..pixels].to_vec()).map(DynamicImage::ImageLuma8),
+        _ => None,
+    };
+
+    encode_png(&image.ok_or("Unsupported page image layout")?)
+}
+
+// Samples per pixel of a bitmap we can decode: gray or RGB. CMYK, palettes and spot colors are refused
+// rather than misread. Image masks have no color space and are 1-bit gray.
+fn components(doc: &lopdf::Document, scan: &lopdf::xobject::PdfImage) -> Result<u8, String> {
+    if scan.origin_dict.get(b"ImageMask").and_then(|m| m.as_bool()).unwrap_or(false) {
+        return Ok(1);
+    }
+    let resolve = |object| doc.dereference(object).ok().map(|(_, object)| object);
+    let color_space = scan.origin_dict.get(b"ColorSpace").ok().and_then(resolve)
+        .ok_or("Page image has no color space")?;
+    // `/DeviceRGB`, or an array such as `[/ICCBased 12 0 R]`
+    let (name, params) = match color_space {
+        lopdf::Object::Name(name) => (name.as_slice(), None),
+        lopdf::Object::Array(array) => (array.first().and_then(|n| n.as_name().ok()).unwrap_or_default(), array.get(1)),
+        _ => (&b""[..], None),
+    };
+
+    match name {
+        b"DeviceGray" | b"CalGray" => Ok(1),
+        b"DeviceRGB" | b"CalRGB" => Ok(3),
+        // ICC profiles declare their component count as /N on the profile stream
+        b"ICCBased" => match params.and_then(resolve).and_then(|p| p.as_stream().ok()).and_then(|p| p.dict.get(b"N").ok()).and_then(|n| n.as_i64().ok()) {
+            Some(1) => Ok(1),
+            Some(3) => Ok(3),
+            Some(n) => Err(format!("Unsupported page image color space ICCBased with {} components", n)),
+            None => Err("Unreadable page image color profile".to_string()),
+        },
+        other => Err(format!("Unsupported page image color space {}", String::from_utf8_lossy(other))),
+    }
+}
+
+// 1 bit per pixel, rows padded to whole bytes, 1 = white
+fn unpack_bits(packed: &[u8], width: u32, height: u32) -> GrayImage {
+    let row_bytes = width.div_ceil(8) as usize;
+    GrayImage::from_fn(width, height, |x, y| {
+        let byte = packed.get(y as usize * row_bytes + x as usize / 8).copied().unwrap_or(0xFF);
+        let bit = (byte >> (7 - (x % 8))) & 1;
+        image::Luma([if bit == 1 { 255 } else { 0 }])
+    })
+}
+
+fn encode_png(image: &DynamicImage) -> Result<Bytes, String> {
+    let mut out = Cursor::new(Vec::new());
+    image.write_to(&mut out, ImageFormat::Png).map_err(|e| e.to_string())?;
+    Ok(Bytes::from(out.into_inner()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lopdf::{Document, Object, Stream, dictionary};
+    use tiff::encoder::{TiffEncoder, colortype};
+
+    fn decoded(page: &ExtractedPage) -> DynamicImage {
+        image::load_from_memory(page.1.as_ref().unwrap()).unwrap()
+    }
+
+    // One-page PDF whose page is a single 2x1 image XObject
+    fn scanned_pdf(color_space: Object, samples: Vec<u8>) -> Vec<u8> {
+        let mut doc = Document::with_version("1.5");
+        let pages_id = doc.new_object_id();
+        let image_id = doc.add_object(Stream::new(dictionary! {
+            "Type" => "XObject", "Subtype" => "Image", "Width" => 2, "Height" => 1,
+            "ColorSpace" => color_space, "BitsPerComponent" => 8,
+        }, samples));
+        let content_id = doc.add_object(Stream::new(dictionary! {}, b"q 2 0 0 1 0 0 cm /Im0 Do Q".to_vec()));
+        let page_id = doc.add_object(dictionary! {
+            "Type" => "Page", "Parent" => pages_id, "Contents" => content_id,
+            "MediaBox" => vec![0.into(), 0.into(), 2.into(), 1.into()],
+            "Resources" => dictionary! { "XObject" => dictionary! { "Im0" => image_id } },
+        });
+        doc.objects.insert(pages_id, Object::Dictionary(dictionary! { "Type" => "Pages", "Kids" => vec![page_id.into()], "Count" => 1 }));
+        let catalog_id = doc.add_object(dictionary! { "Type" => "Catalog", "Pages" => pages_id });
+        doc.trailer.set("Root", catalog_id);
+
+        let mut out = Vec::new();
+        doc.save_to(&mut out).unwrap();
+        out
+    }
+
+    #[test]
+    fn page_selection_parses_ranges_and_rejects_the_rest() {
+        let selection = PageSelection::parse(" 1-3, 5 ,").unwrap();
+        assert_eq!(selection, PageSelection(vec![(1, 3), (5, 5)]));
+        assert_eq!(selection.to_string(), "1-3,5");
+        assert!([1, 2, 3, 5].iter().all(|p| selection.contains(*p)));
+        assert!(![0, 4, 6].iter().any(|p| selection.contains(*p)));
+
+        for invalid in ["", " , ", "0", "0-2", "3-1", "a", "1-", "-2"] {
+            assert!(PageSelection::parse(invalid).is_err(), "{:?}", invalid);
+        }
+    }
+
+    #[test]
+    fn formats_are_detected_from_magic_bytes() {
+        assert_eq!(InputFormat::detect(b"%PDF-1.7\n"), InputFormat::Pdf);
+        assert_eq!(InputFormat::detect(b"II*\0\x08\0\0\0"), InputFormat::Tiff);
+        assert_eq!(InputFormat::detect(b"MM\0*\0\0\0\x08"), InputFormat::Tiff);
+        assert_eq!(InputFormat::detect(b"\x89PNG\r\n\x1a\n"), InputFormat::Image);
+        assert_eq!(InputFormat::detect(b"%PD"), InputFormat::Image);
+        assert!(!InputFormat::Image.is_multi_page());
+    }
+
+    #[test]
+    fn packed_rows_are_padded_to_whole_bytes() {
+        // 10 pixels wide: two bytes per row, the last 6 bits of each row are padding
+        let packed = [0b1010_1010, 0b0100_0000, 0b0000_0000, 0b1111_1111];
+        let image = unpack_bits(&packed, 10, 2);
+        let row = |y| (0..10).map(|x| image.get_pixel(x, y)[0]).collect::<Vec<_>>();
+        assert_eq!(row(0), [255, 0, 255, 0, 255, 0, 255, 0, 0, 255]);
+        assert_eq!(row(1), [0, 0, 0, 0, 0, 0, 0, 0, 255, 255]);
+        // Missing data reads as white
+        assert_eq!(unpack_bits(&[0x00], 8, 2).get_pixel(0, 1)[0], 255);
+    }
+
+    #[test]
+    fn every_tiff_frame_becomes_a_page() {
+        let mut tiff = Cursor::new(Vec::new());
+        let mut encoder = TiffEncoder::new(&mut tiff).unwrap();
+        encoder.write_image::<colortype::Gray8>(4, 2, &[0, 50, 100, 150, 200, 250, 255, 0]).unwrap();
+        encoder.write_image::<colortype::RGB8>(3, 1, &[255, 0, 0, 0, 255, 0, 0, 0, 255]).unwrap();
+        let tiff = tiff.into_inner();
+        assert_eq!(InputFormat::detect(&tiff), InputFormat::Tiff);
+        assert_eq!(count(&tiff, InputFormat::Tiff).unwrap(), 2);
+
+        let (total, pages) = split(&tiff, InputFormat::Tiff, None).unwrap();
+        assert_eq!((total, pages.iter().map(|p| p.0).collect::<Vec<_>>()), (2, vec![1, 2]));
+        let first = decoded(&pages[0]);
+        assert_eq!((first.width(), first.height()), (4, 2));
+        assert_eq!(first.to_luma8().get_pixel(1, 0)[0], 50);
+        assert_eq!(decoded(&pages[1]).to_rgb8().get_pixel(2, 0).0, [0, 0, 255]);
+
+        let only_second = PageSelection::parse("2").unwrap();
+        let (total, pages) = split(&tiff, InputFormat::Tiff, Some(&only_second)).unwrap();
+        assert_eq!((total, pages.len(), pages[0].0), (2, 1, 2));
+        assert!(split(&tiff, InputFormat::Tiff, Some(&PageSelection::parse("3").unwrap())).is_err());
+    }
+
+    #[test]
+    fn pdf_images_are_decoded_by_color_space() {
+        let gray = scanned_pdf(Object::Name(b"DeviceGray".to_vec()), vec![10, 240]);
+        let (_, pages) = split(&gray, InputFormat::Pdf, None).unwrap();
+        assert_eq!(decoded(&pages[0]).to_luma8().into_raw(), [10, 240]);
+
+        let rgb = scanned_pdf(Object::Name(b"DeviceRGB".to_vec()), vec![255, 0, 0, 0, 0, 255]);
+        let (_, pages) = split(&rgb, InputFormat::Pdf, None).unwrap();
+        assert_eq!(decoded(&pages[0]).to_rgb8().into_raw(), [255, 0, 0, 0, 0, 255]);
+
+        // Eight CMYK bytes would otherwise pass for 2 RGB pixels
+        let cmyk = scanned_pdf(Object::Name(b"DeviceCMYK".to_vec()), vec![0, 0, 0, 255, 0, 255, 255, 0]);
+        let (_, pages) = split(&cmyk, InputFormat::Pdf, None).unwrap();
+        assert_eq!(pages[0].1, Err("Unsupported page image color space DeviceCMYK".to_string()));
+    }
+}
@@ -0,0 +1,137 @@
+use axum::body::Bytes;
+use serde::Serialize;
+
+use crate::{error::AppError, ocr::{cache::{self, CacheInfo, CacheMode, CacheStatus, ResultCache}, history::{self, RequestContext, ResultStore}, pages::{self, InputFormat, MultiPageResult, PageResult}, preprocess::{self, PreprocessOptions}, ollama_options::GenerationInfo, provider::{OcrOptions, OcrProvider, OcrResult}}};
+
+// A single image yields one result, PDF/TIFF input yields one result per page
+#[derive(Serialize, Clone, Debug)]
+#[serde(untagged)]
+pub enum AnalyzeOutput {
+    Single(Box<OcrResult>),
+    Pages(MultiPageResult),
+}
+
+impl AnalyzeOutput {
+    // Model that produced the answer (the first analyzed page for multi-page input)
+    pub fn model(&self) -> Option<String> {
+        match self {
+            AnalyzeOutput::Single(result) => Some(result.model.clone()),
+            AnalyzeOutput::Pages(pages) => pages.pages.iter()
+                .find_map(|p| p.result.as_ref())
+                .map(|r| r.model.clone()),
+        }
+    }
+
+    // Ollama options, token counts and timings, from the same page as `model`
+    pub fn generation(&self) -> Option<GenerationInfo> {
+        match self {
+            AnalyzeOutput::Single(result) => result.generation.clone(),
+            AnalyzeOutput::Pages(pages) => pages.pages.iter()
+                .find_map(|p| p.result.as_ref())
+                .and_then(|r| r.generation.clone()),
+        }
+    }
+}
+
+// Everything that happens around a provider call: cache lookup, preprocessing, history, cache fill.
+// Shared by the synchronous handlers and the job workers.
+#[derive(Clone)]
+pub struct OcrPipeline {
+    results: ResultStore,
+    cache: ResultCache,
+}
+
+impl OcrPipeline {
+    pub fn new(results: ResultStore, cache: ResultCache) -> Self {
+        Self { results, cache }
+    }
+
+    // Entry point for uploads that may be PDF/TIFF: providers that read documents get the whole
+    // file, everything else gets one `run` per extracted page
+    pub async fn analyze(
+        &self,
+        provider: &dyn OcrProvider,
+        input: Bytes,
+        options: &OcrOptions,
+        ctx: &RequestContext,
+    ) -> Result<AnalyzeOutput, AppError> {
+        let format = InputFormat::detect(&input);
+        if !format.is_multi_page() {
+            return Ok(AnalyzeOutput::Single(Box::new(self.run(provider, input, options, ctx).await?)));
+        }
+
+        if provider.accepts_documents() {
+            let page_count = pages::count(&input, format)?;
+            // Page images never get decoded locally here, so there is nothing to preprocess
+            let native = OcrOptions { preprocess: PreprocessOptions::default(), ..options.clone() };
+            let result = self.run(provider, input, &native, ctx).await?;
+            return Ok(AnalyzeOutput::Pages(MultiPageResult {
+                format: format.as_str().to_string(),
+                page_count,
+                native: true,
+                pages: pages::split_result(&result),
+                data: result.data,
+            }));
+        }
+
+        let selection = options.pages.clone();
+        let (page_count, split) = tokio::task::spawn_blocking(move || pages::split(&input, format, selection.as_ref()))
+            .await
+            .map_err(|e| AppError::Internal(format!("Page extraction failed: {}", e)))??;
+
+        let mut results = Vec::with_capacity(split.len());
+        for (page, image) in split {
+            let outcome = match image {
+                Ok(image) => self.run(provider, image, options, ctx).await,
+                Err(e) => Err(AppError::UnsupportedMedia(e)),
+            };
+            results.push(match outcome {
+                Ok(result) => PageResult { page, result: Some(result), error: None },
+                Err(e) => PageResult { page, result: None, error: Some(e.to_string()) },
+            });
+        }
+
+        Ok(AnalyzeOutput::Pages(MultiPageResult {
+            format: format.as_str().to_string(),
+            page_count,
+            native: false,
+            pages: results,
+            data: None,
+        }))
+    }
+
+    pub async fn run(
+        &self,
+        provider: &dyn OcrProvider,
+        image: Bytes,
+        options: &OcrOptions,
+        ctx: &RequestContext,
+    ) -> Result<OcrResult, AppError> {
+        let key = cache::cache_key(provider, options, &image);
+
+        if ctx.cache == CacheMode::Use
+            && let Some((mut hit, source)) = self.cache.get(&key).await
+        {
+            hit.cache = Some(CacheInfo { status: CacheStatus::Hit, source: Some(source.to_string()), key });
+            return Ok(hit);
+        }
+
+        // Cache and history are keyed on the submitted bytes, so preprocessing only runs on a miss
+        let hash = history::input_hash(&image);
+        let (image, report) = preprocess::run(image, &options.preprocess).await?;
+
+        let mut result = history::analyze_and_record(&self.results, provider, image, hash, options, ctx).await?;
+        result.preprocess = report;
+        result.prompt = options.template.as_ref().map(|t| t.id());
+
+        let status = if ctx.cache == CacheMode::Bypass {
+            CacheStatus::Bypass
+        } else {
+            self.cache.put(&key, &result).await;
+            CacheStatus::Miss
+        };
+        result.cache = Some(CacheInfo { status, source: None, key });
+
+        Ok(result)
+    }
+}
@@ -0,0 +1,510 @@
+use std::{collections::HashMap, io::Cursor};
+use axum::body::Bytes;
+use image::{DynamicImage, GrayImage, ImageBuffer, ImageDecoder, ImageError, ImageFormat, ImageReader, Luma, Pixel, codecs::jpeg::JpegEncoder, imageops::{self, FilterType}, metadata::Orientation};
+use serde::{Deserialize, Serialize};
+
+use crate::error::AppError;
+
+// Used by `?preprocess=auto`, sized for phone photos of ATM receipts
+const AUTO_MAX_DIMENSION: u32 = 2000;
+// Deskew search range and the smallest correction worth resampling for
+const MAX_SKEW_DEGREES: f32 = 15.0;
+const MIN_SKEW_DEGREES: f32 = 0.2;
+// Deskew is estimated on a reduced copy of the page
+const DESKEW_SAMPLE_DIMENSION: u32 = 1000;
+const CLAHE_TILES: u32 = 8;
+const CLAHE_CLIP_LIMIT: f32 = 2.0;
+// A pixel is ink when it is this much (percent) darker than its neighbourhood mean
+const THRESHOLD_OFFSET_PERCENT: u64 = 15;
+const JPEG_QUALITY: u8 = 90;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Contrast {
+    #[default]
+    None,
+    // Contrast limited adaptive histogram equalization, keeps gray levels
+    Clahe,
+    // Local mean binarization, best for very faded thermal print
+    Threshold,
+}
+
+// Steps selected with `?preprocess=orient,deskew,grayscale,clahe|threshold` (or `auto`)
+// and `?max_dimension=`. Nothing is selected by default, so the image is sent untouched.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PreprocessOptions {
+    pub orient: bool,
+    pub deskew: bool,
+    pub grayscale: bool,
+    pub contrast: Contrast,
+    pub max_dimension: Option<u32>,
+}
+
+impl PreprocessOptions {
+    pub fn from_query(params: &HashMap<String, String>) -> Result<Self, String> {
+        let mut options = Self::default();
+
+        let steps = params.get("preprocess").map(|v| v.as_str()).unwrap_or("");
+        for step in steps.split(',').map(|s| s.trim().to_ascii_lowercase()).filter(|s| !s.is_empty()) {
+            match step.as_str() {
+                "auto" => {
+                    options.orient = true;
+                    options.deskew = true;
+                    options.grayscale = true;
+                    options.contrast = Contrast::Clahe;
+                    options.max_dimension = Some(AUTO_MAX_DIMENSION);
+                }
+                "orient" => options.orient = true,
+                "deskew" => options.deskew = true,
+                "grayscale" => options.grayscale = true,
+                "clahe" => options.contrast = Contrast::Clahe,
+                "threshold" => options.contrast = Contrast::Threshold,
+                other => return Err(format!(
+                    "Unknown preprocess step '{}'. Expected auto, orient, deskew, grayscale, clahe or threshold", other
+                )),
+            }
+        }
+
+        if let Some(v) = params.get("max_dimension").filter(|v| !v.trim().is_empty()) {
+            match v.trim().parse::<u32>() {
+                Ok(max) if max >= 64 => options.max_dimension = Some(max),
+                _ => return Err("invalid max_dimension, expected a number of pixels >= 64".to_string()),
+            }
+        }
+
+        Ok(options)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    // Stable description of the selected steps, part of the cache key
+    pub fn describe(&self) -> String {
+        let mut steps = Vec::new();
+        if self.orient { steps.push("orient".to_string()); }
+        if let Some(max) = self.max_dimension { steps.push(format!("max_dimension={}", max)); }
+        if self.deskew { steps.push("deskew".to_string()); }
+        if self.grayscale { steps.push("grayscale".to_string()); }
+        match self.contrast {
+            Contrast::None => {}
+            Contrast::Clahe => steps.push("clahe".to_string()),
+            Contrast::Threshold => steps.push("threshold".to_string()),
+        }
+        steps.join(",")
+    }
+}
+
+// What was actually done to the image, reported on the OcrResult
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct PreprocessReport {
+    pub steps: Vec<String>,
+    pub original_width: u32,
+    pub original_height: u32,
+    pub width: u32,
+    pub height: u32,
+    // Degrees the page was rotated back by, when deskew found a skew
+    pub deskew_angle: Option<f32>,
+    pub format: String,
+}
+
+// Off the async runtime; passes the bytes through untouched when no step is selected
+pub async fn run(image: Bytes, options: &PreprocessOptions) -> Result<(Bytes, Option<PreprocessReport>), AppError> {
+    if options.is_empty() {
+        return Ok((image, None));
+    }
+    let options = options.clone();
+    let (image, report) = tokio::task::spawn_blocking(move || apply(image, &options))
+        .await
+        .map_err(|e| AppError::Internal(format!("Preprocessing failed: {}", e)))??;
+    Ok((image, Some(report)))
+}
+
+// Runs the selected steps in a fixed order: orient, downscale, deskew, grayscale, contrast.
+// CPU bound, use `run` from async code.
+pub fn apply(image: Bytes, options: &PreprocessOptions) -> Result<(Bytes, PreprocessReport), AppError> {
+    let decode_err = |e: ImageError| match e {
+        ImageError::Limits(_) => AppError::ImageTooLarge(format!("Preprocessing failed, image too large: {}", e)),
+        _ => AppError::UnsupportedMedia(format!("Preprocessing failed, unsupported image: {}", e)),
+    };
+    let encode_err = |e: ImageError| AppError::Internal(format!("Preprocessing failed: {}", e));
+
+    let reader = ImageReader::new(Cursor::new(&image[..]))
+        .with_guessed_format()
+        .map_err(|e| AppError::UnsupportedMedia(format!("Preprocessing failed: {}", e)))?;
+    let mut decoder = reader.into_decoder().map_err(decode_err)?;
+    let orientation = decoder.orientation().ok();
+    let mut img = DynamicImage::from_decoder(decoder).map_err(decode_err)?;
+
+    let mut report = PreprocessReport {
+        original_width: img.width(),
+        original_height: img.height(),
+        ..Default::default()
+    };
+
+    // 1. EXIF orientation (phone photos are usually stored sideways), reported only when it turned the image
+    if options.orient
+        && let Some(orientation) = orientation.filter(|o| *o != Orientation::NoTransforms)
+    {
+        img.apply_orientation(orientation);
+        report.steps.push("orient".to_string());
+    }
+
+    // 2. Downscale first so the remaining steps work on fewer pixels; images already small enough are left alone
+    if let Some(max) = options.max_dimension
+        && (img.width() > max || img.height() > max)
+    {
+        img = img.resize(max, max, FilterType::Lanczos3);
+        report.steps.push("resize".to_string());
+    }
+
+    // 3. Deskew
+    if options.deskew {
+        let angle = estimate_skew(&img.to_luma8());
+        if angle.abs() >= MIN_SKEW_DEGREES {
+            img = match img {
+                DynamicImage::ImageLuma8(gray) => DynamicImage::ImageLuma8(rotate(&gray, angle, Luma([255]))),
+                other => DynamicImage::ImageRgb8(rotate(&other.to_rgb8(), angle, image::Rgb([255, 255, 255]))),
+            };
+            report.deskew_angle = Some(angle);
+            report.steps.push("deskew".to_string());
+        }
+    }
+
+    // 4. Grayscale (implied by the contrast steps, which work on luminance)
+    if options.grayscale || options.contrast != Contrast::None {
+        img = DynamicImage::ImageLuma8(img.to_luma8());
+        report.steps.push("grayscale".to_string());
+    }
+
+    // 5. Contrast
+    match options.contrast {
+        Contrast::None => {}
+        Contrast::Clahe => {
+            img = DynamicImage::ImageLuma8(clahe(&img.to_luma8(), CLAHE_TILES, CLAHE_CLIP_LIMIT));
+            report.steps.push("clahe".to_string());
+        }
+        Contrast::Threshold => {
+            img = DynamicImage::ImageLuma8(adaptive_threshold(&img.to_luma8()));
+            report.steps.push("threshold".to_string());
+        }
+    }
+
+    // 6. Re-encode: lossless for gray/binary pages, JPEG for color photos
+    let mut out = Cursor::new(Vec::new());
+    match &img {
+        DynamicImage::ImageLuma8(_) => {
+            img.write_to(&mut out, ImageFormat::Png).map_err(encode_err)?;
+            report.format = "png".to_string();
+        }
+        _ => {
+            img.to_rgb8()
+                .write_with_encoder(JpegEncoder::new_with_quality(&mut out, JPEG_QUALITY))
+                .map_err(encode_err)?;
+            report.format = "jpeg".to_string();
+        }
+    }
+    report.width = img.width();
+    report.height = img.height();
+
+    Ok((Bytes::from(out.into_inner()), report))
+}
+
+// Projection profile search: the angle at which ink pixels pile up into the fewest,
+// densest rows is the angle of the text lines. Returns degrees, positive = clockwise skew.
+fn estimate_skew(gray: &GrayImage) -> f32 {
+    let sample = skew_sample(gray);
+    let threshold = otsu_threshold(&sample);
+    let ink: Vec<(f32, f32)> = sample.enumerate_pixels()
+        .filter(|(_, _, p)| p[0] < threshold)
+        .map(|(x, y, _)| (x as f32, y as f32))
+        .collect();
+    if ink.is_empty() {
+        return 0.0;
+    }
+
+    let (w, h) = sample.dimensions();
+    let score = |degrees: f32| {
+        let (sin, cos) = degrees.to_radians().sin_cos();
+        let offset = w as f32;
+        let mut rows = vec![0u32; (w + h) as usize * 2 + 2];
+        for &(x, y) in &ink {
+            let r = (y * cos - x * sin + offset).round().max(0.0) as usize;
+            if let Some(bin) = rows.get_mut(r) {
+                *bin += 1;
+            }
+        }
+        rows.iter().map(|&c| (c as u64) * (c as u64)).sum::<u64>()
+    };
+
+    // Coarse search then a finer pass around the best coarse angle
+    let search = |from: f32, to: f32, step: f32| {
+        let mut best = (0.0f32, 0u64);
+        let mut angle = from;
+        while angle <= to + f32::EPSILON {
+            let s = score(angle);
+            if s > best.1 {
+                best = (angle, s);
+            }
+            angle += step;
+        }
+        best.0
+    };
+    let coarse = search(-MAX_SKEW_DEGREES, MAX_SKEW_DEGREES, 0.5);
+    search(coarse - 0.5, coarse + 0.5, 0.1)
+}
+
+// The page with its longer side brought down to DESKEW_SAMPLE_DIMENSION, never enlarged
+fn skew_sample(gray: &GrayImage) -> GrayImage {
+    let (w, h) = gray.dimensions();
+    let longest = w.max(h);
+    if longest <= DESKEW_SAMPLE_DIMENSION {
+        return gray.clone();
+    }
+    let scale = |side: u32| ((side as u64 * DESKEW_SAMPLE_DIMENSION as u64 / longest as u64) as u32).max(1);
+    imageops::resize(gray, scale(w), scale(h), FilterType::Triangle)
+}
+
+// Rotates around the center, keeping the canvas size and filling the uncovered corners
+fn rotate<P>(img: &ImageBuffer<P, Vec<P::Subpixel>>, degrees: f32, fill: P) -> ImageBuffer<P, Vec<P::Subpixel>>
+where
+    P: Pixel + 'static,
+{
+    let (w, h) = img.dimensions();
+    let (sin, cos) = degrees.to_radians().sin_cos();
+    let (cx, cy) = (w as f32 / 2.0, h as f32 / 2.0);
+
+    ImageBuffer::from_fn(w, h, |x, y| {
+        let (dx, dy) = (x as f32 - cx, y as f32 - cy);
+        let sx = cx + dx * cos - dy * sin;
+        let sy = cy + dx * sin + dy * cos;
+        imageops::interpolate_bilinear(img, sx, sy).unwrap_or(fill)
+    })
+}
+
+fn otsu_threshold(gray: &GrayImage) -> u8 {
+    let mut hist = [0u64; 256];
+    for p in gray.pixels() {
+        hist[p[0] as usize] += 1;
+    }
+    let total: u64 = hist.iter().sum();
+    let sum_all: u64 = hist.iter().enumerate().map(|(i, &c)| i as u64 * c).sum();
+
+    let (mut best, mut best_variance) = (128u8, 0f64);
+    let (mut weight_bg, mut sum_bg) = (0u64, 0u64);
+    for (t, &count) in hist.iter().enumerate() {
+        weight_bg += count;
+        sum_bg += t as u64 * count;
+        let weight_fg = total - weight_bg;
+        if weight_bg == 0 || weight_fg == 0 {
+            continue;
+        }
+        let mean_bg = sum_bg as f64 / weight_bg as f64;
+        let mean_fg = (sum_all - sum_bg) as f64 / weight_fg as f64;
+        let variance = weight_bg as f64 * weight_fg as f64 * (mean_bg - mean_fg).powi(2);
+        if variance > best_variance {
+            best_variance = variance;
+            best = t as u8;
+        }
+    }
+    best
+}
+
+// CLAHE: per-tile equalization with a clipped histogram, bilinearly blended between tiles
+fn clahe(gray: &GrayImage, tiles: u32, clip_limit: f32) -> GrayImage {
+    let (w, h) = gray.dimensions();
+    if w == 0 || h == 0 {
+        return gray.clone();
+    }
+    let tile_w = w.div_ceil(tiles.clamp(1, w));
+    let tile_h = h.div_ceil(tiles.clamp(1, h));
+    let tiles_x = w.div_ceil(tile_w);
+    let tiles_y = h.div_ceil(tile_h);
+
+    let mut luts = vec![[0u8; 256]; (tiles_x * tiles_y) as usize];
+    for ty in 0..tiles_y {
+        for tx in 0..tiles_x {
+            let (x0, y0) = (tx * tile_w, ty * tile_h);
+            let (x1, y1) = ((x0 + tile_w).min(w), (y0 + tile_h).min(h));
+
+            let mut hist = [0u32; 256];
+            for y in y0..y1 {
+                for x in x0..x1 {
+                    hist[gray.get_pixel(x, y)[0] as usize] += 1;
+                }
+            }
+
+            // Clip and hand the excess back evenly so flat paper areas are not blown up
+            let area = (x1 - x0) * (y1 - y0);
+            let limit = ((clip_limit * area as f32 / 256.0) as u32).max(1);
+            let mut excess = 0;
+            for count in hist.iter_mut() {
+                if *count > limit {
+                    excess += *count - limit;
+                    *count = limit;
+                }
+            }
+            // Kept fractional: rounding the share down would leave the mapping short of 255 and darken the page
+            let bonus = excess as f32 / 256.0;
+
+            let lut = &mut luts[(ty * tiles_x + tx) as usize];
+            let mut cdf = 0f32;
+            for (value, &count) in hist.iter().enumerate() {
+                cdf += count as f32 + bonus;
+                lut[value] = (cdf * 255.0 / area as f32).round().min(255.0) as u8;
+            }
+        }
+    }
+
+    // Tile index to the left/above the pixel and the blend weight toward the next tile
+    let neighbours = |pos: u32, size: u32, count: u32| {
+        let f = (pos as f32 + 0.5) / size as f32 - 0.5;
+        let first = f.floor().clamp(0.0, (count - 1) as f32) as u32;
+        let second = (first + 1).min(count - 1);
+        (first, second, (f - first as f32).clamp(0.0, 1.0))
+    };
+
+    ImageBuffer::from_fn(w, h, |x, y| {
+        let v = gray.get_pixel(x, y)[0] as usize;
+        let (x0, x1, ax) = neighbours(x, tile_w, tiles_x);
+        let (y0, y1, ay) = neighbours(y, tile_h, tiles_y);
+        let at = |tx: u32, ty: u32| luts[(ty * tiles_x + tx) as usize][v] as f32;
+
+        let top = at(x0, y0) * (1.0 - ax) + at(x1, y0) * ax;
+        let bottom = at(x0, y1) * (1.0 - ax) + at(x1, y1) * ax;
+        Luma([(top * (1.0 - ay) + bottom * ay).round() as u8])
+    })
+}
+
+// Local mean thresholding over an integral image (window ~1/16 of the page)
+fn adaptive_threshold(gray: &GrayImage) -> GrayImage {
+    let (w, h) = gray.dimensions();
+    let stride = w as usize + 1;
+    let mut integral = vec![0u64; stride * (h as usize + 1)];
+    for y in 0..h as usize {
+        let mut row = 0u64;
+        for x in 0..w as usize {
+            row += gray.get_pixel(x as u32, y as u32)[0] as u64;
+            integral[(y + 1) * stride + x + 1] = integral[y * stride + x + 1] + row;
+        }
+    }
+
+    let half = (w.max(h) / 32).max(7);
+    ImageBuffer::from_fn(w, h, |x, y| {
+        let (x0, y0) = (x.saturating_sub(half) as usize, y.saturating_sub(half) as usize);
+        let (x1, y1) = ((x + half + 1).min(w) as usize, (y + half + 1).min(h) as usize);
+        let sum = integral[y1 * stride + x1] + integral[y0 * stride + x0]
+            - integral[y0 * stride + x1] - integral[y1 * stride + x0];
+        let count = ((x1 - x0) * (y1 - y0)) as u64;
+
+        let v = gray.get_pixel(x, y)[0] as u64;
+        if v * count * 100 < sum * (100 - THRESHOLD_OFFSET_PERCENT) { Luma([0]) } else { Luma([255]) }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn query(pairs: &[(&str, &str)]) -> Result<PreprocessOptions, String> {
+        PreprocessOptions::from_query(&pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect())
+    }
+
+    // White page with dark horizontal bars, like lines of text
+    fn lines(w: u32, h: u32) -> GrayImage {
+        ImageBuffer::from_fn(w, h, |x, y| {
+            let margin = w / 10;
+            if x > margin && x < w - margin && y > 20 && y < h - 20 && y % 20 < 4 { Luma([20]) } else { Luma([255]) }
+        })
+    }
+
+    fn png(img: &GrayImage) -> Bytes {
+        let mut out = Cursor::new(Vec::new());
+        img.write_to(&mut out, ImageFormat::Png).unwrap();
+        Bytes::from(out.into_inner())
+    }
+
+    #[test]
+    fn steps_are_parsed_from_the_query() {
+        assert!(query(&[]).unwrap().is_empty());
+        let auto = query(&[("preprocess", "auto")]).unwrap();
+        assert!(auto.orient && auto.deskew && auto.grayscale);
+        assert_eq!((auto.contrast, auto.max_dimension), (Contrast::Clahe, Some(AUTO_MAX_DIMENSION)));
+
+        let picked = query(&[("preprocess", " Deskew, clahe,threshold "), ("max_dimension", "64")]).unwrap();
+        assert_eq!(picked, PreprocessOptions { deskew: true, contrast: Contrast::Threshold, max_dimension: Some(64), ..Default::default() });
+        assert_eq!(picked.describe(), "max_dimension=64,deskew,threshold");
+
+        assert!(query(&[("preprocess", "sharpen")]).is_err());
+        assert!(query(&[("max_dimension", "63")]).is_err());
+        assert!(query(&[("max_dimension", "big")]).is_err());
+    }
+
+    #[test]
+    fn a_rotated_page_is_measured_and_straightened() {
+        // Content turned 4 degrees clockwise
+        let skewed = rotate(&lines(400, 300), -4.0, Luma([255]));
+        let angle = estimate_skew(&skewed);
+        assert!((angle - 4.0).abs() <= 0.2, "{}", angle);
+        assert!(estimate_skew(&lines(400, 300)).abs() < MIN_SKEW_DEGREES);
+
+        let options = PreprocessOptions { deskew: true, ..Default::default() };
+        let (_, report) = apply(png(&skewed), &options).unwrap();
+        assert!(report.deskew_angle.is_some_and(|a| (a - 4.0).abs() <= 0.2));
+        assert_eq!(report.steps, ["deskew"]);
+
+        // A straight page is left alone and not reported
+        let (_, report) = apply(png(&lines(400, 300)), &options).unwrap();
+        assert_eq!(report.deskew_angle, None);
+        assert!(report.steps.is_empty());
+    }
+
+    #[test]
+    fn the_skew_sample_only_ever_shrinks() {
+        let tall = skew_sample(&GrayImage::new(500, 1200));
+        assert_eq!(tall.dimensions(), (416, 1000));
+        let wide = skew_sample(&GrayImage::new(3000, 600));
+        assert_eq!(wide.dimensions(), (1000, 200));
+        assert_eq!(skew_sample(&GrayImage::new(800, 300)).dimensions(), (800, 300));
+    }
+
+    #[test]
+    fn contrast_steps_recover_a_faded_page() {
+        // Faint gradient from 110 to 140 with ink squares every 32 px, 40 levels darker than the paper
+        let faded: GrayImage = ImageBuffer::from_fn(256, 128, |x, y| {
+            let paper = 110 + (x * 30 / 255) as u8;
+            if x % 32 < 6 && y % 32 < 6 { Luma([paper - 40]) } else { Luma([paper]) }
+        });
+        // CLAHE: paper shading stays where it was (the clip limit), ink moves away from the paper
+        let equalized = clahe(&faded, CLAHE_TILES, CLAHE_CLIP_LIMIT);
+        assert_eq!(equalized.dimensions(), faded.dimensions());
+        for x in (8..256).step_by(32) {
+            let (before, after) = (faded.get_pixel(x, 20)[0] as i32, equalized.get_pixel(x, 20)[0] as i32);
+            assert!((after - before).abs() <= 5, "paper at {}: {} -> {}", x, before, after);
+        }
+        let gap = |img: &GrayImage| img.get_pixel(100, 70)[0] as i32 - img.get_pixel(98, 66)[0] as i32;
+        assert!(gap(&equalized) > gap(&faded), "{} vs {}", gap(&equalized), gap(&faded));
+
+        let binary = adaptive_threshold(&faded);
+        assert!(binary.pixels().all(|p| p[0] == 0 || p[0] == 255));
+        for (x, y) in [(2, 2), (98, 66), (226, 98)] {
+            assert_eq!(binary.get_pixel(x, y)[0], 0, "ink at {},{}", x, y);
+        }
+        for (x, y) in [(16, 16), (120, 80), (250, 120)] {
+            assert_eq!(binary.get_pixel(x, y)[0], 255, "paper at {},{}", x, y);
+        }
+    }
+
+    #[test]
+    fn orient_and_resize_are_reported_only_when_applied() {
+        let options = PreprocessOptions { orient: true, max_dimension: Some(1000), ..Default::default() };
+        let (_, report) = apply(png(&lines(400, 300)), &options).unwrap();
+        assert!(report.steps.is_empty());
+        assert_eq!((report.width, report.height), (400, 300));
+
+        let options = PreprocessOptions { max_dimension: Some(200), ..Default::default() };
+        let (_, report) = apply(png(&lines(400, 300)), &options).unwrap();
+        assert_eq!(report.steps, ["resize"]);
+        assert_eq!((report.width, report.height), (200, 150));
+    }
+}
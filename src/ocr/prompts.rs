@@ -0,0 +1,323 @@
+use std::{collections::{BTreeMap, HashMap}, sync::{Arc, atomic::{AtomicU64, Ordering}}};
+use axum::{Json, extract::{Query, State}, http::StatusCode, response::{IntoResponse, Response}};
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::{constant::ApiResponse, db::execute_sp_dynamic, error::AppError, model::SqlParam, ocr::{counters::CounterType, history}, state::AppState};
+
+// Templates known without any configuration, see the file for the format
+const BUILT_IN: &str = include_str!("prompts.toml");
+
+// Template used when the request names none
+const DEFAULT_PROMPT: &str = "ocr";
+const DEFAULT_COUNTER_PROMPT: &str = "counter";
+
+// Variables filled from the counter type; a template using them needs `?counter_name=`
+const COUNTER_VARIABLES: &[&str] = &["counter", "description", "instructions", "fields", "schema"];
+const REQUEST_VARIABLES: &[&str] = &["bank"];
+
+// One version of a named prompt
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct PromptTemplate {
+    pub name: String,
+    pub version: u32,
+    #[serde(default)]
+    pub description: String,
+    pub template: String,
+    #[serde(default = "default_weight")]
+    pub weight: u32,
+}
+
+fn default_weight() -> u32 {
+    1
+}
+
+#[derive(Deserialize)]
+struct PromptFile {
+    prompts: Vec<PromptTemplate>,
+}
+
+// Replaces every `{{name}}` placeholder with `value(name)`, stopping at the first error
+fn fill<E>(template: &str, mut value: impl FnMut(&str) -> Result<String, E>) -> Result<String, E> {
+    let mut text = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") && let Some(end) = rest[start..].find("}}") {
+        text.push_str(&rest[..start]);
+        text.push_str(&value(rest[start + 2..start + end].trim())?);
+        rest = &rest[start + end + 2..];
+    }
+    text.push_str(rest);
+    Ok(text)
+}
+
+impl PromptTemplate {
+    fn check(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        if self.name.trim().is_empty() {
+            problems.push("name must not be empty".to_string());
+        }
+        if self.template.trim().is_empty() {
+            problems.push("template must not be empty".to_string());
+        }
+        let _ = fill(&self.template, |name| {
+            if !COUNTER_VARIABLES.contains(&name) && !REQUEST_VARIABLES.contains(&name) {
+                problems.push(format!("unknown variable {{{{{}}}}}, expected one of {}", name, [COUNTER_VARIABLES, REQUEST_VARIABLES].concat().join(", ")));
+            }
+            Ok::<_, ()>(String::new())
+        });
+        problems
+    }
+
+    pub fn render(&self, counter: Option<&CounterType>, bank: Option<&str>) -> Result<RenderedPrompt, AppError> {
+        let text = fill(&self.template, |name| Ok(match (name, counter) {
+            ("bank", _) => bank.unwrap_or("unknown").to_string(),
+            (_, None) => return Err(AppError::InvalidPayload(format!(
+                "Prompt '{}' v{} uses {{{{{}}}}} and needs counter_name", self.name, self.version, name
+            ))),
+            ("counter", Some(c)) => c.name.clone(),
+            ("description", Some(c)) => c.description.clone(),
+            ("instructions", Some(c)) => c.prompt.trim().to_string(),
+            ("schema", Some(c)) => c.schema.to_string(),
+            ("fields", Some(c)) => c.schema["properties"].as_object()
+                .map(|p| p.keys().cloned().collect::<Vec<_>>().join(", "))
+                .unwrap_or_default(),
+            // Unknown names are refused when the registry is built
+            _ => String::new(),
+        }))?;
+        Ok(RenderedPrompt { name: self.name.clone(), version: self.version, text })
+    }
+}
+
+// The template picked for a request, filled in
+#[derive(Clone, Debug)]
+pub struct RenderedPrompt {
+    pub name: String,
+    pub version: u32,
+    pub text: String,
+}
+
+impl RenderedPrompt {
+    pub fn id(&self) -> PromptVersion {
+        PromptVersion { name: self.name.clone(), version: self.version }
+    }
+}
+
+// Which template produced a result, returned with it and stored in the history
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PromptVersion {
+    pub name: String,
+    pub version: u32,
+}
+
+// Built-in templates overlaid with the ones from config.toml
+pub struct PromptRegistry {
+    // Versions of each name, ascending
+    templates: BTreeMap<String, Vec<Arc<PromptTemplate>>>,
+    // Requests served per name, so the rotation of one name is not skewed by the others
+    next: HashMap<String, AtomicU64>,
+}
+
+impl PromptRegistry {
+    pub fn new(configured: &[PromptTemplate]) -> Result<Self, Vec<String>> {
+        let built_in: PromptFile = toml::from_str(BUILT_IN).expect("Invalid built-in prompt templates");
+        let mut by_id = BTreeMap::new();
+        for template in built_in.prompts.into_iter().chain(configured.iter().cloned()) {
+            by_id.insert((template.name.clone(), template.version), template);
+        }
+
+        let mut problems = Vec::new();
+        let mut templates: BTreeMap<String, Vec<Arc<PromptTemplate>>> = BTreeMap::new();
+        for ((name, version), template) in by_id {
+            problems.extend(template.check().into_iter().map(|p| format!("prompts {} v{}: {}", name, version, p)));
+            templates.entry(name).or_default().push(Arc::new(template));
+        }
+        if !problems.is_empty() {
+            return Err(problems);
+        }
+        let next = templates.keys().map(|name| (name.clone(), AtomicU64::new(0))).collect();
+        Ok(Self { templates, next })
+    }
+
+    // An explicit version, otherwise a weighted rotation over the versions in use (the latest when all weigh 0)
+    pub fn select(&self, name: &str, version: Option<u32>) -> Result<Arc<PromptTemplate>, AppError> {
+        let versions = self.templates.get(name).ok_or_else(|| AppError::InvalidPayload(
+            format!("Unknown prompt_name '{}'. Available: {}", name, self.templates.keys().cloned().collect::<Vec<_>>().join(", "))
+        ))?;
+
+        if let Some(version) = version {
+            return versions.iter().find(|t| t.version == version).cloned().ok_or_else(|| AppError::InvalidPayload(
+                format!("Prompt '{}' has no version {}. Available: {}", name, version, versions.iter().map(|t| t.version.to_string()).collect::<Vec<_>>().join(", "))
+            ));
+        }
+
+        let total: u64 = versions.iter().map(|t| t.weight as u64).sum();
+        if total == 0 {
+            return Ok(versions[versions.len() - 1].clone());
+        }
+        let mut pick = self.next[name].fetch_add(1, Ordering::Relaxed) % total;
+        for template in versions {
+            if pick < template.weight as u64 {
+                return Ok(template.clone());
+            }
+            pick -= template.weight as u64;
+        }
+        unreachable!("pick is below the total weight")
+    }
+
+    // `?prompt_name=`, `?prompt_version=` and `?bank=`; None when the request sends its own `?prompt=`
+    pub fn resolve(&self, params: &HashMap<String, String>, counter: Option<&CounterType>) -> Result<Option<RenderedPrompt>, AppError> {
+        let non_empty = |key: &str| params.get(key).map(|v| v.trim()).filter(|v| !v.is_empty());
+
+        let name = non_empty("prompt_name");
+        if non_empty("prompt").is_some() {
+            return match name {
+                Some(_) => Err(AppError::InvalidPayload("Send either prompt or prompt_name, not both".to_string())),
+                None => Ok(None),
+            };
+        }
+        let version = non_empty("prompt_version")
+            .map(|v| v.parse::<u32>().map_err(|_| AppError::InvalidPayload("invalid prompt_version".to_string())))
+            .transpose()?;
+        let default = if counter.is_some() { DEFAULT_COUNTER_PROMPT } else { DEFAULT_PROMPT };
+
+        let template = self.select(name.unwrap_or(default), version)?;
+        template.render(counter, non_empty("bank")).map(Some)
+    }
+}
+
+pub async fn list_prompts(State(state): State<AppState>) -> Response {
+    let templates: Vec<PromptTemplate> = state.prompts.templates.values().flatten().map(|t| t.as_ref().clone()).collect();
+    (StatusCode::OK, Json(ApiResponse::success(templates, "Prompt templates"))).into_response()
+}
+
+// Success and schema-valid rates per prompt version, to compare versions on the same counter type
+pub async fn get_prompt_stats(
+    State(state): State<AppState>,
+    Query(params): Query<HashMap<String, String>>,
+) -> Result<Response, AppError> {
+    // 1. Validation
+    let date_param = |key: &str, parse: fn(&str) -> Option<NaiveDateTime>| match params.get(key).filter(|v| !v.is_empty()) {
+        Some(v) => parse(v)
+            .map(SqlParam::DateTime)
+            .ok_or_else(|| AppError::InvalidPayload(format!("invalid {} date, expected YYYY-MM-DD", key))),
+        None => Ok(SqlParam::Null),
+    };
+    let date_from = date_param("from", history::parse_date)?;
+    let date_to = date_param("to", history::parse_date_to)?;
+    let counter_name = params.get("counter_name")
+        .filter(|v| !v.is_empty())
+        .map(|v| SqlParam::String(v.clone()))
+        .unwrap_or(SqlParam::Null);
+
+    // 2. DB Query
+    let mut client = state.db()?.get().await.map_err(|_| AppError::DbPool)?;
+
+    let sp_params = vec![
+        ("dateFrom", date_from),
+        ("dateTo", date_to),
+        ("counterName", counter_name),
+    ];
+
+    let sp = execute_sp_dynamic(&mut client, "usp_Get_Ocr_Prompt_Stats", &sp_params).await
+        .map_err(AppError::Database)?;
+    if !sp.status {
+        return Err(AppError::SpExecution { message: sp.message, data: None });
+    }
+
+    let rows = sp.result_sets.into_iter().next().unwrap_or_default();
+    Ok((StatusCode::OK, Json(ApiResponse::success(json!({ "items": rows }), &sp.message))).into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_trait::async_trait;
+    use axum::body::Bytes;
+
+    use crate::ocr::{counters::CounterRegistry, provider::{OcrOptions, OcrProvider, OcrResult}};
+
+    struct Stub(bool);
+
+    #[async_trait]
+    impl OcrProvider for Stub {
+        fn name(&self) -> &'static str {
+            "stub"
+        }
+
+        fn takes_prompt(&self) -> bool {
+            self.0
+        }
+
+        async fn analyze(&self, _image: Bytes, _options: &OcrOptions) -> Result<OcrResult, AppError> {
+            unreachable!("options are built without calling the provider")
+        }
+    }
+
+    fn template(name: &str, version: u32, weight: u32, template: &str) -> PromptTemplate {
+        PromptTemplate { name: name.to_string(), version, description: String::new(), template: template.to_string(), weight }
+    }
+
+    #[test]
+    fn weights_split_requests_between_versions() {
+        let registry = PromptRegistry::new(&[
+            template("ab", 1, 3, "first"),
+            template("ab", 2, 1, "second"),
+            template("ab", 3, 0, "third"),
+        ]).unwrap();
+
+        let picks: Vec<u32> = (0..8).map(|_| registry.select("ab", None).unwrap().version).collect();
+        assert_eq!(picks, [1, 1, 1, 2, 1, 1, 1, 2]);
+        assert_eq!(registry.select("ab", Some(3)).unwrap().template, "third");
+        assert!(registry.select("ab", Some(4)).is_err());
+    }
+
+    #[test]
+    fn each_name_rotates_on_its_own() {
+        let registry = PromptRegistry::new(&[
+            template("ab", 1, 1, "first"),
+            template("ab", 2, 1, "second"),
+            template("cd", 1, 1, "first"),
+            template("cd", 2, 1, "second"),
+        ]).unwrap();
+
+        // Requests alternate between the names; each must still alternate between its versions
+        let picks: Vec<(u32, u32)> = (0..3)
+            .map(|_| (registry.select("ab", None).unwrap().version, registry.select("cd", None).unwrap().version))
+            .collect();
+        assert_eq!(picks, [(1, 1), (2, 2), (1, 1)]);
+    }
+
+    #[test]
+    fn templates_are_only_picked_for_prompted_providers() {
+        let counters = CounterRegistry::new(&BTreeMap::new()).unwrap();
+        let registry = PromptRegistry::new(&[template("ab", 1, 1, "first"), template("ab", 2, 1, "second")]).unwrap();
+        let params = HashMap::from([("prompt_name".to_string(), "ab".to_string())]);
+
+        let read = OcrOptions::from_query(&params, &Stub(false), &counters, &registry).unwrap();
+        assert!(read.template.is_none());
+        // The request above must not have used up version 1
+        let llm = OcrOptions::from_query(&params, &Stub(true), &counters, &registry).unwrap();
+        assert_eq!(llm.template.unwrap().version, 1);
+    }
+
+    #[test]
+    fn renders_counter_and_request_variables() {
+        let counters = CounterRegistry::new(&BTreeMap::new()).unwrap();
+        let params = HashMap::from([("counter_name".to_string(), "CHEQUE".to_string())]);
+        let cheque = counters.lookup(&params).unwrap().unwrap();
+        let registry = PromptRegistry::new(&[template("t", 1, 1, "{{ counter }} from {{bank}}: {{fields}}")]).unwrap();
+
+        let rendered = registry.select("t", None).unwrap().render(Some(&cheque), Some("HDFC")).unwrap();
+        assert!(rendered.text.starts_with("CHEQUE from HDFC: account_number, amount"), "{}", rendered.text);
+        assert!(registry.select("t", None).unwrap().render(None, None).is_err());
+    }
+
+    #[test]
+    fn configured_templates_are_checked() {
+        let problems = PromptRegistry::new(&[template("ocr", 2, 1, "Read {{document}}")]).err().unwrap();
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0].starts_with("prompts ocr v2: unknown variable {{document}}"), "{}", problems[0]);
+    }
+}
@@ -0,0 +1,141 @@
+use std::{collections::HashMap, sync::Arc};
+
+use async_trait::async_trait;
+use axum::body::Bytes;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{error::AppError, ocr::{cache::CacheInfo, document::OcrDocument, pages::PageSelection, preprocess::{PreprocessOptions, PreprocessReport}, counters::{CounterRegistry, CounterType}, json_repair::JsonRepair, ollama_options::{GenerationInfo, GenerationOptions}, prompts::{PromptRegistry, PromptVersion, RenderedPrompt}, schema::SchemaValidation, voting::{VoteMode, VoteReport}, hybrid::HybridStages}};
+
+// Per-request options shared by every OCR backend.
+// Backends ignore the options that do not apply to them (e.g. Azure Read has no prompt).
+#[derive(Clone, Default, Debug)]
+pub struct OcrOptions {
+    pub model: Option<String>,
+    // Free text `?prompt=`; otherwise `template` is the prompt
+    pub prompt: Option<String>,
+    pub template: Option<RenderedPrompt>,
+    // Image clean-up applied by the pipeline before the backend sees the bytes
+    pub preprocess: PreprocessOptions,
+    // `?pages=` for PDF/TIFF input
+    pub pages: Option<PageSelection>,
+    // `?counter_name=`: prompt, schema, Azure model and post-processing of the document type
+    pub counter: Option<Arc<CounterType>>,
+    // Sampling options for Ollama, the other backends ignore them
+    pub generation: GenerationOptions,
+    // `?votes=` / `?vote_models=`: several Ollama runs merged by majority per field
+    pub voting: Option<VoteMode>,
+    // `?hybrid_image=true`: the hybrid provider also sends the image to the model
+    pub hybrid_image: bool,
+}
+
+impl OcrOptions {
+    // A template is only picked for a backend that sends prompts, so others do not advance the rotation
+    pub fn from_query(params: &HashMap<String, String>, provider: &dyn OcrProvider, counters: &CounterRegistry, prompts: &PromptRegistry) -> Result<Self, AppError> {
+        let non_empty = |key: &str| {
+            params.get(key)
+                .map(|v| v.trim().to_string())
+                .filter(|v| !v.is_empty())
+        };
+
+        let counter = counters.lookup(params)?;
+        Ok(Self {
+            model: non_empty("model_name"),
+            prompt: non_empty("prompt"),
+            template: if provider.takes_prompt() { prompts.resolve(params, counter.as_deref())? } else { None },
+            preprocess: PreprocessOptions::from_query(params).map_err(AppError::InvalidPayload)?,
+            pages: non_empty("pages").map(|p| PageSelection::parse(&p)).transpose().map_err(AppError::InvalidPayload)?,
+            counter,
+            generation: GenerationOptions::from_query(params).map_err(AppError::InvalidPayload)?,
+            voting: VoteMode::from_query(params).map_err(AppError::InvalidPayload)?,
+            hybrid_image: match non_empty("hybrid_image").as_deref() {
+                None | Some("false") => false,
+                Some("true") => true,
+                Some(_) => return Err(AppError::InvalidPayload("invalid hybrid_image, expected true or false".to_string())),
+            },
+        })
+    }
+
+    // What generative providers send to the model
+    pub fn prompt(&self) -> Option<String> {
+        self.prompt.clone().or_else(|| self.template.as_ref().map(|t| t.text.clone()))
+    }
+}
+
+// Normalized result returned by every provider
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct OcrResult {
+    pub provider: String,
+    pub model: String,
+    // Plain recognized text (or the raw LLM answer for generative backends)
+    pub text: String,
+    // Structured extraction when the backend produces one
+    pub data: Option<Value>,
+    // Normalized pages/lines/words/fields, same schema for every backend
+    pub document: OcrDocument,
+    // Untouched upstream response, kept for debugging
+    pub raw: Value,
+    // Hit/miss reporting, filled in by the pipeline
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cache: Option<CacheInfo>,
+    // Preprocessing steps applied to the input, filled in by the pipeline
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub preprocess: Option<PreprocessReport>,
+    // Schema check of `data` when the request asked for a counter type
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub validation: Option<SchemaValidation>,
+    // Syntax fixes needed to read `data` out of a generative answer
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub repairs: Vec<JsonRepair>,
+    // Prompt template version sent to the model, filled in by the pipeline
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prompt: Option<PromptVersion>,
+    // Effective Ollama options with token counts and timings
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub generation: Option<GenerationInfo>,
+    // Per-field majority and agreement when the request asked for voting
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub voting: Option<VoteReport>,
+    // Read and structuring stages of the hybrid provider
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hybrid: Option<HybridStages>,
+}
+
+#[async_trait]
+pub trait OcrProvider: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    // Whether whole PDF/TIFF files can be sent as is instead of page by page
+    fn accepts_documents(&self) -> bool {
+        false
+    }
+
+    // Whether the prompt is sent to the backend; prompt templates are not picked for the others
+    fn takes_prompt(&self) -> bool {
+        false
+    }
+
+    async fn analyze(&self, image: Bytes, options: &OcrOptions) -> Result<OcrResult, AppError>;
+}
+
+// Registered providers, keyed by the name used in `?provider=`
+#[derive(Default)]
+pub struct ProviderRegistry {
+    providers: HashMap<&'static str, Arc<dyn OcrProvider>>,
+}
+
+impl ProviderRegistry {
+    pub fn register(&mut self, provider: Arc<dyn OcrProvider>) {
+        self.providers.insert(provider.name(), provider);
+    }
+
+    pub fn get(&self, name: &str) -> Option<Arc<dyn OcrProvider>> {
+        self.providers.get(name).cloned()
+    }
+
+    pub fn names(&self) -> Vec<&'static str> {
+        let mut names: Vec<_> = self.providers.keys().copied().collect();
+        names.sort();
+        names
+    }
+}
@@ -0,0 +1,107 @@
+use jsonschema::error::ValidationErrorKind;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+// One schema violation. `field` is a JSON pointer into the extracted data, "" for the whole document.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct FieldError {
+    pub field: String,
+    pub message: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SchemaValidation {
+    pub valid: bool,
+    pub errors: Vec<FieldError>,
+}
+
+// Checks the JSON recovered from a structured-output answer against the schema it was generated with
+pub fn validate_output(schema: &Value, data: Option<&Value>) -> SchemaValidation {
+    match data {
+        Some(data) => validate(schema, data),
+        None => SchemaValidation {
+            valid: false,
+            errors: vec![FieldError { field: String::new(), message: "Response contains no JSON".to_string() }],
+        },
+    }
+}
+
+pub fn validate(schema: &Value, data: &Value) -> SchemaValidation {
+    let validator = match jsonschema::validator_for(schema) {
+        Ok(v) => v,
+        Err(e) => return SchemaValidation {
+            valid: false,
+            errors: vec![FieldError { field: String::new(), message: format!("Invalid schema: {}", e) }],
+        },
+    };
+
+    let errors: Vec<FieldError> = validator.iter_errors(data)
+        .map(|e| {
+            let mut field = e.instance_path().to_string();
+            // A missing property is reported on its parent object, point at the property itself
+            if let ValidationErrorKind::Required { property } = e.kind() {
+                field = format!("{}/{}", field, property.as_str().unwrap_or_default());
+            }
+            FieldError { field, message: e.to_string() }
+        })
+        .collect();
+
+    SchemaValidation { valid: errors.is_empty(), errors }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn schema() -> Value {
+        json!({
+            "type": "object",
+            "required": ["atm_id", "cassettes"],
+            "properties": {
+                "atm_id": { "type": "string" },
+                "cassettes": {
+                    "type": "array",
+                    "items": {
+                        "type": "object",
+                        "required": ["denomination", "count"],
+                        "properties": { "denomination": { "type": "integer" }, "count": { "type": "integer" } }
+                    }
+                }
+            }
+        })
+    }
+
+    fn fields(validation: &SchemaValidation) -> Vec<&str> {
+        validation.errors.iter().map(|e| e.field.as_str()).collect()
+    }
+
+    #[test]
+    fn errors_point_at_the_offending_field() {
+        let valid = validate(&schema(), &json!({ "atm_id": "S1AW001", "cassettes": [{ "denomination": 500, "count": 10 }] }));
+        assert!(valid.valid && valid.errors.is_empty());
+
+        let missing = validate(&schema(), &json!({ "cassettes": [] }));
+        assert!(!missing.valid);
+        assert_eq!(fields(&missing), ["/atm_id"]);
+
+        let in_items = validate(&schema(), &json!({
+            "atm_id": "S1AW001",
+            "cassettes": [{ "denomination": 500, "count": "ten" }, { "count": 3 }]
+        }));
+        let mut pointers = fields(&in_items);
+        pointers.sort();
+        assert_eq!(pointers, ["/cassettes/0/count", "/cassettes/1/denomination"]);
+    }
+
+    #[test]
+    fn missing_json_and_broken_schemas_point_at_the_document() {
+        let none = validate_output(&schema(), None);
+        assert!(!none.valid);
+        assert_eq!(fields(&none), [""]);
+
+        let broken = validate(&json!({ "type": "nonsense" }), &json!({}));
+        assert_eq!(fields(&broken), [""]);
+        assert!(broken.errors[0].message.starts_with("Invalid schema"));
+    }
+}
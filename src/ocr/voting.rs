@@ -0,0 +1,305 @@
+use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::ocr::ollama_options::GenerationInfo;
+
+// Upper bound on runs per request, each one is a full generation on the Ollama pool
+pub const MAX_VOTES: usize = 7;
+
+// Self-consistency voting: the same image is read several times and the answers are merged
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum VoteMode {
+    // `?votes=N`: N runs of `model_name` with seeds counting up from the effective seed
+    Seeds(usize),
+    // `?vote_models=a,b,c`: one run per model
+    Models(Vec<String>),
+}
+
+impl VoteMode {
+    pub fn from_query(params: &HashMap<String, String>) -> Result<Option<Self>, String> {
+        let non_empty = |key: &str| params.get(key).map(|v| v.trim()).filter(|v| !v.is_empty());
+
+        let mode = match (non_empty("votes"), non_empty("vote_models")) {
+            (None, None) => return Ok(None),
+            (Some(_), Some(_)) => return Err("Send either votes or vote_models, not both".to_string()),
+            (Some(votes), None) => {
+                let votes: usize = votes.parse().map_err(|_| "invalid votes".to_string())?;
+                // `votes=1` is a plain request
+                if votes == 1 {
+                    return Ok(None);
+                }
+                Self::Seeds(votes)
+            }
+            (None, Some(models)) => {
+                let mut list: Vec<String> = Vec::new();
+                for model in models.split(',').map(str::trim).filter(|m| !m.is_empty()) {
+                    if !list.iter().any(|m| m == model) {
+                        list.push(model.to_string());
+                    }
+                }
+                Self::Models(list)
+            }
+        };
+
+        let runs = mode.runs();
+        if !(2..=MAX_VOTES).contains(&runs) {
+            return Err(format!("voting needs between 2 and {} runs, got {}", MAX_VOTES, runs));
+        }
+        Ok(Some(mode))
+    }
+
+    pub fn runs(&self) -> usize {
+        match self {
+            Self::Seeds(n) => *n,
+            Self::Models(models) => models.len(),
+        }
+    }
+}
+
+// One run of a vote, failed runs count as having no answer for every field
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct VoteRun {
+    pub model: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seed: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub instance: Option<String>,
+    // Schema check of this run's own answer
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub valid: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub generation: Option<GenerationInfo>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+// A value the runs proposed for a field, None when runs left the field out
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Alternative {
+    pub value: Option<Value>,
+    pub votes: usize,
+}
+
+// Outcome for one leaf field, addressed by JSON pointer (`/cassettes/0/dispensed`)
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct FieldVote {
+    pub field: String,
+    // Majority value, None when most runs left the field out (it is then missing from the data)
+    pub value: Option<Value>,
+    pub votes: usize,
+    // Share of all runs that gave the majority value
+    pub agreement: f64,
+    // The runs disagree, a person should check this field
+    pub flagged: bool,
+    // The losing values, only when the runs disagree
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub alternatives: Vec<Alternative>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct VoteReport {
+    pub mode: VoteMode,
+    pub runs: Vec<VoteRun>,
+    // Mean agreement over the fields, 1 when there was nothing to compare
+    pub agreement: f64,
+    pub fields: Vec<FieldVote>,
+    // Pointers of the flagged fields, for review queues
+    pub flagged: Vec<String>,
+}
+
+// Answers merged field by field, with the vote on every leaf
+pub struct Consensus {
+    pub data: Option<Value>,
+    pub fields: Vec<FieldVote>,
+}
+
+impl Consensus {
+    pub fn agreement(&self) -> f64 {
+        if self.fields.is_empty() {
+            return 1.0;
+        }
+        self.fields.iter().map(|f| f.agreement).sum::<f64>() / self.fields.len() as f64
+    }
+
+    pub fn flagged(&self) -> Vec<String> {
+        self.fields.iter().filter(|f| f.flagged).map(|f| f.field.clone()).collect()
+    }
+
+    // Majority fields a run's own answer matches, to pick the run whose text is returned
+    pub fn matches(&self, data: Option<&Value>) -> usize {
+        self.fields.iter()
+            .filter(|f| match (&f.value, data) {
+                (Some(value), Some(data)) => data.pointer(&f.field).is_some_and(|v| key(v) == key(value)),
+                (None, data) => data.and_then(|d| d.pointer(&f.field)).is_none(),
+                _ => false,
+            })
+            .count()
+    }
+}
+
+// One answer per run (None for failed runs or when no JSON could be read)
+pub fn vote(answers: &[Option<Value>]) -> Consensus {
+    let values: Vec<Option<&Value>> = answers.iter().map(Option::as_ref).collect();
+    let mut fields = Vec::new();
+    let data = vote_value(&values, "", &mut fields);
+    Consensus { data, fields }
+}
+
+// What is compared: containers by shape (their contents are voted on separately), scalars by value
+#[derive(PartialEq)]
+enum Key {
+    Absent,
+    Object,
+    Array,
+    Scalar(String),
+}
+
+fn key(value: &Value) -> Key {
+    match value {
+        Value::Object(_) => Key::Object,
+        Value::Array(_) => Key::Array,
+        // Integers exactly, so long account numbers differing in a digit do not agree.
+        // Floats print without a fraction when whole: 500 and 500.0 are the same amount.
+        Value::Number(n) => Key::Scalar(match (n.as_i64(), n.as_u64(), n.as_f64()) {
+            (Some(i), _, _) => i.to_string(),
+            (_, Some(u), _) => u.to_string(),
+            (_, _, Some(f)) => f.to_string(),
+            _ => n.to_string(),
+        }),
+        Value::String(s) => Key::Scalar(format!("{:?}", s.trim())),
+        other => Key::Scalar(other.to_string()),
+    }
+}
+
+fn vote_value(values: &[Option<&Value>], field: &str, fields: &mut Vec<FieldVote>) -> Option<Value> {
+    // Groups of runs giving the same answer, in order of first appearance
+    let mut groups: Vec<(Key, Option<&Value>, usize)> = Vec::new();
+    for value in values {
+        let k = value.map(key).unwrap_or(Key::Absent);
+        match groups.iter_mut().find(|(g, _, _)| *g == k) {
+            Some(group) => group.2 += 1,
+            None => groups.push((k, *value, 1)),
+        }
+    }
+    // Largest group wins, the earliest one on ties
+    let mut winner = 0;
+    for (i, group) in groups.iter().enumerate() {
+        if group.2 > groups[winner].2 {
+            winner = i;
+        }
+    }
+
+    match &groups[winner].0 {
+        Key::Object => {
+            let objects: Vec<Option<&Map<String, Value>>> = values.iter().map(|v| v.and_then(Value::as_object)).collect();
+            let mut names: Vec<&String> = Vec::new();
+            for name in objects.iter().flatten().flat_map(|o| o.keys()) {
+                if !names.contains(&name) {
+                    names.push(name);
+                }
+            }
+            let mut merged = Map::new();
+            for name in names {
+                let children: Vec<Option<&Value>> = objects.iter().map(|o| o.and_then(|o| o.get(name))).collect();
+                let pointer = format!("{}/{}", field, name.replace('~', "~0").replace('/', "~1"));
+                if let Some(value) = vote_value(&children, &pointer, fields) {
+                    merged.insert(name.clone(), value);
+                }
+            }
+            Some(Value::Object(merged))
+        }
+        // Items are aligned by position; the list ends where most runs have no more items
+        Key::Array => {
+            let arrays: Vec<Option<&Vec<Value>>> = values.iter().map(|v| v.and_then(Value::as_array)).collect();
+            let len = arrays.iter().flatten().map(|a| a.len()).max().unwrap_or(0);
+            let mut merged = Vec::new();
+            for i in 0..len {
+                let children: Vec<Option<&Value>> = arrays.iter().map(|a| a.and_then(|a| a.get(i))).collect();
+                match vote_value(&children, &format!("{}/{}", field, i), fields) {
+                    Some(value) => merged.push(value),
+                    None => break,
+                }
+            }
+            Some(Value::Array(merged))
+        }
+        // Nothing here in most runs: still reported when some run had a value
+        Key::Absent if groups.len() == 1 => None,
+        Key::Absent | Key::Scalar(_) => {
+            let (_, value, votes) = &groups[winner];
+            let alternatives: Vec<Alternative> = groups.iter().enumerate()
+                .filter(|(i, _)| *i != winner)
+                .map(|(_, (_, value, votes))| Alternative { value: value.cloned(), votes: *votes })
+                .collect();
+            fields.push(FieldVote {
+                field: field.to_string(),
+                value: value.cloned(),
+                votes: *votes,
+                agreement: *votes as f64 / values.len() as f64,
+                flagged: !alternatives.is_empty(),
+                alternatives,
+            });
+            value.cloned()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn majority_wins_per_field_and_disagreements_are_flagged() {
+        let answers = [
+            Some(json!({ "atm_id": "A1", "cassettes": [{ "dispensed": 120 }, { "dispensed": 40 }] })),
+            Some(json!({ "atm_id": "A1 ", "cassettes": [{ "dispensed": 128 }, { "dispensed": 40.0 }] })),
+            Some(json!({ "atm_id": "A1", "cassettes": [{ "dispensed": 120 }, { "dispensed": 40 }], "note": "x" })),
+        ];
+        let consensus = vote(&answers);
+
+        assert_eq!(consensus.data, Some(json!({ "atm_id": "A1", "cassettes": [{ "dispensed": 120 }, { "dispensed": 40 }] })));
+        assert_eq!(consensus.flagged(), ["/cassettes/0/dispensed", "/note"]);
+        let dispensed = consensus.fields.iter().find(|f| f.field == "/cassettes/0/dispensed").unwrap();
+        assert_eq!(dispensed.votes, 2);
+        assert_eq!(dispensed.alternatives, [Alternative { value: Some(json!(128)), votes: 1 }]);
+        assert_eq!(consensus.matches(answers[0].as_ref()), 4);
+    }
+
+    #[test]
+    fn failed_runs_count_against_agreement() {
+        let consensus = vote(&[Some(json!({ "amount": 500 })), None]);
+        assert_eq!(consensus.data, Some(json!({ "amount": 500 })));
+        assert_eq!(consensus.fields[0].agreement, 0.5);
+        assert!(consensus.fields[0].flagged);
+    }
+
+    #[test]
+    fn large_integers_are_compared_exactly() {
+        // Equal as f64, one digit apart as integers
+        let consensus = vote(&[
+            Some(json!({ "account": 12_345_678_901_234_567_u64 })),
+            Some(json!({ "account": 12_345_678_901_234_568_u64 })),
+        ]);
+        assert_eq!(consensus.flagged(), ["/account"]);
+        assert_eq!(consensus.fields[0].votes, 1);
+    }
+
+    #[test]
+    fn modes_are_parsed_from_the_query() {
+        let modes = |q: &[(&str, &str)]| VoteMode::from_query(&q.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect());
+        assert_eq!(modes(&[]), Ok(None));
+        assert_eq!(modes(&[("votes", "3")]), Ok(Some(VoteMode::Seeds(3))));
+        assert_eq!(modes(&[("votes", "1")]), Ok(None));
+        assert!(modes(&[("votes", "0")]).is_err());
+        assert!(modes(&[("votes", "9")]).is_err());
+        assert!(modes(&[("votes", "three")]).is_err());
+        assert_eq!(modes(&[("vote_models", "a, b,a")]), Ok(Some(VoteMode::Models(vec!["a".into(), "b".into()]))));
+        // Repeating one model is a single run, not a vote
+        assert!(modes(&[("vote_models", "a,a")]).is_err());
+        assert!(modes(&[("votes", "3"), ("vote_models", "a,b")]).is_err());
+    }
+}
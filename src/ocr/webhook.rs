@@ -0,0 +1,249 @@
+use std::{future::Future, net::{IpAddr, SocketAddr}, sync::Arc, time::Duration};
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use reqwest::{Url, redirect};
+use serde::Serialize;
+use sha2::Sha256;
+
+use crate::config::WebhookConfig;
+
+pub const SIGNATURE_HEADER: &str = "X-OCR-Signature";
+pub const TIMESTAMP_HEADER: &str = "X-OCR-Timestamp";
+
+// Attempts per delivery and the first retry delay (doubled after every failure)
+pub const MAX_ATTEMPTS: u32 = 5;
+pub const BASE_BACKOFF_MS: u64 = 1000;
+
+#[derive(Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct DeliveryAttempt {
+    pub attempt: u32,
+    pub at: DateTime<Utc>,
+    pub success: bool,
+    pub status_code: Option<u16>,
+    pub error: Option<String>,
+}
+
+// Posts job results to caller supplied URLs.
+// The signature is `sha256=<hex hmac>` over `"{timestamp}.{body}"` so receivers can reject replays.
+// Callback URLs may not point at loopback, link-local or private addresses unless their host is in
+// `webhooks.allowed_hosts`; when that list is set, no other host is accepted.
+#[derive(Clone)]
+pub struct WebhookSender {
+    secret: String,
+    timeout: Duration,
+    allowed_hosts: Arc<Vec<String>>,
+    backoff: Duration,
+}
+
+impl WebhookSender {
+    // None when no secret is configured: callbacks are disabled
+    pub fn new(config: &WebhookConfig, timeout: Duration) -> Option<Self> {
+        let secret = config.secret.clone()?;
+        let allowed_hosts = Arc::new(config.allowed_hosts.iter().map(|h| h.trim().to_ascii_lowercase()).collect());
+        Some(Self { secret, timeout, allowed_hosts, backoff: Duration::from_millis(BASE_BACKOFF_MS) })
+    }
+
+    pub fn validate_callback_url(&self, url: &str) -> Result<String, String> {
+        self.check(url).map(|(url, _)| url.to_string())
+    }
+
+    // The parsed URL and its host, refused when it may not be called
+    fn check(&self, url: &str) -> Result<(Url, String), String> {
+        let parsed = Url::parse(url).map_err(|e| format!("Invalid callback_url: {}", e))?;
+        match parsed.scheme() {
+            "http" | "https" => {}
+            other => return Err(format!("Unsupported callback_url scheme: {}", other)),
+        }
+
+        let host = parsed.host_str().unwrap_or("").trim_matches(['[', ']']).to_ascii_lowercase();
+        if self.allowed(&host) {
+            return Ok((parsed, host));
+        }
+        if !self.allowed_hosts.is_empty() {
+            return Err(format!("callback_url host '{}' is not in webhooks.allowed_hosts", host));
+        }
+        let internal = match host.parse::<IpAddr>() {
+            Ok(ip) => is_internal(ip),
+            Err(_) => host.is_empty() || host == "localhost" || host.ends_with(".localhost"),
+        };
+        if internal {
+            return Err(format!("callback_url host '{}' is a loopback, link-local or private address", host));
+        }
+        Ok((parsed, host))
+    }
+
+    fn allowed(&self, host: &str) -> bool {
+        self.allowed_hosts.iter().any(|h| h == host)
+    }
+
+    // A client for one delivery. Names are resolved here and only public addresses are handed to
+    // reqwest, and redirects are not followed, so neither DNS nor a 3xx can lead to an internal address.
+    async fn client_for(&self, url: &str) -> Result<reqwest::Client, String> {
+        let (parsed, host) = self.check(url)?;
+        let mut builder = reqwest::Client::builder()
+            .timeout(self.timeout)
+            .redirect(redirect::Policy::none());
+        if host.parse::<IpAddr>().is_err() && !self.allowed(&host) {
+            let port = parsed.port_or_known_default().unwrap_or(80);
+            let addrs: Vec<SocketAddr> = tokio::net::lookup_host((host.as_str(), port)).await
+                .map_err(|e| format!("Cannot resolve {}: {}", host, e))?
+                .filter(|addr| !is_internal(addr.ip()))
+                .collect();
+            if addrs.is_empty() {
+                return Err(format!("{} resolves only to internal addresses", host));
+            }
+            builder = builder.resolve_to_addrs(&host, &addrs);
+        }
+        builder.build().map_err(|e| format!("Failed to build webhook HTTP client: {}", e))
+    }
+
+    pub fn sign(&self, timestamp: i64, body: &[u8]) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(self.secret.as_bytes())
+            .expect("HMAC accepts keys of any length");
+        mac.update(timestamp.to_string().as_bytes());
+        mac.update(b".");
+        mac.update(body);
+        format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+    }
+
+    // One POST; any non-2xx is reported as an error so the caller can retry
+    pub async fn send(&self, url: &str, body: &[u8]) -> Result<u16, (Option<u16>, String)> {
+        let client = self.client_for(url).await.map_err(|e| (None, e))?;
+        let timestamp = Utc::now().timestamp();
+        let response = client.post(url)
+            .header("Content-Type", "application/json")
+            .header(TIMESTAMP_HEADER, timestamp.to_string())
+            .header(SIGNATURE_HEADER, self.sign(timestamp, body))
+            .body(body.to_vec())
+            .send()
+            .await
+            .map_err(|e| (None, e.to_string()))?;
+
+        let status = response.status();
+        if status.is_success() {
+            Ok(status.as_u16())
+        } else {
+            Err((Some(status.as_u16()), format!("Callback returned {}", status)))
+        }
+    }
+
+    // Sends until the receiver accepts or MAX_ATTEMPTS is reached, with exponential backoff.
+    // `record` gets every attempt as soon as it is made.
+    pub async fn deliver<F, Fut>(&self, url: &str, body: &[u8], mut record: F)
+    where
+        F: FnMut(DeliveryAttempt) -> Fut,
+        Fut: Future<Output = ()>,
+    {
+        let mut backoff = self.backoff;
+        for attempt in 1..=MAX_ATTEMPTS {
+            let outcome = self.send(url, body).await;
+            record(DeliveryAttempt {
+                attempt,
+                at: Utc::now(),
+                success: outcome.is_ok(),
+                status_code: match &outcome { Ok(code) => Some(*code), Err((code, _)) => *code },
+                error: outcome.as_ref().err().map(|(_, e)| e.clone()),
+            }).await;
+
+            match outcome {
+                Ok(_) => return,
+                Err((_, e)) => tracing::warn!("callback to {} failed (attempt {}): {}", url, attempt, e),
+            }
+            if attempt < MAX_ATTEMPTS {
+                tokio::time::sleep(backoff).await;
+                backoff *= 2;
+            }
+        }
+    }
+}
+
+// Addresses a callback must not reach: this host, the cloud metadata service and private networks
+fn is_internal(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            ip.is_loopback() || ip.is_private() || ip.is_link_local() || ip.is_unspecified()
+                || ip.is_broadcast() || ip.is_multicast() || ip.is_documentation()
+                // 0.0.0.0/8 and carrier-grade NAT 100.64.0.0/10
+                || a == 0 || (a == 100 && (64..128).contains(&b))
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(v4) => is_internal(IpAddr::V4(v4)),
+            None => {
+                let first = ip.segments()[0];
+                ip.is_loopback() || ip.is_unspecified() || ip.is_multicast()
+                    // Unique local fc00::/7 and link-local fe80::/10
+                    || (first & 0xfe00) == 0xfc00 || (first & 0xffc0) == 0xfe80
+            }
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+    use axum::{Router, http::{HeaderMap, StatusCode}, routing::post};
+
+    fn sender(allowed_hosts: &[&str]) -> WebhookSender {
+        let config = WebhookConfig { secret: Some("key".to_string()), allowed_hosts: allowed_hosts.iter().map(|h| h.to_string()).collect() };
+        let mut sender = WebhookSender::new(&config, Duration::from_secs(5)).unwrap();
+        sender.backoff = Duration::from_millis(1);
+        sender
+    }
+
+    #[test]
+    fn signature_matches_a_known_vector() {
+        // HMAC-SHA256 over `1700000000.{"jobId":"42"}` with the key `key`, computed independently
+        assert_eq!(
+            sender(&[]).sign(1_700_000_000, br#"{"jobId":"42"}"#),
+            "sha256=d9dafa0d115c93719e3678575c01e0cb5c8394b3d05c5c46114cd64f371396a0"
+        );
+    }
+
+    #[test]
+    fn internal_callback_hosts_are_refused() {
+        let open = sender(&[]);
+        for url in ["http://127.0.0.1/cb", "http://localhost:8080/cb", "http://169.254.169.254/latest/meta-data", "http://10.1.2.3/cb", "http://192.168.0.10/cb", "http://[::1]/cb", "http://[fd00::1]/cb", "http://[::ffff:127.0.0.1]/cb", "ftp://example.com/cb"] {
+            assert!(open.validate_callback_url(url).is_err(), "{}", url);
+        }
+        assert!(open.validate_callback_url("https://hooks.example.com/ocr").is_ok());
+
+        let listed = sender(&["ocr-receiver.internal", "127.0.0.1"]);
+        assert!(listed.validate_callback_url("http://127.0.0.1:9000/cb").is_ok());
+        assert!(listed.validate_callback_url("https://hooks.example.com/ocr").is_err());
+    }
+
+    #[tokio::test]
+    async fn failed_deliveries_are_retried_until_accepted() {
+        // 500 twice, then 200; every request must carry a valid signature
+        let seen: Arc<Mutex<Vec<bool>>> = Arc::default();
+        let receiver = sender(&["127.0.0.1"]);
+        let app = Router::new().route("/cb", post({
+            let seen = seen.clone();
+            let receiver = receiver.clone();
+            move |headers: HeaderMap, body: axum::body::Bytes| async move {
+                let timestamp: i64 = headers[TIMESTAMP_HEADER].to_str().unwrap().parse().unwrap();
+                let valid = headers[SIGNATURE_HEADER].to_str().unwrap() == receiver.sign(timestamp, &body);
+                let mut seen = seen.lock().unwrap();
+                seen.push(valid);
+                if seen.len() < 3 { StatusCode::INTERNAL_SERVER_ERROR } else { StatusCode::OK }
+            }
+        }));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://127.0.0.1:{}/cb", listener.local_addr().unwrap().port());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let attempts: Arc<Mutex<Vec<DeliveryAttempt>>> = Arc::default();
+        receiver.deliver(&url, b"{\"ok\":true}", |attempt| {
+            attempts.lock().unwrap().push(attempt);
+            async {}
+        }).await;
+
+        let attempts = attempts.lock().unwrap();
+        let outcomes: Vec<(u32, bool, Option<u16>)> = attempts.iter().map(|a| (a.attempt, a.success, a.status_code)).collect();
+        assert_eq!(outcomes, [(1, false, Some(500)), (2, false, Some(500)), (3, true, Some(200))]);
+        assert_eq!(*seen.lock().unwrap(), [true, true, true]);
+    }
+}
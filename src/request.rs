@@ -0,0 +1,89 @@
+use std::time::Instant;
+use axum::{extract::{Request, State}, http::{HeaderMap, HeaderValue, header::AUTHORIZATION}, middleware::Next, response::Response};
+use uuid::Uuid;
+
+use crate::{constant::ResponseMeta, error::AppError, state::AppState};
+
+pub const REQUEST_ID_HEADER: &str = "X-Request-Id";
+
+// Per-request id and start time, put in the request extensions by `assign_request_id`
+#[derive(Clone, Debug)]
+pub struct RequestInfo {
+    pub id: String,
+    pub started: Instant,
+}
+
+impl RequestInfo {
+    pub fn meta(&self, provider: &str, model: Option<String>) -> ResponseMeta {
+        ResponseMeta {
+            request_id: self.id.clone(),
+            provider: provider.to_string(),
+            model,
+            elapsed_ms: self.started.elapsed().as_millis() as u64,
+            generation: None,
+        }
+    }
+}
+
+// Reuses the caller's X-Request-Id when it sends a sane one, otherwise generates one.
+// The id is echoed on every response, errors included.
+pub async fn assign_request_id(mut req: Request, next: Next) -> Response {
+    let id = req.headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|h| h.to_str().ok())
+        .map(|h| h.trim().to_string())
+        .filter(|h| !h.is_empty() && h.len() <= 128)
+        .unwrap_or_else(|| Uuid::new_v4().to_string());
+
+    req.extensions_mut().insert(RequestInfo { id: id.clone(), started: Instant::now() });
+    let mut response = next.run(req).await;
+    if let Ok(value) = HeaderValue::from_str(&id) {
+        response.headers_mut().insert(REQUEST_ID_HEADER, value);
+    }
+    response
+}
+
+// Guards /admin/*. Without admin.token the admin feature is disabled and its routes answer 503 themselves.
+pub async fn require_admin_token(State(state): State<AppState>, req: Request, next: Next) -> Result<Response, AppError> {
+    if let Some(token) = &state.config.admin.token
+        && !bearer_matches(req.headers(), token)
+    {
+        return Err(AppError::Unauthorized("Missing or invalid admin token, send Authorization: Bearer <admin.token>".to_string()));
+    }
+    Ok(next.run(req).await)
+}
+
+// Compared in constant time so the token cannot be found byte by byte from response times
+fn bearer_matches(headers: &HeaderMap, token: &str) -> bool {
+    let Some(sent) = headers.get(AUTHORIZATION)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.strip_prefix("Bearer "))
+    else {
+        return false;
+    };
+    let (sent, token) = (sent.trim().as_bytes(), token.trim().as_bytes());
+    sent.len() == token.len() && sent.iter().zip(token).fold(0u8, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_the_exact_bearer_token_is_accepted() {
+        let token = "0123456789abcdef";
+        let with = |value: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(AUTHORIZATION, HeaderValue::from_str(value).unwrap());
+            headers
+        };
+
+        assert!(bearer_matches(&with("Bearer 0123456789abcdef"), token));
+        assert!(!bearer_matches(&HeaderMap::new(), token));
+        assert!(!bearer_matches(&with("0123456789abcdef"), token));
+        assert!(!bearer_matches(&with("Basic 0123456789abcdef"), token));
+        assert!(!bearer_matches(&with("Bearer 0123456789abcdeF"), token));
+        assert!(!bearer_matches(&with("Bearer 0123456789abcdef0"), token));
+        assert!(!bearer_matches(&with("Bearer "), token));
+    }
+}
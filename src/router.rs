@@ -1,91 +1,143 @@
-use std::{collections::HashMap, env, sync::Arc};
-use axum::{Router, routing::post};
+use std::{sync::Arc, time::Duration};
+use axum::{Router, routing::{get, post}};
 use bb8::Pool;
 use bb8_tiberius::ConnectionManager;
-use ollama_rs::Ollama;
 use tiberius::Config;
 
-use crate::{ model::TokenResponse, ocr::{copilot, deepseek_ocr, azure_service}, state::AppState};
+use crate::{ capabilities::{self, Capabilities, Feature}, config::{self, AppConfig, AzureServiceConfig, CopilotConfig}, error, request, ocr::{analyze, cache::ResultCache, counters::{self, CounterRegistry}, prompts::{self, PromptRegistry}, history::{self, ResultStore}, pipeline::OcrPipeline, jobs::{self, JobQueue}, webhook::WebhookSender, azure_service::{self, AzureDocumentProvider, AzureReadProvider}, copilot::{self, CopilotProvider}, graph_token::GraphTokenManager, deepseek_ocr::{self, OllamaProvider}, hybrid::HybridProvider, ollama_options::ModelDefaults, ollama_pool::{self, OllamaInstance, OllamaPool}, provider::ProviderRegistry}, state::AppState};
 
 
-pub async fn get_router() -> Router {
-    // 1. Fetch Environment Variables
-    let tenant_id = env::var("TENANT_ID").expect("TENANT_ID missing");
-    let client_secret = env::var("CLIENT_SECRET").expect("CLIENT_SECRET missing");
-    let client_id = env::var("CLIENT_ID").expect("CLIENT_ID missing");
-    let vision_key = env::var("VISION_KEY").expect("VISION_KEY missing");
-    let vision_endpoint = env::var("VISION_ENDPOINT").expect("VISION_ENDPOINT missing");
-    let document_endpoint = env::var("AZURE_DOCUMENT_INTELLIGENCE_ENDPOINT").expect("AZURE_DOCUMENT_INTELLIGENCE_ENDPOINT missing");
-    let document_key = env::var("AZURE_DOCUMENT_INTELLIGENCE_KEY").expect("AZURE_DOCUMENT_INTELLIGENCE_KEY missing");
+pub async fn get_router(config: AppConfig) -> Router {
+    // 1. Optional features: whatever is not configured is disabled instead of aborting startup
+    let db_set = config.database.connection_string.is_some();
+    let copilot_feature = Feature::new("copilot", &[
+        ("copilot.tenant_id", config.copilot.tenant_id.is_some()),
+        ("copilot.client_id", config.copilot.client_id.is_some()),
+        ("copilot.client_secret", config.copilot.client_secret.is_some()),
+    ], &["/ocr/ask-copilot"]);
+    let azure_read_feature = Feature::new("azure-read", &[
+        ("azure_read.endpoint", config.azure_read.endpoint.is_some()),
+        ("azure_read.key", config.azure_read.key.is_some()),
+    ], &["/ocr/azure-ocr"]);
+    let azure_document_feature = Feature::new("azure-document", &[
+        ("azure_document.endpoint", config.azure_document.endpoint.is_some()),
+        ("azure_document.key", config.azure_document.key.is_some()),
+    ], &["/ocr/azure-ocr-document-intelligence"]);
+    // Ollama has a default instance; unreachable ones are reported by /admin/ollama
+    let ollama_feature = Feature::new("ollama", &[], &["/ocr/deepseek-ocr"]);
+    // Azure Read text structured by Ollama, `?provider=hybrid` on /ocr/analyze
+    let hybrid_feature = Feature::new("hybrid", &[
+        ("azure_read.endpoint", config.azure_read.endpoint.is_some()),
+        ("azure_read.key", config.azure_read.key.is_some()),
+    ], &[]);
+    let database = Feature::new("database", &[("database.connection_string", db_set)], &["/ocr/results", "/ocr/prompts/stats"]);
+    let webhooks = Feature::new("webhooks", &[("webhooks.secret", config.webhooks.secret.is_some())], &[]);
+    let video = Feature::new("video", &[
+        ("database.connection_string", db_set),
+        ("video.chunk_dir", config.video.chunk_dir.is_some()),
+        ("video.output_dir", config.video.output_dir.is_some()),
+    ], &["/ocr/test"]);
+    let admin = Feature::new("admin", &[("admin.token", config.admin.token.is_some())], &["/admin/config", "/admin/ollama"]);
 
-    let token_data: TokenResponse;
-    // if tenant_id == ""{    
-        // 2. Microsoft OAuth 2.0 Token Request
-        let url = format!("https://login.microsoftonline.com/{}/oauth2/v2.0/token", tenant_id);
-
-        let mut params = HashMap::new();
-        params.insert("client_id", client_id);
-        params.insert("client_secret", client_secret);
-        params.insert("grant_type", "client_credentials".to_string());
-        params.insert("scope", "https://graph.microsoft.com/.default".to_string());
-
-        let client = reqwest::Client::new();
-        
-        // We use .expect() here because get_router returns Router, not Result.
-        let response = client
-            .post(&url)
-            .form(&params)
-            .send()
+    // 2. Database Pool Setup (connections are opened on first use; the string was validated at load)
+    let mut db_pool = None;
+    if let Some(conn_str) = &config.database.connection_string {
+        let db_config = Config::from_ado_string(conn_str).expect("Invalid database.connection_string");
+        let pool = Pool::builder()
+            .max_size(config.database.pool_size)
+            .build(ConnectionManager::new(db_config))
             .await
-            .expect("CRITICAL: Failed to contact Microsoft Identity platform");
-        
-        let text = response.text().await.expect("Failed to read body");
-        println!("Response Body: {}", text); 
+            .expect("Failed to create DB pool");
+        db_pool = Some(Arc::new(pool));
+    }
 
-        // let token_data: TokenResponse = response
-        //     .json()
-        //     .await
-        //     .expect("CRITICAL: Failed to parse Microsoft token response");
+    // 3. OCR Providers (one shared HTTP client for every backend)
+    let provider_timeout = Duration::from_secs(config.ocr.provider_timeout_secs);
+    let http_client = reqwest::Client::builder()
+        .timeout(provider_timeout)
+        .build()
+        .expect("Failed to build HTTP client");
+    let ollama_instances = config.ollama.instances.iter()
+        .map(|i| OllamaInstance::new(&i.url, i.max_concurrency.unwrap_or(config.ollama.max_concurrency)))
+        .collect::<Result<Vec<_>, _>>()
+        .expect("Invalid ollama.instances");
+    let ollama = Arc::new(OllamaPool::new(ollama_instances, config.ollama.strategy, provider_timeout, Duration::from_secs(config.ollama.queue_timeout_secs)));
+    ollama.start_health_checks(Duration::from_secs(config.ollama.health_check_interval_secs));
 
-        token_data = serde_json::from_str(&text)
-        .expect("CRITICAL: Failed to parse token response");
-    // }else{
-    //     token_data = TokenResponse { access_token: "no id".to_string() };
-    // }
+    let mut providers = ProviderRegistry::default();
+    let model_defaults = ModelDefaults::new(config.ollama.defaults.clone(), config.ollama.models.clone());
+    let ollama_provider = Arc::new(OllamaProvider::new(ollama.clone(), model_defaults));
+    providers.register(ollama_provider.clone());
+    if let AzureServiceConfig { endpoint: Some(endpoint), key: Some(key) } = &config.azure_read {
+        let azure_read = Arc::new(AzureReadProvider::new(http_client.clone(), endpoint.clone(), key.clone()));
+        providers.register(azure_read.clone());
+        providers.register(Arc::new(HybridProvider::new(azure_read, ollama_provider)));
+    }
+    if let AzureServiceConfig { endpoint: Some(endpoint), key: Some(key) } = &config.azure_document {
+        providers.register(Arc::new(AzureDocumentProvider::new(http_client.clone(), endpoint.clone(), key.clone(), provider_timeout)));
+    }
+    if let CopilotConfig { tenant_id: Some(tenant_id), client_id: Some(client_id), client_secret: Some(client_secret), authority_url } = &config.copilot {
+        // Graph token is fetched on first Copilot call and refreshed before it expires
+        let graph_tokens = GraphTokenManager::new(http_client.clone(), authority_url, tenant_id, client_id.clone(), client_secret.clone());
+        providers.register(Arc::new(CopilotProvider::new(http_client.clone(), Arc::new(graph_tokens))));
+    }
 
-    // 3. Database Pool Setup
-    let conn_str = env::var("ATM_SYNC_DATABASE_CONNECTION_STRING")
-        .expect("ATM_SYNC_DATABASE_CONNECTION_STRING missing");
-    let config = Config::from_ado_string(&conn_str).expect("Invalid DB connection string");
+    // 4. Background OCR workers
+    let webhook_sender = WebhookSender::new(&config.webhooks, provider_timeout);
 
-    let manager = ConnectionManager::new(config);
-    let pool = Pool::builder()
-        .max_size(20)
-        .build(manager)
-        .await
-        .expect("Failed to create DB pool");
-
-    // 4. State Initialization
+    // 5. State Initialization
+    let pipeline = OcrPipeline::new(
+        ResultStore::new(db_pool.clone()),
+        ResultCache::new(config.ocr.cache_capacity, db_pool.clone().filter(|_| config.database.cache_sql)),
+    );
     let state = AppState {
-        db_pool: Arc::new(pool),
-        ollama: Arc::new(Ollama::default()),
-        copilot_token: token_data.access_token, // Token shared with all handlers
-        azure_vision_endpoint: vision_endpoint,
-        azure_vision_key: vision_key,
-        azure_document_endpoint: document_endpoint,
-        azure_document_key: document_key
+        jobs: JobQueue::start(config.ocr.job_workers, config.ocr.job_queue_capacity, webhook_sender, pipeline.clone()),
+        db_pool,
+        providers: Arc::new(providers),
+        pipeline,
+        capabilities: Arc::new(Capabilities {
+            providers: vec![ollama_feature.clone(), azure_read_feature.clone(), azure_document_feature.clone(), copilot_feature.clone(), hybrid_feature],
+            database: database.clone(),
+            webhooks,
+            video: video.clone(),
+            admin: admin.clone(),
+        }),
+        counters: Arc::new(CounterRegistry::new(&config.counters).expect("Invalid counter types")),
+        prompts: Arc::new(PromptRegistry::new(&config.prompts).expect("Invalid prompt templates")),
+        config: Arc::new(config),
+        ollama,
     };
 
-    // 5. Route Definition and Nesting
+    // 6. Route Definition and Nesting
     let sync_routes = Router::new()
-        .route("/test", post(deepseek_ocr::mark_complete))
-        .route("/deepseek-ocr", post(deepseek_ocr::deepseek_ocr))
-        .route("/ask-copilot", post(copilot::ocr_image)) 
-        .route("/azure-ocr", post(azure_service::azure_ocr))
-        .route("/azure-ocr-document-intelligence", post(azure_service::azure_structured_ocr)); 
+        .route("/capabilities", get(capabilities::get_capabilities))
+        .route("/counters", get(counters::list_counters))
+        .route("/prompts", get(prompts::list_prompts))
+        .route("/prompts/stats", database.route(get(prompts::get_prompt_stats)))
+        .route("/analyze", post(analyze::analyze))
+        .route("/results", database.route(get(history::list_results)))
+        .route("/jobs", post(jobs::submit_job))
+        .route("/jobs/{id}", get(jobs::get_job))
+        .route("/jobs/{id}/deliveries", get(jobs::get_job_deliveries))
+        .route("/jobs/{id}/deliveries/replay", post(jobs::replay_job_delivery))
+        .route("/test", video.route(post(deepseek_ocr::mark_complete)))
+        .route("/deepseek-ocr", ollama_feature.route(post(deepseek_ocr::deepseek_ocr)))
+        .route("/ask-copilot", copilot_feature.route(post(copilot::ocr_image)))
+        .route("/azure-ocr", azure_read_feature.route(post(azure_service::azure_ocr)))
+        .route("/azure-ocr-document-intelligence", azure_document_feature.route(post(azure_service::azure_structured_ocr)));
+
+    // Configuration and instance URLs are not for every caller
+    let admin_routes = Router::new()
+        .route("/config", admin.route(get(config::get_config)))
+        .route("/ollama", admin.route(get(ollama_pool::get_instances)))
+        .route_layer(axum::middleware::from_fn_with_state(state.clone(), request::require_admin_token));
 
     Router::new()
         .nest("/ocr", sync_routes)
+        .nest("/admin", admin_routes)
+        .fallback(error::not_found)
+        .layer(axum::middleware::map_response(error::wrap_rejections))
+        .layer(axum::middleware::from_fn(request::assign_request_id))
         .layer(axum::extract::DefaultBodyLimit::max(15 * 1024 * 1024 * 1024)) // 15 GB
         .layer(tower_http::cors::CorsLayer::permissive())
         .with_state(state) 
@@ -2,15 +2,36 @@ use std::sync::Arc;
 
 use bb8::Pool;
 use bb8_tiberius::ConnectionManager;
-use ollama_rs::Ollama;
+
+use crate::{capabilities::Capabilities, config::AppConfig, error::AppError, ocr::{counters::CounterRegistry, jobs::JobQueue, ollama_pool::OllamaPool, prompts::PromptRegistry, pipeline::OcrPipeline, provider::{OcrProvider, ProviderRegistry}}};
 
 #[derive(Clone)]
 pub struct AppState {
-    pub db_pool: Arc<Pool<ConnectionManager>>,
-    pub ollama: Arc<Ollama>, 
-    pub copilot_token: String,
-    pub azure_vision_key: String,
-    pub azure_vision_endpoint: String,
-    pub azure_document_endpoint: String,
-    pub azure_document_key: String
+    // None when no connection string is configured
+    pub db_pool: Option<Arc<Pool<ConnectionManager>>>,
+    pub providers: Arc<ProviderRegistry>,
+    pub jobs: JobQueue,
+    pub pipeline: OcrPipeline,
+    pub capabilities: Arc<Capabilities>,
+    pub config: Arc<AppConfig>,
+    pub ollama: Arc<OllamaPool>,
+    pub counters: Arc<CounterRegistry>,
+    pub prompts: Arc<PromptRegistry>,
+}
+
+impl AppState {
+    pub fn db(&self) -> Result<&Pool<ConnectionManager>, AppError> {
+        self.db_pool.as_deref().ok_or_else(|| self.capabilities.database.unavailable())
+    }
+
+    // `?provider=` lookup; a known provider without configuration is reported as unavailable, not invalid
+    pub fn provider(&self, name: &str) -> Result<Arc<dyn OcrProvider>, AppError> {
+        if let Some(provider) = self.providers.get(name) {
+            return Ok(provider);
+        }
+        match self.capabilities.providers.iter().find(|p| p.name == name) {
+            Some(feature) => Err(feature.unavailable()),
+            None => Err(AppError::InvalidPayload(format!("Invalid or missing provider. Available: {}", self.providers.names().join(", ")))),
+        }
+    }
 }
\ No newline at end of file
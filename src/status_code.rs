@@ -1,25 +1,74 @@
+use axum::http::StatusCode;
 use serde::{Deserialize, Serialize};
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
 pub enum AppStatusCode {
     #[serde(rename = "0")]
     Success,
-    
+
     #[serde(rename = "OCR-00001")]
     DbPoolError, // Searchable and descriptive
 
     #[serde(rename = "OCR-00002")]
     SpExecutionFailed,
-    
+
     #[serde(rename = "OCR-00003")]
     InvalidPayload,
-    
+
     #[serde(rename = "OCR-00004")]
     PathCreation,
 
     #[serde(rename = "OCR-00005")]
     SpKnownFailed,
-    
+
+    #[serde(rename = "OCR-00006")]
+    ProviderFailed,
+
+    #[serde(rename = "OCR-00007")]
+    QueueFull,
+
+    #[serde(rename = "OCR-00008")]
+    JobNotFound,
+
+    #[serde(rename = "OCR-00009")]
+    ProviderTimeout,
+
+    // The backend refused this input (bad image, unknown model, ...)
+    #[serde(rename = "OCR-00010")]
+    ProviderRejected,
+
+    #[serde(rename = "OCR-00011")]
+    UnsupportedMedia,
+
+    #[serde(rename = "OCR-00012")]
+    ImageTooLarge,
+
+    // The backend answered but its response could not be parsed
+    #[serde(rename = "OCR-00013")]
+    ParseFailure,
+
+    #[serde(rename = "OCR-00014")]
+    RateLimited,
+
+    #[serde(rename = "OCR-00015")]
+    Unauthorized,
+
+    // The backend is not configured on this server
+    #[serde(rename = "OCR-00016")]
+    ProviderUnavailable,
+
+    #[serde(rename = "OCR-00017")]
+    NotFound,
+
+    #[serde(rename = "OCR-00018")]
+    Conflict,
+
+    #[serde(rename = "OCR-00019")]
+    MethodNotAllowed,
+
+    #[serde(rename = "OCR-00020")]
+    InternalError,
+
     // #[serde(rename = "OCR-00005")]
     // InvalidBearerToken,
 
@@ -27,3 +76,99 @@ pub enum AppStatusCode {
     // MessingBearerToken,
 
 }
+
+impl AppStatusCode {
+    // HTTP status every response carrying this code is sent with
+    pub fn http_status(&self) -> StatusCode {
+        match self {
+            AppStatusCode::Success => StatusCode::OK,
+            AppStatusCode::InvalidPayload | AppStatusCode::SpExecutionFailed => StatusCode::BAD_REQUEST,
+            AppStatusCode::Unauthorized => StatusCode::UNAUTHORIZED,
+            AppStatusCode::JobNotFound | AppStatusCode::NotFound => StatusCode::NOT_FOUND,
+            AppStatusCode::MethodNotAllowed => StatusCode::METHOD_NOT_ALLOWED,
+            AppStatusCode::Conflict => StatusCode::CONFLICT,
+            AppStatusCode::ImageTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+            AppStatusCode::UnsupportedMedia => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            AppStatusCode::ProviderRejected => StatusCode::UNPROCESSABLE_ENTITY,
+            AppStatusCode::RateLimited => StatusCode::TOO_MANY_REQUESTS,
+            AppStatusCode::DbPoolError
+            | AppStatusCode::PathCreation
+            | AppStatusCode::SpKnownFailed
+            | AppStatusCode::InternalError => StatusCode::INTERNAL_SERVER_ERROR,
+            AppStatusCode::ProviderFailed | AppStatusCode::ParseFailure => StatusCode::BAD_GATEWAY,
+            AppStatusCode::QueueFull | AppStatusCode::ProviderUnavailable => StatusCode::SERVICE_UNAVAILABLE,
+            AppStatusCode::ProviderTimeout => StatusCode::GATEWAY_TIMEOUT,
+        }
+    }
+
+    // Best match for an error response produced outside our handlers (extractor rejections, fallbacks)
+    pub fn from_http_status(status: StatusCode) -> Self {
+        match status {
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => AppStatusCode::Unauthorized,
+            StatusCode::NOT_FOUND => AppStatusCode::NotFound,
+            StatusCode::METHOD_NOT_ALLOWED => AppStatusCode::MethodNotAllowed,
+            StatusCode::CONFLICT => AppStatusCode::Conflict,
+            StatusCode::PAYLOAD_TOO_LARGE => AppStatusCode::ImageTooLarge,
+            StatusCode::UNSUPPORTED_MEDIA_TYPE => AppStatusCode::UnsupportedMedia,
+            StatusCode::TOO_MANY_REQUESTS => AppStatusCode::RateLimited,
+            StatusCode::GATEWAY_TIMEOUT => AppStatusCode::ProviderTimeout,
+            s if s.is_client_error() => AppStatusCode::InvalidPayload,
+            _ => AppStatusCode::InternalError,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Every code, its wire value and the HTTP status it is sent with
+    const TABLE: &[(AppStatusCode, &str, StatusCode)] = &[
+        (AppStatusCode::Success, "0", StatusCode::OK),
+        (AppStatusCode::DbPoolError, "OCR-00001", StatusCode::INTERNAL_SERVER_ERROR),
+        (AppStatusCode::SpExecutionFailed, "OCR-00002", StatusCode::BAD_REQUEST),
+        (AppStatusCode::InvalidPayload, "OCR-00003", StatusCode::BAD_REQUEST),
+        (AppStatusCode::PathCreation, "OCR-00004", StatusCode::INTERNAL_SERVER_ERROR),
+        (AppStatusCode::SpKnownFailed, "OCR-00005", StatusCode::INTERNAL_SERVER_ERROR),
+        (AppStatusCode::ProviderFailed, "OCR-00006", StatusCode::BAD_GATEWAY),
+        (AppStatusCode::QueueFull, "OCR-00007", StatusCode::SERVICE_UNAVAILABLE),
+        (AppStatusCode::JobNotFound, "OCR-00008", StatusCode::NOT_FOUND),
+        (AppStatusCode::ProviderTimeout, "OCR-00009", StatusCode::GATEWAY_TIMEOUT),
+        (AppStatusCode::ProviderRejected, "OCR-00010", StatusCode::UNPROCESSABLE_ENTITY),
+        (AppStatusCode::UnsupportedMedia, "OCR-00011", StatusCode::UNSUPPORTED_MEDIA_TYPE),
+        (AppStatusCode::ImageTooLarge, "OCR-00012", StatusCode::PAYLOAD_TOO_LARGE),
+        (AppStatusCode::ParseFailure, "OCR-00013", StatusCode::BAD_GATEWAY),
+        (AppStatusCode::RateLimited, "OCR-00014", StatusCode::TOO_MANY_REQUESTS),
+        (AppStatusCode::Unauthorized, "OCR-00015", StatusCode::UNAUTHORIZED),
+        (AppStatusCode::ProviderUnavailable, "OCR-00016", StatusCode::SERVICE_UNAVAILABLE),
+        (AppStatusCode::NotFound, "OCR-00017", StatusCode::NOT_FOUND),
+        (AppStatusCode::Conflict, "OCR-00018", StatusCode::CONFLICT),
+        (AppStatusCode::MethodNotAllowed, "OCR-00019", StatusCode::METHOD_NOT_ALLOWED),
+        (AppStatusCode::InternalError, "OCR-00020", StatusCode::INTERNAL_SERVER_ERROR),
+    ];
+
+    #[test]
+    fn every_code_has_its_wire_value_and_http_status() {
+        for (code, wire, status) in TABLE {
+            assert_eq!(serde_json::to_value(code).unwrap(), *wire, "{:?}", code);
+            assert_eq!(serde_json::from_value::<AppStatusCode>(serde_json::json!(wire)).unwrap(), *code);
+            assert_eq!(code.http_status(), *status, "{:?}", code);
+        }
+    }
+
+    #[test]
+    fn outside_errors_map_back_to_a_code_with_the_same_status() {
+        for status in [401, 404, 405, 409, 413, 415, 429, 504, 400, 422, 500, 502] {
+            let status = StatusCode::from_u16(status).unwrap();
+            let code = AppStatusCode::from_http_status(status);
+            let expected = match status.as_u16() {
+                // No dedicated code: generic client or server error
+                422 => StatusCode::BAD_REQUEST,
+                502 => StatusCode::INTERNAL_SERVER_ERROR,
+                _ => status,
+            };
+            assert_eq!(code.http_status(), expected, "{} -> {:?}", status, code);
+        }
+        assert_eq!(AppStatusCode::from_http_status(StatusCode::FORBIDDEN), AppStatusCode::Unauthorized);
+    }
+}